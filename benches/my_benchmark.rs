@@ -11,13 +11,37 @@
 //
 
 use criterion::{criterion_group, criterion_main, Criterion};
-use gs_rs::optimizer::optimize;
+use gs_rs::error::Error;
+use gs_rs::optimizer::solver::sparse_cholesky::SparseCholeskySolver;
+use gs_rs::optimizer::solver::Solver;
+use gs_rs::optimizer::sparse_matrix::SparseMatrix;
+use gs_rs::optimizer::{optimize, optimize_with_solver, OptimizerSettings};
 use gs_rs::parser::g2o::G2oParser;
 use gs_rs::parser::Parser;
+use nalgebra::DVector;
+
+/// Sparse Cholesky solver which repeats the symbolic analysis of H in every iteration.
+struct FreshSparseCholeskySolver;
+
+impl Solver for FreshSparseCholeskySolver {
+    #[allow(non_snake_case)]
+    fn solve(&mut self, H: &SparseMatrix, b: &DVector<f64>) -> Result<Vec<f64>, Error> {
+        SparseCholeskySolver::default().solve(H, b)
+    }
+}
 
 fn bench_optimization(file_name: &str, iterations: usize) {
     let factor_graph = G2oParser::parse_file(&["data_files/benchmark_input/", file_name, ".g2o"].concat()).unwrap();
-    optimize(&factor_graph, iterations);
+    optimize(&factor_graph, iterations).unwrap();
+}
+
+fn bench_optimization_with_solver(file_name: &str, iterations: usize, solver: &mut dyn Solver) {
+    let factor_graph = G2oParser::parse_file(&["data_files/benchmark_input/", file_name, ".g2o"].concat()).unwrap();
+    let settings = OptimizerSettings {
+        iterations,
+        ..Default::default()
+    };
+    optimize_with_solver(&factor_graph, &settings, solver).unwrap();
 }
 
 fn bench_mit_2d_1(c: &mut Criterion) {
@@ -40,9 +64,28 @@ fn bench_sphere_3d_10(c: &mut Criterion) {
     });
 }
 
+fn bench_mit_2d_symbolic_cholesky(c: &mut Criterion) {
+    c.bench_function("MIT_2D_10_iterations_fresh_symbolic_cholesky", |b| {
+        b.iter(|| bench_optimization_with_solver("MIT_2D", 10, &mut FreshSparseCholeskySolver))
+    });
+    c.bench_function("MIT_2D_10_iterations_reused_symbolic_cholesky", |b| {
+        b.iter(|| bench_optimization_with_solver("MIT_2D", 10, &mut SparseCholeskySolver::default()))
+    });
+}
+
+fn bench_sphere_3d_symbolic_cholesky(c: &mut Criterion) {
+    c.bench_function("Sphere_3D_5_iterations_fresh_symbolic_cholesky", |b| {
+        b.iter(|| bench_optimization_with_solver("Sphere_3D", 5, &mut FreshSparseCholeskySolver))
+    });
+    c.bench_function("Sphere_3D_5_iterations_reused_symbolic_cholesky", |b| {
+        b.iter(|| bench_optimization_with_solver("Sphere_3D", 5, &mut SparseCholeskySolver::default()))
+    });
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default().sample_size(10);
-    targets = bench_mit_2d_1, bench_mit_2d_50, bench_sphere_3d_1, bench_sphere_3d_10
+    targets = bench_mit_2d_1, bench_mit_2d_50, bench_sphere_3d_1, bench_sphere_3d_10,
+        bench_mit_2d_symbolic_cholesky, bench_sphere_3d_symbolic_cholesky
 }
 criterion_main!(benches);
@@ -0,0 +1,48 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+use gs_rs::factor_graph::builder::FactorGraphBuilder;
+use gs_rs::optimizer::optimize;
+use gs_rs::parser::g2o::G2oParser;
+use gs_rs::parser::Parser;
+use nalgebra::DMatrix;
+
+fn main() {
+    // build a factor graph with three 2D poses and one observed landmark in memory
+    let mut builder = FactorGraphBuilder::new();
+    builder
+        .add_vehicle_2d(0, [0.0, 0.0, 0.0])
+        .unwrap()
+        .add_vehicle_2d(1, [1.2, 0.1, 0.0])
+        .unwrap()
+        .add_vehicle_2d(2, [1.9, -0.2, 0.1])
+        .unwrap()
+        .add_landmark_2d(3, [1.0, 1.5])
+        .unwrap()
+        .fix(0)
+        .unwrap()
+        .add_odometry_2d(0, 1, [1.0, 0.0, 0.0], DMatrix::identity(3, 3))
+        .unwrap()
+        .add_odometry_2d(1, 2, [1.0, 0.0, 0.0], DMatrix::identity(3, 3))
+        .unwrap()
+        .add_observation_2d(0, 3, [1.0, 1.0], DMatrix::identity(2, 2))
+        .unwrap()
+        .add_observation_2d(2, 3, [-1.0, 1.0], DMatrix::identity(2, 2))
+        .unwrap();
+    let factor_graph = builder.build();
+
+    // optimize the factor graph's variables with 10 iterations
+    optimize(&factor_graph, 10).unwrap();
+
+    // compose g2o file containing the optimized variables
+    G2oParser::compose_file(&factor_graph, "examples/io_files/Builder_2D_optimized.g2o").unwrap();
+}
@@ -19,7 +19,7 @@ fn main() {
     let factor_graph = G2oParser::parse_file("examples/io_files/MIT_2D.g2o").unwrap();
 
     // optimize the factor graph's variables with 10 iterations
-    optimize(&factor_graph, 10);
+    optimize(&factor_graph, 10).unwrap();
 
     // compose g2o file containing optimized 2D variables and unchanged odometries
     G2oParser::compose_file(&factor_graph, "examples/io_files/MIT_2D_optimized.g2o").unwrap();
@@ -19,7 +19,7 @@ fn main() {
     let factor_graph = JsonParser::parse_file("examples/io_files/MIT_2D.json").unwrap();
 
     // optimize the factor graph's variables with 10 iterations
-    optimize(&factor_graph, 10);
+    optimize(&factor_graph, 10).unwrap();
 
     // compose json file containing optimized 2D variables and unchanged odometries
     JsonParser::compose_file(&factor_graph, "examples/io_files/MIT_2D_optimized.json").unwrap();
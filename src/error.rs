@@ -0,0 +1,117 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Errors returned by the public API.
+
+use crate::parser::model::validation::ValidationIssue;
+use std::fmt;
+
+/// Error type of all fallible operations on files, models, factor graphs and optimizers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The content of a file or string could not be parsed, stating the 1-based position of the invalid token.
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// A file could not be read or written.
+    Io { path: String, message: String },
+    /// A factor graph model could not be serialized.
+    Serialization(String),
+    /// The vertex type is not supported in this context.
+    UnknownVertexType(String),
+    /// The edge type is not supported in this context.
+    UnknownEdgeType(String),
+    /// The robust kernel type is not supported.
+    UnknownRobustKernelType(String),
+    /// The number of values does not fit the type of a vertex or edge.
+    DimensionMismatch {
+        context: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// No vertex with the given ID exists.
+    UnknownVertexId(usize),
+    /// A vertex with the given ID exists already.
+    DuplicateVertexId(usize),
+    /// The information matrix of an edge is not symmetric and positive semi-definite.
+    NonPsdInformationMatrix(String),
+    /// The factor graph or a change to it is inconsistent in any other way.
+    InvalidFactorGraph(String),
+    /// The linear system of an optimization step could not be solved.
+    SolverFailure(String),
+    /// The validation of a model found issues of the rejected severity, stating all found issues.
+    Validation(Vec<ValidationIssue>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse { line, column, message } => {
+                write!(f, "Parse error in line {}, column {}: {}", line, column, message)
+            }
+            Error::Io { path, message } => write!(f, "I/O error at {}: {}", path, message),
+            Error::Serialization(message) => write!(f, "Serialization error: {}", message),
+            Error::UnknownVertexType(vertex_type) => write!(f, "Unknown vertex type: {}", vertex_type),
+            Error::UnknownEdgeType(edge_type) => write!(f, "Unknown edge type: {}", edge_type),
+            Error::UnknownRobustKernelType(kernel_type) => write!(f, "Unknown robust kernel type: {}", kernel_type),
+            Error::DimensionMismatch {
+                context,
+                expected,
+                actual,
+            } => write!(f, "{} has {} instead of {} values", context, actual, expected),
+            Error::UnknownVertexId(id) => write!(f, "Unknown vertex ID: {}", id),
+            Error::DuplicateVertexId(id) => write!(f, "Vertex ID {} is already in use", id),
+            Error::NonPsdInformationMatrix(context) => write!(
+                f,
+                "Information matrix of {} is not symmetric positive semi-definite",
+                context
+            ),
+            Error::InvalidFactorGraph(message) => write!(f, "Invalid factor graph: {}", message),
+            Error::SolverFailure(message) => write!(f, "Solver failure: {}", message),
+            Error::Validation(issues) => {
+                let messages: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+                write!(f, "Validation failed: {}", messages.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let error = Error::Parse {
+            line: 3,
+            column: 11,
+            message: String::from("Expected an unsigned integer: a"),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Parse error in line 3, column 11: Expected an unsigned integer: a"
+        );
+        let error = Error::DimensionMismatch {
+            context: String::from("Content of Vehicle2D vertex 0"),
+            expected: 3,
+            actual: 2,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Content of Vehicle2D vertex 0 has 2 instead of 3 values"
+        );
+    }
+}
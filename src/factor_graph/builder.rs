@@ -0,0 +1,398 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Programmatic construction of factor graphs without going through a parsed file.
+
+use crate::error::Error;
+use crate::factor_graph::factor::robust_kernel::RobustKernel;
+use crate::factor_graph::factor::{Factor, FactorType, FactorType::*, InformationMatrix};
+use crate::factor_graph::variable::{
+    FixedType, LandmarkVariable2D, LandmarkVariable3D, SwitchVariable, Variable, VehicleVariable2D, VehicleVariable3D,
+};
+use crate::factor_graph::FactorGraph;
+use nalgebra::DMatrix;
+
+/// Typed builder for factor graphs.
+///
+/// Variables are referenced by custom IDs, which have to be unique. Every method validates its arguments
+/// and returns an error without changing the builder if they are invalid.
+///
+/// A builder can also be created from an existing factor graph in order to continue growing it.
+#[derive(Debug, Default)]
+pub struct FactorGraphBuilder {
+    factor_graph: FactorGraph,
+}
+
+impl From<FactorGraph> for FactorGraphBuilder {
+    fn from(factor_graph: FactorGraph) -> Self {
+        FactorGraphBuilder { factor_graph }
+    }
+}
+
+impl FactorGraphBuilder {
+    /// Returns an empty builder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a 2D vehicle variable with the pose [x, y, phi].
+    pub fn add_vehicle_2d(&mut self, id: usize, pose: [f64; 3]) -> Result<&mut Self, Error> {
+        let [x, y, phi] = pose;
+        self.add_variable(Variable::Vehicle2D(VehicleVariable2D::new(id, x, y, phi, non_fixed())))
+    }
+
+    /// Adds a 2D landmark variable with the position [x, y].
+    pub fn add_landmark_2d(&mut self, id: usize, position: [f64; 2]) -> Result<&mut Self, Error> {
+        let [x, y] = position;
+        self.add_variable(Variable::Landmark2D(LandmarkVariable2D::new(id, x, y, non_fixed())))
+    }
+
+    /// Adds a 3D vehicle variable with the pose [x, y, z, rot_x, rot_y, rot_z, rot_w], where the rotation is given as
+    /// a quaternion.
+    pub fn add_vehicle_3d(&mut self, id: usize, pose: [f64; 7]) -> Result<&mut Self, Error> {
+        check_quaternion(&pose)?;
+        let [x, y, z, rot_x, rot_y, rot_z, rot_w] = pose;
+        self.add_variable(Variable::Vehicle3D(VehicleVariable3D::new(
+            id,
+            x,
+            y,
+            z,
+            rot_x,
+            rot_y,
+            rot_z,
+            rot_w,
+            non_fixed(),
+        )))
+    }
+
+    /// Adds a 3D landmark variable with the position [x, y, z].
+    pub fn add_landmark_3d(&mut self, id: usize, position: [f64; 3]) -> Result<&mut Self, Error> {
+        let [x, y, z] = position;
+        self.add_variable(Variable::Landmark3D(LandmarkVariable3D::new(id, x, y, z, non_fixed())))
+    }
+
+    /// Adds a switch variable with a value in [0, 1].
+    pub fn add_switch(&mut self, id: usize, value: f64) -> Result<&mut Self, Error> {
+        if !(0.0..=1.0).contains(&value) {
+            return Err(Error::InvalidFactorGraph(format!(
+                "Switch value {} of variable {} is not in [0, 1]",
+                value, id
+            )));
+        }
+        self.add_variable(Variable::Switch(SwitchVariable::new(id, value, non_fixed())))
+    }
+
+    /// Adds a measurement of a 2D vehicle's pose [x, y, phi] with a 3x3 information matrix.
+    pub fn add_position_2d(
+        &mut self,
+        id: usize,
+        measurement: [f64; 3],
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        self.add_factor(Position2D, &[id], measurement.to_vec(), information)
+    }
+
+    /// Adds a relative measurement [x, y, phi] from one 2D vehicle to another with a 3x3 information matrix.
+    pub fn add_odometry_2d(
+        &mut self,
+        from: usize,
+        to: usize,
+        measurement: [f64; 3],
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        self.add_factor(Odometry2D, &[from, to], measurement.to_vec(), information)
+    }
+
+    /// Adds a relative measurement [x, y] from a 2D vehicle to a 2D landmark with a 2x2 information matrix.
+    pub fn add_observation_2d(
+        &mut self,
+        vehicle: usize,
+        landmark: usize,
+        measurement: [f64; 2],
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        self.add_factor(Observation2D, &[vehicle, landmark], measurement.to_vec(), information)
+    }
+
+    /// Adds a measurement of a 3D vehicle's pose [x, y, z, rot_x, rot_y, rot_z, rot_w] with a 6x6 information matrix.
+    pub fn add_position_3d(
+        &mut self,
+        id: usize,
+        measurement: [f64; 7],
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        check_quaternion(&measurement)?;
+        self.add_factor(Position3D, &[id], measurement.to_vec(), information)
+    }
+
+    /// Adds a relative measurement [x, y, z, rot_x, rot_y, rot_z, rot_w] from one 3D vehicle to another
+    /// with a 6x6 information matrix.
+    pub fn add_odometry_3d(
+        &mut self,
+        from: usize,
+        to: usize,
+        measurement: [f64; 7],
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        check_quaternion(&measurement)?;
+        self.add_factor(Odometry3D, &[from, to], measurement.to_vec(), information)
+    }
+
+    /// Adds a relative measurement [x, y, z] from a 3D vehicle to a 3D landmark with a 3x3 information matrix.
+    pub fn add_observation_3d(
+        &mut self,
+        vehicle: usize,
+        landmark: usize,
+        measurement: [f64; 3],
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        self.add_factor(Observation3D, &[vehicle, landmark], measurement.to_vec(), information)
+    }
+
+    /// Adds an odometry measurement as in add_odometry_2d() which is scaled by the given switch variable.
+    pub fn add_switchable_odometry_2d(
+        &mut self,
+        from: usize,
+        to: usize,
+        switch: usize,
+        measurement: [f64; 3],
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        self.add_factor(
+            SwitchableOdometry2D,
+            &[from, to, switch],
+            measurement.to_vec(),
+            information,
+        )
+    }
+
+    /// Adds an odometry measurement as in add_odometry_3d() which is scaled by the given switch variable.
+    pub fn add_switchable_odometry_3d(
+        &mut self,
+        from: usize,
+        to: usize,
+        switch: usize,
+        measurement: [f64; 7],
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        check_quaternion(&measurement)?;
+        self.add_factor(
+            SwitchableOdometry3D,
+            &[from, to, switch],
+            measurement.to_vec(),
+            information,
+        )
+    }
+
+    /// Adds a prior measurement of a switch variable's value with a 1x1 information matrix.
+    pub fn add_switch_prior(
+        &mut self,
+        switch: usize,
+        value: f64,
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        self.add_factor(SwitchPrior, &[switch], vec![value], information)
+    }
+
+    /// Fixes the variable with the given ID, so that it is not changed during optimization.
+    pub fn fix(&mut self, id: usize) -> Result<&mut Self, Error> {
+        self.factor_graph.set_fixed(id, true)?;
+        Ok(self)
+    }
+
+    /// Sets the robust kernel used for all factors of the given type.
+    pub fn set_robust_kernel(&mut self, factor_type: FactorType, kernel: RobustKernel) -> &mut Self {
+        self.factor_graph.robust_kernels.insert(factor_type, kernel);
+        self
+    }
+
+    /// Returns the factor graph containing all added variables and factors.
+    pub fn build(self) -> FactorGraph {
+        self.factor_graph
+    }
+
+    fn add_variable(&mut self, variable: Variable) -> Result<&mut Self, Error> {
+        self.factor_graph.add_variable(variable)?;
+        Ok(self)
+    }
+
+    fn add_factor(
+        &mut self,
+        factor_type: FactorType,
+        variable_ids: &[usize],
+        measurement: Vec<f64>,
+        information: DMatrix<f64>,
+    ) -> Result<&mut Self, Error> {
+        let factor = Factor {
+            factor_type,
+            constraint: measurement,
+            information_matrix: InformationMatrix { content: information },
+            robust_kernel: None,
+            switch_index: None,
+            prior_indices: vec![],
+        };
+        self.factor_graph.add_factor(factor, variable_ids)?;
+        Ok(self)
+    }
+}
+
+/// Returns the fixed type of an added variable, whose range within H is assigned by the factor graph.
+fn non_fixed() -> FixedType {
+    FixedType::NonFixed(0..0)
+}
+
+/// Returns an error if the rotation quaternion at the end of the given 3D pose has zero length.
+fn check_quaternion(pose: &[f64; 7]) -> Result<(), Error> {
+    if pose[3..].iter().map(|value| value * value).sum::<f64>() == 0.0 {
+        return Err(Error::InvalidFactorGraph(format!(
+            "Rotation of pose {:?} is not a valid quaternion",
+            pose
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::model::FactorGraphModel;
+    use crate::parser::Parser;
+    use log::LevelFilter;
+    use std::convert::TryInto;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    #[test]
+    fn test_equal_to_parsed_graph() {
+        init();
+        let mut builder = FactorGraphBuilder::new();
+        builder
+            .add_vehicle_2d(0, [0.0, 0.0, 0.0])
+            .unwrap()
+            .add_vehicle_2d(1, [1.0, 0.1, 0.2])
+            .unwrap()
+            .add_landmark_2d(2, [2.0, 1.0])
+            .unwrap()
+            .add_switch(3, 1.0)
+            .unwrap()
+            .add_position_2d(0, [0.0, 0.0, 0.0], DMatrix::identity(3, 3))
+            .unwrap()
+            .add_odometry_2d(0, 1, [1.0, 0.0, 0.0], DMatrix::identity(3, 3) * 2.0)
+            .unwrap()
+            .add_observation_2d(1, 2, [1.0, 1.0], DMatrix::identity(2, 2))
+            .unwrap()
+            .add_switchable_odometry_2d(1, 0, 3, [-1.0, 0.0, 0.0], DMatrix::identity(3, 3))
+            .unwrap()
+            .add_switch_prior(3, 1.0, DMatrix::identity(1, 1))
+            .unwrap()
+            .fix(0)
+            .unwrap();
+        let built = builder.build();
+
+        let parsed: FactorGraph = G2oParser::parse_string_to_model(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             FIX 0\n\
+             VERTEX_SE2 1 1.0 0.1 0.2\n\
+             VERTEX_XY 2 2.0 1.0\n\
+             VERTEX_SWITCH 3 1.0\n\
+             EDGE_PRIOR_SE2 0 0.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2 0 1 1.0 0.0 0.0 2.0 0.0 0.0 2.0 0.0 2.0\n\
+             EDGE_SE2_XY 1 2 1.0 1.0 1.0 0.0 1.0\n\
+             EDGE_SE2_SWITCHABLE 1 0 3 -1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SWITCH_PRIOR 3 1.0 1.0\n",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(built.matrix_dim, parsed.matrix_dim);
+        assert_eq!(FactorGraphModel::from(&built), FactorGraphModel::from(&parsed));
+    }
+
+    #[test]
+    fn test_3d_graph() {
+        init();
+        let mut builder = FactorGraphBuilder::new();
+        builder
+            .add_vehicle_3d(0, [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0])
+            .unwrap()
+            .add_vehicle_3d(1, [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0])
+            .unwrap()
+            .add_landmark_3d(2, [1.0, 1.0, 1.0])
+            .unwrap()
+            .add_position_3d(0, [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0], DMatrix::identity(6, 6))
+            .unwrap()
+            .add_odometry_3d(0, 1, [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0], DMatrix::identity(6, 6))
+            .unwrap()
+            .add_observation_3d(1, 2, [0.0, 1.0, 1.0], DMatrix::identity(3, 3))
+            .unwrap();
+        let factor_graph = builder.build();
+        assert_eq!(factor_graph.matrix_dim, 15);
+        assert_eq!(factor_graph.csr.edge_count(), 3);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        init();
+        let mut builder = FactorGraphBuilder::new();
+        builder
+            .add_vehicle_2d(0, [0.0, 0.0, 0.0])
+            .unwrap()
+            .add_landmark_2d(1, [1.0, 1.0])
+            .unwrap();
+        assert_eq!(
+            builder.add_landmark_2d(0, [1.0, 1.0]).unwrap_err(),
+            Error::DuplicateVertexId(0)
+        );
+        assert!(builder.add_vehicle_2d(2, [f64::NAN, 0.0, 0.0]).is_err());
+        assert!(builder.add_vehicle_3d(3, [0.0; 7]).is_err());
+        assert!(builder.add_switch(4, 1.5).is_err());
+        assert_eq!(builder.fix(5).unwrap_err(), Error::UnknownVertexId(5));
+
+        let information = DMatrix::identity(3, 3);
+        assert!(builder.add_odometry_2d(0, 5, [0.0; 3], information.clone()).is_err());
+        assert!(builder.add_odometry_2d(0, 1, [0.0; 3], information.clone()).is_err());
+        assert!(builder.add_odometry_2d(0, 0, [0.0; 3], information.clone()).is_err());
+        assert_eq!(
+            builder.add_observation_2d(0, 1, [0.0; 2], information).unwrap_err(),
+            Error::DimensionMismatch {
+                context: String::from("Information matrix of Observation2D factor"),
+                expected: 4,
+                actual: 9,
+            }
+        );
+        assert!(builder
+            .add_observation_2d(0, 1, [0.0; 2], DMatrix::from_vec(2, 2, vec![1.0, 0.5, 0.0, 1.0]))
+            .is_err());
+        assert_eq!(
+            builder
+                .add_observation_2d(0, 1, [0.0; 2], DMatrix::from_diagonal_element(2, 2, -1.0))
+                .unwrap_err(),
+            Error::NonPsdInformationMatrix(String::from("Observation2D factor"))
+        );
+        assert!(builder
+            .add_observation_2d(0, 1, [0.0; 2], DMatrix::identity(2, 2))
+            .is_ok());
+        assert!(builder
+            .add_observation_2d(0, 1, [0.0; 2], DMatrix::identity(2, 2))
+            .is_err());
+
+        let factor_graph = builder.build();
+        assert_eq!(factor_graph.node_indices.len(), 2);
+        assert_eq!(factor_graph.csr.edge_count(), 1);
+    }
+}
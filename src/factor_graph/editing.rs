@@ -0,0 +1,508 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Adding and removing variables and factors of an existing factor graph.
+
+use crate::error::Error;
+use crate::factor_graph::factor::{Factor, FactorType, FactorType::*};
+use crate::factor_graph::variable::{FixedType, Variable};
+use crate::factor_graph::FactorGraph;
+use petgraph::csr::{Csr, NodeIndex};
+use petgraph::visit::EdgeRef;
+use std::collections::HashMap;
+
+impl FactorGraph {
+    /// Appends the given variable, whose custom ID has to be unused.
+    ///
+    /// If the variable is not fixed, it is assigned the rows following all other non-fixed variables in H.
+    /// The range stated in its FixedType is ignored.
+    pub fn add_variable(&mut self, mut variable: Variable) -> Result<NodeIndex<usize>, Error> {
+        let id = variable.get_id();
+        if self.custom_to_csr_id_map.contains_key(&id) {
+            return Err(Error::DuplicateVertexId(id));
+        }
+        if variable.get_content().iter().any(|value| !value.is_finite()) {
+            return Err(Error::InvalidFactorGraph(format!(
+                "Content {:?} of variable {} is not finite",
+                variable.get_content(),
+                id
+            )));
+        }
+        if let FixedType::NonFixed(_) = variable.get_fixed_type() {
+            self.matrix_dim += variable.get_dim();
+            variable.set_fixed_type(FixedType::NonFixed(
+                self.matrix_dim - variable.get_dim()..self.matrix_dim,
+            ));
+        }
+        let index = self.csr.add_node(variable);
+        self.node_indices.push(index);
+        self.custom_to_csr_id_map.insert(id, index);
+        Ok(index)
+    }
+
+    /// Adds the given factor between the variables with the given custom IDs.
+    ///
+    /// The IDs are expected in the same order as in the serialized model, i.e. the source variable, followed by the
+    /// target variable for factors between two variables and the switch variable for switchable factors.
+    /// The factor's switch index is set accordingly. Only a single factor may exist from a source to a target.
+    ///
+    /// A linearized prior expects the IDs of all of its variables in the order of its rows and is stored apart from the
+    /// CSR representation, so it does not prevent other factors between its variables.
+    pub fn add_factor(&mut self, mut factor: Factor, variable_ids: &[usize]) -> Result<(), Error> {
+        let indices = variable_ids
+            .iter()
+            .map(|id| self.get_index(*id))
+            .collect::<Result<Vec<usize>, Error>>()?;
+        let variables: Vec<&Variable> = indices.iter().map(|index| self.get_var(*index)).collect();
+        if !fits_variables(&factor.factor_type, &variables) {
+            return Err(Error::InvalidFactorGraph(format!(
+                "Variables {:?} do not fit a {:?} factor",
+                variable_ids, factor.factor_type
+            )));
+        }
+        check_dimensions(&factor, &variables)?;
+        if factor.factor_type == LinearizedPrior {
+            return self.add_linearized_prior(factor, indices, variable_ids);
+        }
+        let source = indices[0];
+        let target = *indices.get(1).unwrap_or(&source);
+        if variable_ids.len() > 1 && source == target {
+            return Err(Error::InvalidFactorGraph(format!(
+                "{:?} factor connects variable {} with itself",
+                factor.factor_type, variable_ids[0]
+            )));
+        }
+        factor.switch_index = indices.get(2).copied();
+        if !self.csr.add_edge(source, target, factor) {
+            return Err(Error::InvalidFactorGraph(format!(
+                "Variables {:?} are already connected by a factor in this direction",
+                &variable_ids[..variable_ids.len().min(2)]
+            )));
+        }
+        Ok(())
+    }
+
+    /// Removes the variable with the given custom ID together with all factors it is part of.
+    pub fn remove_variable(&mut self, id: usize) -> Result<(), Error> {
+        let removed = self.get_index(id)?;
+        self.retain(|index| index != removed, |_| true);
+        Ok(())
+    }
+
+    /// Removes the factor from the source to the target variable with the given custom IDs.
+    ///
+    /// For factors of a single variable, source and target are identical.
+    pub fn remove_factor(&mut self, source_id: usize, target_id: usize) -> Result<(), Error> {
+        let (source, target) = (self.get_index(source_id)?, self.get_index(target_id)?);
+        if !self.csr.contains_edge(source, target) {
+            return Err(Error::InvalidFactorGraph(format!(
+                "No factor from variable {} to variable {}",
+                source_id, target_id
+            )));
+        }
+        self.retain(|_| true, |edge| edge != (source, target));
+        Ok(())
+    }
+
+    /// Sets whether the variable with the given custom ID is fixed, i.e. not changed during optimization.
+    ///
+    /// The rows of all non-fixed variables in H are updated accordingly.
+    pub fn set_fixed(&mut self, id: usize, fixed: bool) -> Result<(), Error> {
+        let index = self.get_index(id)?;
+        let fixed_type = if fixed {
+            FixedType::Fixed
+        } else {
+            FixedType::NonFixed(0..0)
+        };
+        self.csr[index].set_fixed_type(fixed_type);
+        self.update_ranges();
+        Ok(())
+    }
+
+    fn add_linearized_prior(
+        &mut self,
+        mut factor: Factor,
+        indices: Vec<usize>,
+        variable_ids: &[usize],
+    ) -> Result<(), Error> {
+        if (1..indices.len()).any(|i| indices[..i].contains(&indices[i])) {
+            return Err(Error::InvalidFactorGraph(format!(
+                "Linearized prior on variables {:?} contains a variable more than once",
+                variable_ids
+            )));
+        }
+        factor.switch_index = None;
+        factor.prior_indices = indices;
+        self.linearized_priors.push(factor);
+        Ok(())
+    }
+
+    /// Returns the dimension of the information matrix a factor of the given type needs on the given variables.
+    pub(crate) fn get_information_dim(&self, factor_type: &FactorType, variable_ids: &[usize]) -> Result<usize, Error> {
+        let variables = variable_ids
+            .iter()
+            .map(|id| self.get_index(*id).map(|index| self.get_var(index)))
+            .collect::<Result<Vec<&Variable>, Error>>()?;
+        Ok(get_dims(factor_type, &variables).1)
+    }
+
+    fn get_index(&self, id: usize) -> Result<usize, Error> {
+        self.custom_to_csr_id_map
+            .get(&id)
+            .copied()
+            .ok_or(Error::UnknownVertexId(id))
+    }
+
+    /// Rebuilds the CSR representation with the kept variables and factors, as petgraph's Csr does not support
+    /// removal. Factors of removed variables, including switch variables and variables of linearized priors,
+    /// are removed as well.
+    fn retain(&mut self, keep_variable: impl Fn(usize) -> bool, keep_factor: impl Fn((usize, usize)) -> bool) {
+        let mut csr = Csr::new();
+        let mut new_indices: Vec<Option<usize>> = vec![None; self.node_indices.len()];
+        self.node_indices
+            .iter()
+            .filter(|index| keep_variable(**index))
+            .for_each(|index| new_indices[*index] = Some(csr.add_node(self.get_var(*index).clone())));
+        for index in &self.node_indices {
+            for edge in self
+                .csr
+                .edges(*index)
+                .filter(|edge| keep_factor((edge.source(), edge.target())))
+            {
+                let factor = edge.weight();
+                let switch_index = factor.switch_index.map(|switch_index| new_indices[switch_index]);
+                match (new_indices[edge.source()], new_indices[edge.target()], switch_index) {
+                    (_, _, Some(None)) => {}
+                    (Some(source), Some(target), switch_index) => {
+                        csr.add_edge(
+                            source,
+                            target,
+                            Factor {
+                                switch_index: switch_index.flatten(),
+                                ..factor.clone()
+                            },
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.linearized_priors = self
+            .linearized_priors
+            .iter()
+            .filter_map(|prior| {
+                let prior_indices: Option<Vec<usize>> =
+                    prior.prior_indices.iter().map(|index| new_indices[*index]).collect();
+                prior_indices.map(|prior_indices| Factor {
+                    prior_indices,
+                    ..prior.clone()
+                })
+            })
+            .collect();
+        self.csr = csr;
+        self.node_indices = (0..self.csr.node_count()).collect();
+        self.custom_to_csr_id_map = self
+            .node_indices
+            .iter()
+            .map(|index| (self.get_var(*index).get_id(), *index))
+            .collect::<HashMap<usize, usize>>();
+        self.update_ranges();
+    }
+
+    /// Assigns consecutive rows in H to all non-fixed variables in the order of their indices.
+    fn update_ranges(&mut self) {
+        self.matrix_dim = 0;
+        for index in self.node_indices.clone() {
+            if let FixedType::NonFixed(_) = self.get_var(index).get_fixed_type() {
+                let dim = self.get_var(index).get_dim();
+                self.csr[index].set_fixed_type(FixedType::NonFixed(self.matrix_dim..self.matrix_dim + dim));
+                self.matrix_dim += dim;
+            }
+        }
+    }
+}
+
+/// Returns whether the given variables, in the order of the serialized model, fit the factor type.
+fn fits_variables(factor_type: &FactorType, variables: &[&Variable]) -> bool {
+    use crate::factor_graph::variable::Variable::*;
+    matches!(
+        (factor_type, variables),
+        (Position2D, [Vehicle2D(_)])
+            | (Odometry2D, [Vehicle2D(_), Vehicle2D(_)])
+            | (Observation2D, [Vehicle2D(_), Landmark2D(_)])
+            | (Position3D, [Vehicle3D(_)])
+            | (Odometry3D, [Vehicle3D(_), Vehicle3D(_)])
+            | (Observation3D, [Vehicle3D(_), Landmark3D(_)])
+            | (SwitchableOdometry2D, [Vehicle2D(_), Vehicle2D(_), Switch(_)])
+            | (SwitchableOdometry3D, [Vehicle3D(_), Vehicle3D(_), Switch(_)])
+            | (SwitchPrior, [Switch(_)])
+            | (LinearizedPrior, [_, ..])
+    )
+}
+
+/// Returns an error if the factor's constraint or information matrix do not fit its type and variables or are not
+/// finite, or if its information matrix is not symmetric positive semi-definite.
+fn check_dimensions(factor: &Factor, variables: &[&Variable]) -> Result<(), Error> {
+    let (constraint_len, dim) = get_dims(&factor.factor_type, variables);
+    let information = &factor.information_matrix.content;
+    if factor.constraint.len() != constraint_len {
+        return Err(Error::DimensionMismatch {
+            context: format!("Constraint of {:?} factor", factor.factor_type),
+            expected: constraint_len,
+            actual: factor.constraint.len(),
+        });
+    }
+    if information.shape() != (dim, dim) {
+        return Err(Error::DimensionMismatch {
+            context: format!("Information matrix of {:?} factor", factor.factor_type),
+            expected: dim * dim,
+            actual: information.len(),
+        });
+    }
+    if factor
+        .constraint
+        .iter()
+        .chain(information.iter())
+        .any(|value| !value.is_finite())
+    {
+        return Err(Error::InvalidFactorGraph(format!(
+            "{:?} factor contains values which are not finite",
+            factor.factor_type
+        )));
+    }
+    if !factor.information_matrix.is_positive_semi_definite() {
+        return Err(Error::NonPsdInformationMatrix(format!(
+            "{:?} factor",
+            factor.factor_type
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the length of the constraint and the dimension of the information matrix of a factor of the given type.
+fn get_dims(factor_type: &FactorType, variables: &[&Variable]) -> (usize, usize) {
+    match factor_type {
+        Position2D | Odometry2D | SwitchableOdometry2D => (3, 3),
+        Observation2D => (2, 2),
+        Position3D | Odometry3D | SwitchableOdometry3D => (7, 6),
+        Observation3D => (3, 3),
+        SwitchPrior => (1, 1),
+        LinearizedPrior => variables.iter().fold((0, 0), |(constraint_len, dim), var| {
+            (constraint_len + var.get_content().len(), dim + var.get_dim())
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factor_graph::builder::FactorGraphBuilder;
+    use crate::factor_graph::factor::InformationMatrix;
+    use crate::factor_graph::variable::{LandmarkVariable2D, VehicleVariable2D};
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::model::FactorGraphModel;
+    use crate::parser::Parser;
+    use log::LevelFilter;
+    use nalgebra::DMatrix;
+    use std::convert::TryFrom;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    fn parse(s: &str) -> FactorGraph {
+        FactorGraph::try_from(G2oParser::parse_string_to_model(s).unwrap()).unwrap()
+    }
+
+    fn get_range(factor_graph: &FactorGraph, id: usize) -> FixedType {
+        factor_graph
+            .get_var(factor_graph.custom_to_csr_id_map[&id])
+            .get_fixed_type()
+            .clone()
+    }
+
+    #[test]
+    fn test_add_to_existing_graph() {
+        init();
+        let mut factor_graph = parse(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             FIX 0\n\
+             VERTEX_SE2 1 1.0 0.0 0.0\n\
+             EDGE_SE2 0 1 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n",
+        );
+        factor_graph
+            .add_variable(Variable::Vehicle2D(VehicleVariable2D::new(
+                2,
+                2.0,
+                0.0,
+                0.0,
+                FixedType::Fixed,
+            )))
+            .unwrap();
+        factor_graph.set_fixed(2, false).unwrap();
+        factor_graph
+            .add_variable(Variable::Landmark2D(LandmarkVariable2D::new(
+                3,
+                1.0,
+                1.0,
+                FixedType::NonFixed(0..0),
+            )))
+            .unwrap();
+        let mut builder = FactorGraphBuilder::from(factor_graph);
+        builder
+            .add_odometry_2d(1, 2, [1.0, 0.0, 0.0], DMatrix::identity(3, 3))
+            .unwrap()
+            .add_observation_2d(2, 3, [-1.0, 1.0], DMatrix::identity(2, 2))
+            .unwrap();
+        let factor_graph = builder.build();
+
+        let expected = parse(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             FIX 0\n\
+             VERTEX_SE2 1 1.0 0.0 0.0\n\
+             VERTEX_SE2 2 2.0 0.0 0.0\n\
+             VERTEX_XY 3 1.0 1.0\n\
+             EDGE_SE2 0 1 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2 1 2 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2_XY 2 3 -1.0 1.0 1.0 0.0 1.0\n",
+        );
+        assert_eq!(FactorGraphModel::from(&factor_graph), FactorGraphModel::from(&expected));
+        assert_eq!(factor_graph.matrix_dim, 8);
+        assert_eq!(get_range(&factor_graph, 2), FixedType::NonFixed(3..6));
+        assert_eq!(get_range(&factor_graph, 3), FixedType::NonFixed(6..8));
+    }
+
+    #[test]
+    fn test_remove_from_existing_graph() {
+        init();
+        let mut factor_graph = parse(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             FIX 0\n\
+             VERTEX_SE2 1 1.0 0.0 0.0\n\
+             VERTEX_SE2 2 2.0 0.0 0.0\n\
+             VERTEX_SWITCH 3 1.0\n\
+             VERTEX_XY 4 1.0 1.0\n\
+             EDGE_SE2 0 1 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2 1 2 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2_SWITCHABLE 0 2 3 2.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SWITCH_PRIOR 3 1.0 1.0\n\
+             EDGE_SE2_XY 2 4 -1.0 1.0 1.0 0.0 1.0\n",
+        );
+        factor_graph.remove_variable(1).unwrap();
+        assert_eq!(factor_graph.csr.edge_count(), 3);
+        assert_eq!(factor_graph.matrix_dim, 6);
+        assert_eq!(get_range(&factor_graph, 2), FixedType::NonFixed(0..3));
+        assert_eq!(get_range(&factor_graph, 4), FixedType::NonFixed(4..6));
+        let switch_index = factor_graph.custom_to_csr_id_map[&3];
+        assert!(factor_graph
+            .node_indices
+            .iter()
+            .flat_map(|i| factor_graph.csr.edges(*i))
+            .any(|edge| edge.weight().switch_index == Some(switch_index)));
+
+        factor_graph.remove_variable(3).unwrap();
+        assert_eq!(factor_graph.csr.edge_count(), 1);
+        factor_graph.remove_factor(2, 4).unwrap();
+        assert_eq!(factor_graph.csr.edge_count(), 0);
+
+        assert!(factor_graph.remove_variable(1).is_err());
+        assert!(factor_graph.remove_factor(2, 4).is_err());
+        assert_eq!(factor_graph.node_indices, vec![0, 1, 2]);
+        assert_eq!(factor_graph.matrix_dim, 5);
+    }
+
+    #[test]
+    fn test_factors_on_variables_with_prior() {
+        init();
+        let mut factor_graph = parse(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             VERTEX_SE2 1 1.0 0.0 0.0\n",
+        );
+        let prior = |dim: usize| Factor {
+            factor_type: LinearizedPrior,
+            constraint: vec![0.0; dim],
+            information_matrix: InformationMatrix {
+                content: DMatrix::identity(dim, dim),
+            },
+            robust_kernel: None,
+            switch_index: None,
+            prior_indices: vec![],
+        };
+        factor_graph.add_factor(prior(3), &[0]).unwrap();
+        factor_graph.add_factor(prior(6), &[0, 1]).unwrap();
+        let position = Factor {
+            factor_type: Position2D,
+            constraint: vec![0.0; 3],
+            ..prior(3)
+        };
+        let odometry = Factor {
+            factor_type: Odometry2D,
+            constraint: vec![1.0, 0.0, 0.0],
+            ..prior(3)
+        };
+        factor_graph.add_factor(position, &[0]).unwrap();
+        factor_graph.add_factor(odometry.clone(), &[0, 1]).unwrap();
+        factor_graph.add_factor(odometry, &[1, 0]).unwrap();
+        assert_eq!(factor_graph.csr.edge_count(), 3);
+        assert_eq!(factor_graph.linearized_priors.len(), 2);
+        assert!(factor_graph.add_factor(prior(6), &[1, 1]).is_err());
+
+        factor_graph.remove_factor(0, 0).unwrap();
+        assert_eq!(factor_graph.csr.edge_count(), 2);
+        assert_eq!(factor_graph.linearized_priors.len(), 2);
+        factor_graph.remove_variable(1).unwrap();
+        assert_eq!(factor_graph.csr.edge_count(), 0);
+        assert_eq!(factor_graph.linearized_priors.len(), 1);
+        assert_eq!(factor_graph.linearized_priors[0].prior_indices, vec![0]);
+    }
+
+    #[test]
+    fn test_invalid_factors() {
+        init();
+        let mut factor_graph = parse(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             VERTEX_XY 1 1.0 1.0\n",
+        );
+        let factor = Factor {
+            factor_type: Observation2D,
+            constraint: vec![1.0, 1.0],
+            information_matrix: vec![1.0, 0.0, 0.0, 1.0].into(),
+            robust_kernel: None,
+            switch_index: None,
+            prior_indices: vec![],
+        };
+        assert!(factor_graph.add_factor(factor.clone(), &[1, 0]).is_err());
+        assert_eq!(
+            factor_graph.add_factor(factor.clone(), &[0, 2]).unwrap_err(),
+            Error::UnknownVertexId(2)
+        );
+        assert!(factor_graph.add_factor(factor.clone(), &[0]).is_err());
+        let wrong_constraint = Factor {
+            constraint: vec![1.0, 1.0, 0.0],
+            ..factor.clone()
+        };
+        assert_eq!(
+            factor_graph.add_factor(wrong_constraint, &[0, 1]).unwrap_err(),
+            Error::DimensionMismatch {
+                context: String::from("Constraint of Observation2D factor"),
+                expected: 2,
+                actual: 3,
+            }
+        );
+        factor_graph.add_factor(factor.clone(), &[0, 1]).unwrap();
+        assert!(factor_graph.add_factor(factor, &[0, 1]).is_err());
+        assert_eq!(factor_graph.csr.edge_count(), 1);
+    }
+}
@@ -14,8 +14,12 @@
 
 use nalgebra::DMatrix;
 
+pub mod robust_kernel;
+
+use robust_kernel::RobustKernel;
+
 /// Enum representing a supported factor type.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum FactorType {
     /// Vehicle pose measurement in 2D.
     Position2D,
@@ -29,6 +33,14 @@ pub enum FactorType {
     Odometry3D,
     /// Relative measurement to an observed stationary variable in 3D.
     Observation3D,
+    /// Relative measurement between two poses in 2D which can be disabled by a switch variable.
+    SwitchableOdometry2D,
+    /// Relative measurement between two poses in 3D which can be disabled by a switch variable.
+    SwitchableOdometry3D,
+    /// Prior measurement of a switch variable.
+    SwitchPrior,
+    /// Dense Gaussian prior on several variables, resulting from the marginalization of other variables.
+    LinearizedPrior,
 }
 
 /// Structure representing a measurement.
@@ -38,16 +50,27 @@ pub struct Factor {
     pub factor_type: FactorType,
     /// The factor's constraint.
     ///
-    /// Content for Position2D and Odometry2D: vec![position_x, position_y, rotation]
+    /// Content for Position2D, Odometry2D and SwitchableOdometry2D: vec![position_x, position_y, rotation]
     ///
     /// Content for Observation2D: vec![position_x, position_y]
     ///
-    /// Content for Position3D and Odometry3D: vec![position_x, position_y, position_z, rotation_quaternion_x, rotation_quaternion_y, rotation_quaternion_z, rotation_quaternion_w]
+    /// Content for Position3D, Odometry3D and SwitchableOdometry3D: vec![position_x, position_y, position_z, rotation_quaternion_x, rotation_quaternion_y, rotation_quaternion_z, rotation_quaternion_w]
     ///
     /// Content for Observation3D: vec![position_x, position_y, position_z]
+    ///
+    /// Content for SwitchPrior: vec![switch_value]
+    ///
+    /// Content for LinearizedPrior: the concatenated contents of the prior's mean for all of its variables
     pub constraint: Vec<f64>,
     /// The factor's wrapped information matrix, equalling the inverse of the factor's mean matrix.
     pub information_matrix: InformationMatrix,
+    /// The factor's robust kernel. If None, the factor graph's robust kernel for the factor's type is used, if any.
+    pub robust_kernel: Option<RobustKernel>,
+    /// The internal CSR index of the switch variable scaling the factor. Only set for switchable factors.
+    pub switch_index: Option<usize>,
+    /// The internal CSR indices of all variables of a linearized prior, in the order of its rows.
+    /// Empty for all other factor types.
+    pub prior_indices: Vec<usize>,
 }
 
 /// Structure wrapping the information matrix of a factor.
@@ -56,6 +79,26 @@ pub struct InformationMatrix {
     pub content: DMatrix<f64>,
 }
 
+impl InformationMatrix {
+    /// Returns whether the matrix is square and equals its transpose.
+    pub fn is_symmetric(&self) -> bool {
+        self.content == self.content.transpose()
+    }
+
+    /// Returns whether the matrix is symmetric and none of its eigenvalues is negative beyond rounding errors.
+    pub fn is_positive_semi_definite(&self) -> bool {
+        if !self.is_symmetric() {
+            return false;
+        }
+        let tolerance = 1e-9 * self.content.amax();
+        self.content
+            .clone()
+            .symmetric_eigenvalues()
+            .iter()
+            .all(|eigenvalue| *eigenvalue >= -tolerance)
+    }
+}
+
 impl From<Vec<f64>> for InformationMatrix {
     fn from(content: Vec<f64>) -> Self {
         let dim = (content.len() as f64).sqrt() as usize;
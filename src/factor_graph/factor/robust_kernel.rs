@@ -0,0 +1,139 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Robust cost functions reducing the influence of outlier measurements.
+
+/// Enum representing a robust kernel, i.e. a function rho(chi²) replacing a factor's chi² in the total cost.
+///
+/// Each variant contains the kernel width delta. Definitions are equal to those used by g2o.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RobustKernel {
+    /// Quadratic for errors smaller than delta and linear for larger ones.
+    Huber(f64),
+    /// Logarithmic cost, never completely ignoring a measurement.
+    Cauchy(f64),
+    /// Constant cost for errors larger than delta, completely ignoring such measurements.
+    Tukey(f64),
+    /// Bounded cost, gradually ignoring measurements with errors larger than delta.
+    GemanMcClure(f64),
+    /// Dynamic Covariance Scaling, scaling the information matrix by s² with s = min(1, 2 * delta / (delta + chi²)).
+    ///
+    /// In contrast to the other kernels, delta corresponds to a chi² rather than to an error.
+    Dcs(f64),
+}
+
+impl RobustKernel {
+    /// Returns the kernel width delta.
+    pub fn delta(&self) -> f64 {
+        match self {
+            RobustKernel::Huber(delta)
+            | RobustKernel::Cauchy(delta)
+            | RobustKernel::Tukey(delta)
+            | RobustKernel::GemanMcClure(delta)
+            | RobustKernel::Dcs(delta) => *delta,
+        }
+    }
+
+    /// Returns the robust cost rho(chi2) and its first derivative rho'(chi2).
+    ///
+    /// The derivative is used as the weight of a factor's information matrix during iteratively reweighted least squares.
+    pub fn robustify(&self, chi2: f64) -> (f64, f64) {
+        let delta_sq = self.delta() * self.delta();
+        match self {
+            RobustKernel::Huber(delta) => {
+                if chi2 <= delta_sq {
+                    (chi2, 1.0)
+                } else {
+                    let err = chi2.sqrt();
+                    (2.0 * err * delta - delta_sq, delta / err)
+                }
+            }
+            RobustKernel::Cauchy(_) => {
+                let aux = chi2 / delta_sq + 1.0;
+                (delta_sq * aux.ln(), 1.0 / aux)
+            }
+            RobustKernel::Tukey(_) => {
+                if chi2 <= delta_sq {
+                    let aux = 1.0 - chi2 / delta_sq;
+                    (delta_sq * (1.0 - aux.powi(3)) / 3.0, aux * aux)
+                } else {
+                    (delta_sq / 3.0, 0.0)
+                }
+            }
+            RobustKernel::GemanMcClure(_) => {
+                let aux = delta_sq / (delta_sq + chi2);
+                (chi2 * aux, aux * aux)
+            }
+            RobustKernel::Dcs(phi) => {
+                if chi2 <= *phi {
+                    (chi2, 1.0)
+                } else {
+                    let scale = 2.0 * phi / (phi + chi2);
+                    (phi * (3.0 * chi2 - phi) / (phi + chi2), scale * scale)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_errors_are_quadratic() {
+        let kernels = vec![
+            RobustKernel::Huber(2.0),
+            RobustKernel::Cauchy(2.0),
+            RobustKernel::Tukey(2.0),
+            RobustKernel::GemanMcClure(2.0),
+            RobustKernel::Dcs(2.0),
+        ];
+        kernels.iter().for_each(|kernel| {
+            let (rho, weight) = kernel.robustify(1e-8);
+            assert!(approx::relative_eq!(rho, 1e-8, epsilon = 1e-12), "{:?}", kernel);
+            assert!(approx::relative_eq!(weight, 1.0, epsilon = 1e-6), "{:?}", kernel);
+        });
+    }
+
+    #[test]
+    fn test_large_errors_are_down_weighted() {
+        assert_eq!(RobustKernel::Huber(2.0).robustify(16.0), (12.0, 0.5));
+        assert_eq!(RobustKernel::Cauchy(2.0).robustify(4.0), (4.0 * 2.0f64.ln(), 0.5));
+        assert_eq!(RobustKernel::Tukey(2.0).robustify(16.0), (4.0 / 3.0, 0.0));
+        assert_eq!(RobustKernel::GemanMcClure(2.0).robustify(4.0), (2.0, 0.25));
+        assert_eq!(RobustKernel::Dcs(1.0).robustify(3.0), (2.0, 0.25));
+    }
+
+    #[test]
+    fn test_weight_is_derivative() {
+        let kernels = vec![
+            RobustKernel::Huber(1.5),
+            RobustKernel::Cauchy(1.5),
+            RobustKernel::Tukey(1.5),
+            RobustKernel::GemanMcClure(1.5),
+            RobustKernel::Dcs(1.5),
+        ];
+        let h = 1e-6;
+        kernels.iter().for_each(|kernel| {
+            [0.5, 1.0, 4.0].iter().for_each(|chi2| {
+                let numeric = (kernel.robustify(chi2 + h).0 - kernel.robustify(chi2 - h).0) / (2.0 * h);
+                assert!(
+                    approx::relative_eq!(kernel.robustify(*chi2).1, numeric, epsilon = 1e-6),
+                    "{:?} at {}",
+                    kernel,
+                    chi2
+                );
+            });
+        });
+    }
+}
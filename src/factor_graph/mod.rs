@@ -13,21 +13,28 @@
 //! The internal representation of a factor graph.
 
 use petgraph::csr::{Csr, NodeIndex};
+use petgraph::visit::EdgeRef;
 use petgraph::Directed;
 use std::collections::HashMap;
 use std::ops::Index;
 
+pub mod builder;
+mod editing;
 pub mod factor;
 pub mod variable;
 
-use factor::Factor;
+use factor::robust_kernel::RobustKernel;
+use factor::{Factor, FactorType};
 use variable::Variable;
 
 /// A CSR (compressed sparse row) representation of a factor graph.
 pub type FactorGraphCsr<'a> = Csr<Variable, Factor, Directed, usize>;
 
 /// Structure representing the factor graph internally.
-#[derive(Debug)]
+///
+/// Besides parsing a file, a factor graph can be constructed with the typed builder::FactorGraphBuilder.
+/// Variables and factors can be added and removed after construction.
+#[derive(Debug, Default)]
 pub struct FactorGraph {
     /// The factor graph's CSR (compressed sparse row) representation.
     pub csr: Csr<Variable, Factor, Directed, usize>,
@@ -37,6 +44,11 @@ pub struct FactorGraph {
     pub custom_to_csr_id_map: HashMap<usize, NodeIndex<usize>>,
     /// The number of nodes which are dynamic, i.e. the number of fixed nodes subtracted of the total number of nodes.
     pub matrix_dim: usize,
+    /// The robust kernels used for all factors of a type which do not have their own robust kernel.
+    pub robust_kernels: HashMap<FactorType, RobustKernel>,
+    /// The linearized priors, which are not stored in the CSR representation as they may connect any number of
+    /// variables. Their variables are stated by their prior indices.
+    pub linearized_priors: Vec<Factor>,
 }
 
 impl FactorGraph {
@@ -44,4 +56,91 @@ impl FactorGraph {
     pub fn get_var(&self, csr_index: usize) -> &Variable {
         self.csr.index(csr_index)
     }
+
+    /// Returns the robust kernel applied to the given factor, which is either the factor's own robust kernel
+    /// or the robust kernel set for the factor's type.
+    pub fn get_robust_kernel(&self, factor: &Factor) -> Option<RobustKernel> {
+        factor
+            .robust_kernel
+            .or_else(|| self.robust_kernels.get(&factor.factor_type).copied())
+    }
+
+    /// Returns all factors, i.e. the factors of the CSR representation followed by the linearized priors, each
+    /// together with the internal CSR indices of its variables in the order expected by add_factor().
+    pub fn factors(&self) -> impl Iterator<Item = (&Factor, Vec<usize>)> + '_ {
+        let edges = self
+            .node_indices
+            .iter()
+            .flat_map(move |i| self.csr.edges(*i))
+            .map(|edge| {
+                let factor = edge.weight();
+                let mut indices = vec![edge.source()];
+                if edge.target() != edge.source() {
+                    indices.push(edge.target());
+                }
+                indices.extend(factor.switch_index);
+                (factor, indices)
+            });
+        let priors = self
+            .linearized_priors
+            .iter()
+            .map(|prior| (prior, prior.prior_indices.clone()));
+        edges.chain(priors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::optimize;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+    use log::LevelFilter;
+    use std::sync::Arc;
+    use std::thread;
+    use variable::{FixedType, VehicleVariable2D};
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<FactorGraph>();
+    }
+
+    #[test]
+    fn test_variable_accessors() {
+        let var = VehicleVariable2D::new(0, 1.0, 2.0, 0.5, FixedType::NonFixed(0..3));
+        assert_eq!(var.pose(), [1.0, 2.0, 0.5]);
+        var.set_pose([3.0, 4.0, -0.5]);
+        assert_eq!(var.pose(), [3.0, 4.0, -0.5]);
+        assert_eq!(Variable::Vehicle2D(var).get_content(), vec![3.0, 4.0, -0.5]);
+    }
+
+    #[test]
+    fn test_optimize_in_background_thread() {
+        init();
+        let file_path = "data_files/optimizer_tests/full2d_0.g2o";
+        let factor_graph = Arc::new(G2oParser::parse_file(file_path).unwrap());
+        let background_graph = Arc::clone(&factor_graph);
+        let report = thread::spawn(move || optimize(&background_graph, 5).unwrap())
+            .join()
+            .unwrap();
+        assert!(report.final_chi2 < report.initial_chi2);
+
+        let expected_graph = G2oParser::parse_file(file_path).unwrap();
+        optimize(&expected_graph, 5).unwrap();
+        factor_graph.node_indices.iter().for_each(|i| {
+            assert_eq!(
+                factor_graph.get_var(*i).get_content(),
+                expected_graph.get_var(*i).get_content()
+            )
+        });
+    }
 }
@@ -11,12 +11,14 @@
 //
 
 //! The internal representation of a factor graph's optimizable variable.
+//!
+//! The contents of all variables are stored behind locks, so that they can be updated through shared references
+//! while a factor graph is shared between threads.
 
-use std::cell::RefCell;
 use std::ops::Range;
-use std::rc::Rc;
+use std::sync::RwLock;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum FixedType {
     Fixed,
     NonFixed(Range<usize>),
@@ -26,7 +28,7 @@ pub enum FixedType {
 #[derive(Debug)]
 pub struct VehicleVariable2D {
     pub id: usize,
-    pub pose: Rc<RefCell<[f64; 3]>>,
+    pose: RwLock<[f64; 3]>,
     pub fixed_type: FixedType,
 }
 
@@ -34,7 +36,7 @@ pub struct VehicleVariable2D {
 #[derive(Debug)]
 pub struct LandmarkVariable2D {
     pub id: usize,
-    pub position: Rc<RefCell<[f64; 2]>>,
+    position: RwLock<[f64; 2]>,
     pub fixed_type: FixedType,
 }
 
@@ -42,7 +44,7 @@ pub struct LandmarkVariable2D {
 #[derive(Debug)]
 pub struct VehicleVariable3D {
     pub id: usize,
-    pub pose: Rc<RefCell<[f64; 7]>>,
+    pose: RwLock<[f64; 7]>,
     pub fixed_type: FixedType,
 }
 
@@ -50,12 +52,20 @@ pub struct VehicleVariable3D {
 #[derive(Debug)]
 pub struct LandmarkVariable3D {
     pub id: usize,
-    pub position: Rc<RefCell<[f64; 3]>>,
+    position: RwLock<[f64; 3]>,
     pub fixed_type: FixedType,
 }
 
-/// Enum representing a supported variable type.
+/// Representation of an optimizable switch variable, scaling a switchable factor.
 #[derive(Debug)]
+pub struct SwitchVariable {
+    pub id: usize,
+    value: RwLock<f64>,
+    pub fixed_type: FixedType,
+}
+
+/// Enum representing a supported variable type.
+#[derive(Debug, Clone)]
 pub enum Variable {
     /// Vehicle pose (position and rotation) in 2D.
     Vehicle2D(VehicleVariable2D),
@@ -65,16 +75,28 @@ pub enum Variable {
     Vehicle3D(VehicleVariable3D),
     /// Landmark position in 3D.
     Landmark3D(LandmarkVariable3D),
+    /// Switch value in [0, 1] of a switchable factor.
+    Switch(SwitchVariable),
 }
 impl VehicleVariable2D {
     /// Returns a new variable from a 2D pose, a given ID and whether the variable is fixed.
     pub fn new(id: usize, x: f64, y: f64, phi: f64, fixed_type: FixedType) -> Self {
         VehicleVariable2D {
             id,
-            pose: Rc::new(RefCell::new([x, y, phi])),
+            pose: RwLock::new([x, y, phi]),
             fixed_type,
         }
     }
+
+    /// Returns the current pose [x, y, phi].
+    pub fn pose(&self) -> [f64; 3] {
+        *self.pose.read().unwrap()
+    }
+
+    /// Replaces the current pose [x, y, phi].
+    pub fn set_pose(&self, pose: [f64; 3]) {
+        *self.pose.write().unwrap() = pose;
+    }
 }
 
 impl LandmarkVariable2D {
@@ -82,10 +104,20 @@ impl LandmarkVariable2D {
     pub fn new(id: usize, x: f64, y: f64, fixed_type: FixedType) -> Self {
         LandmarkVariable2D {
             id,
-            position: Rc::new(RefCell::new([x, y])),
+            position: RwLock::new([x, y]),
             fixed_type,
         }
     }
+
+    /// Returns the current position [x, y].
+    pub fn position(&self) -> [f64; 2] {
+        *self.position.read().unwrap()
+    }
+
+    /// Replaces the current position [x, y].
+    pub fn set_position(&self, position: [f64; 2]) {
+        *self.position.write().unwrap() = position;
+    }
 }
 
 impl VehicleVariable3D {
@@ -103,10 +135,20 @@ impl VehicleVariable3D {
     ) -> Self {
         VehicleVariable3D {
             id,
-            pose: Rc::new(RefCell::new([x, y, z, rot_x, rot_y, rot_z, rot_w])),
+            pose: RwLock::new([x, y, z, rot_x, rot_y, rot_z, rot_w]),
             fixed_type,
         }
     }
+
+    /// Returns the current pose [x, y, z, rot_x, rot_y, rot_z, rot_w].
+    pub fn pose(&self) -> [f64; 7] {
+        *self.pose.read().unwrap()
+    }
+
+    /// Replaces the current pose [x, y, z, rot_x, rot_y, rot_z, rot_w].
+    pub fn set_pose(&self, pose: [f64; 7]) {
+        *self.pose.write().unwrap() = pose;
+    }
 }
 
 impl LandmarkVariable3D {
@@ -114,10 +156,91 @@ impl LandmarkVariable3D {
     pub fn new(id: usize, x: f64, y: f64, z: f64, fixed_type: FixedType) -> Self {
         LandmarkVariable3D {
             id,
-            position: Rc::new(RefCell::new([x, y, z])),
+            position: RwLock::new([x, y, z]),
+            fixed_type,
+        }
+    }
+
+    /// Returns the current position [x, y, z].
+    pub fn position(&self) -> [f64; 3] {
+        *self.position.read().unwrap()
+    }
+
+    /// Replaces the current position [x, y, z].
+    pub fn set_position(&self, position: [f64; 3]) {
+        *self.position.write().unwrap() = position;
+    }
+}
+
+impl SwitchVariable {
+    /// Returns a new variable from a switch value, a given ID and whether the variable is fixed.
+    pub fn new(id: usize, value: f64, fixed_type: FixedType) -> Self {
+        SwitchVariable {
+            id,
+            value: RwLock::new(value),
             fixed_type,
         }
     }
+
+    /// Returns the current switch value.
+    pub fn value(&self) -> f64 {
+        *self.value.read().unwrap()
+    }
+
+    /// Replaces the current switch value.
+    pub fn set_value(&self, value: f64) {
+        *self.value.write().unwrap() = value;
+    }
+}
+
+impl Clone for VehicleVariable2D {
+    fn clone(&self) -> Self {
+        VehicleVariable2D {
+            id: self.id,
+            pose: RwLock::new(self.pose()),
+            fixed_type: self.fixed_type.clone(),
+        }
+    }
+}
+
+impl Clone for LandmarkVariable2D {
+    fn clone(&self) -> Self {
+        LandmarkVariable2D {
+            id: self.id,
+            position: RwLock::new(self.position()),
+            fixed_type: self.fixed_type.clone(),
+        }
+    }
+}
+
+impl Clone for VehicleVariable3D {
+    fn clone(&self) -> Self {
+        VehicleVariable3D {
+            id: self.id,
+            pose: RwLock::new(self.pose()),
+            fixed_type: self.fixed_type.clone(),
+        }
+    }
+}
+
+impl Clone for LandmarkVariable3D {
+    fn clone(&self) -> Self {
+        LandmarkVariable3D {
+            id: self.id,
+            position: RwLock::new(self.position()),
+            fixed_type: self.fixed_type.clone(),
+        }
+    }
+}
+
+impl Clone for SwitchVariable {
+    fn clone(&self) -> Self {
+        SwitchVariable {
+            id: self.id,
+            value: RwLock::new(self.value()),
+            fixed_type: self.fixed_type.clone(),
+        }
+    }
 }
 
 impl Variable {
@@ -127,15 +250,45 @@ impl Variable {
             Variable::Landmark2D(v) => &v.fixed_type,
             Variable::Vehicle3D(v) => &v.fixed_type,
             Variable::Landmark3D(v) => &v.fixed_type,
+            Variable::Switch(v) => &v.fixed_type,
+        }
+    }
+    pub(crate) fn set_fixed_type(&mut self, fixed_type: FixedType) {
+        match self {
+            Variable::Vehicle2D(v) => v.fixed_type = fixed_type,
+            Variable::Landmark2D(v) => v.fixed_type = fixed_type,
+            Variable::Vehicle3D(v) => v.fixed_type = fixed_type,
+            Variable::Landmark3D(v) => v.fixed_type = fixed_type,
+            Variable::Switch(v) => v.fixed_type = fixed_type,
+        }
+    }
+    /// Returns the number of rows the variable occupies in H if it is not fixed.
+    pub fn get_dim(&self) -> usize {
+        match self {
+            Variable::Vehicle2D(_) => 3,
+            Variable::Landmark2D(_) => 2,
+            Variable::Vehicle3D(_) => 6,
+            Variable::Landmark3D(_) => 3,
+            Variable::Switch(_) => 1,
+        }
+    }
+    pub fn get_content(&self) -> Vec<f64> {
+        match self {
+            Variable::Vehicle2D(v) => v.pose().to_vec(),
+            Variable::Landmark2D(v) => v.position().to_vec(),
+            Variable::Vehicle3D(v) => v.pose().to_vec(),
+            Variable::Landmark3D(v) => v.position().to_vec(),
+            Variable::Switch(v) => vec![v.value()],
         }
     }
     pub fn set_content(&self, update: Vec<f64>) {
         let u = update;
         match self {
-            Variable::Vehicle2D(v) => *v.pose.borrow_mut() = [u[0], u[1], u[2]],
-            Variable::Landmark2D(v) => *v.position.borrow_mut() = [u[0], u[1]],
-            Variable::Vehicle3D(v) => *v.pose.borrow_mut() = [u[0], u[1], u[2], u[3], u[4], u[5], u[6]],
-            Variable::Landmark3D(v) => *v.position.borrow_mut() = [u[0], u[1], u[2]],
+            Variable::Vehicle2D(v) => v.set_pose([u[0], u[1], u[2]]),
+            Variable::Landmark2D(v) => v.set_position([u[0], u[1]]),
+            Variable::Vehicle3D(v) => v.set_pose([u[0], u[1], u[2], u[3], u[4], u[5], u[6]]),
+            Variable::Landmark3D(v) => v.set_position([u[0], u[1], u[2]]),
+            Variable::Switch(v) => v.set_value(u[0]),
         }
     }
     pub fn get_id(&self) -> usize {
@@ -144,6 +297,7 @@ impl Variable {
             Variable::Landmark2D(v) => v.id,
             Variable::Vehicle3D(v) => v.id,
             Variable::Landmark3D(v) => v.id,
+            Variable::Switch(v) => v.id,
         }
     }
 }
@@ -10,19 +10,21 @@
 // This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
 //
 
+pub mod error;
 pub mod factor_graph;
 pub mod optimizer;
 pub mod parser;
 pub mod visualizer;
 
+use error::Error;
 use parser::g2o::G2oParser;
 use parser::Parser;
 
 /// Example for the usage of the library
-pub fn optimize(in_file: &str, out_file: &str, iterations: usize) {
-    let factor_graph = G2oParser::parse_file(in_file).unwrap();
+pub fn optimize(in_file: &str, out_file: &str, iterations: usize) -> Result<(), Error> {
+    let factor_graph = G2oParser::parse_file(in_file)?;
 
-    optimizer::optimize(&factor_graph, iterations);
+    optimizer::optimize(&factor_graph, iterations)?;
 
-    G2oParser::compose_file(&factor_graph, out_file).unwrap();
+    G2oParser::compose_file(&factor_graph, out_file)
 }
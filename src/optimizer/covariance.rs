@@ -0,0 +1,161 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Recovery of the variables' marginal covariances from the Cholesky factor of H.
+
+#![allow(non_snake_case)]
+
+use crate::error::Error;
+use crate::factor_graph::variable::FixedType;
+use crate::factor_graph::FactorGraph;
+use crate::optimizer::linear_system::calculate_H_b;
+use nalgebra::{CsCholesky, DMatrix, DVector, Dynamic};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Structure holding the Cholesky factor L of H = L * Lᵀ, linearized at the variables' current contents.
+///
+/// Covariances are recovered column by column via forward and backward substitution with L,
+/// so the dense inverse of H is never calculated.
+/// The covariance of a variable refers to its update, i.e. 3x3 for 2D poses and 6x6 for 3D poses.
+pub struct CovarianceRecovery {
+    cholesky: CsCholesky<f64, Dynamic>,
+    ranges: HashMap<usize, Range<usize>>,
+}
+
+impl CovarianceRecovery {
+    /// Builds and decomposes H of the given factor graph, which is usually done after the optimization.
+    pub fn new(factor_graph: &FactorGraph) -> Result<Self, Error> {
+        let (H, _) = calculate_H_b(factor_graph);
+        let cholesky = CsCholesky::new(&H.to_cs_matrix());
+        if cholesky.l().is_none() {
+            return Err(Error::SolverFailure(String::from("H is not positive-definite")));
+        }
+        let ranges = factor_graph
+            .node_indices
+            .iter()
+            .map(|i| factor_graph.get_var(*i))
+            .filter_map(|var| match var.get_fixed_type() {
+                FixedType::NonFixed(range) => Some((var.get_id(), range.to_owned())),
+                FixedType::Fixed => None,
+            })
+            .collect();
+        Ok(CovarianceRecovery { cholesky, ranges })
+    }
+
+    /// Returns the marginal covariance of the variable with the given custom ID.
+    pub fn marginal_covariance(&self, id: usize) -> Result<DMatrix<f64>, Error> {
+        self.cross_covariance(id, id)
+    }
+
+    /// Returns the cross-covariance between the variables with the given custom IDs.
+    /// Its rows belong to the first and its columns to the second variable.
+    pub fn cross_covariance(&self, id_a: usize, id_b: usize) -> Result<DMatrix<f64>, Error> {
+        let range_a = self.get_range(id_a)?;
+        let range_b = self.get_range(id_b)?;
+        let columns = self.solve_unit_columns(range_b);
+        Ok(columns.rows(range_a.start, range_a.len()).into_owned())
+    }
+
+    fn get_range(&self, id: usize) -> Result<Range<usize>, Error> {
+        match self.ranges.get(&id) {
+            Some(range) => Ok(range.to_owned()),
+            None => Err(Error::InvalidFactorGraph(format!(
+                "No covariance for variable {}: It is fixed or does not exist",
+                id
+            ))),
+        }
+    }
+
+    /// Returns the columns of the inverse of H in the given range by solving H * x = e_i for each column i.
+    fn solve_unit_columns(&self, range: Range<usize>) -> DMatrix<f64> {
+        let l = self.cholesky.l().unwrap();
+        let columns: Vec<DVector<f64>> = range
+            .map(|i| {
+                let mut unit_column = DVector::zeros(l.nrows());
+                unit_column[i] = 1.0;
+                l.tr_solve_lower_triangular(&l.solve_lower_triangular(&unit_column).unwrap())
+                    .unwrap()
+            })
+            .collect();
+        DMatrix::from_columns(&columns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::optimize;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+    use std::convert::TryInto;
+
+    use log::LevelFilter;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    fn assert_approx_equal(a: &DMatrix<f64>, b: &DMatrix<f64>) {
+        assert_eq!(a.shape(), b.shape());
+        a.iter().zip(b.iter()).for_each(|(x, y)| {
+            assert!(
+                approx::relative_eq!(x, y, epsilon = 1e-8, max_relative = 1e-6),
+                "{} versus {}",
+                x,
+                y
+            )
+        });
+    }
+
+    #[test]
+    fn test_single_odometry() {
+        init();
+        let factor_graph: FactorGraph = G2oParser::parse_string_to_model(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             FIX 0\n\
+             VERTEX_SE2 1 1.0 0.0 0.0\n\
+             EDGE_SE2 0 1 1.0 0.0 0.0 4.0 0.0 0.0 4.0 0.0 4.0\n",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let recovery = CovarianceRecovery::new(&factor_graph).unwrap();
+        let covariance = recovery.marginal_covariance(1).unwrap();
+        assert_approx_equal(&covariance, &(DMatrix::identity(3, 3) * 0.25));
+        assert!(recovery.marginal_covariance(0).is_err());
+        assert!(recovery.marginal_covariance(2).is_err());
+    }
+
+    #[test]
+    fn test_equal_to_dense_inverse() {
+        init();
+        let factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+        optimize(&factor_graph, 5).unwrap();
+        let H_inv = calculate_H_b(&factor_graph).0.to_dense().try_inverse().unwrap();
+        let recovery = CovarianceRecovery::new(&factor_graph).unwrap();
+        let ids: Vec<usize> = recovery.ranges.keys().take(5).copied().collect();
+        ids.iter().for_each(|id_a| {
+            ids.iter().for_each(|id_b| {
+                let (range_a, range_b) = (&recovery.ranges[id_a], &recovery.ranges[id_b]);
+                let expected = H_inv.slice((range_a.start, range_b.start), (range_a.len(), range_b.len()));
+                assert_approx_equal(
+                    &recovery.cross_covariance(*id_a, *id_b).unwrap(),
+                    &expected.into_owned(),
+                );
+            })
+        });
+    }
+}
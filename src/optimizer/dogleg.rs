@@ -0,0 +1,304 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Powell's dogleg iterations within an adaptive trust region.
+
+#![allow(non_snake_case)]
+
+use crate::factor_graph::FactorGraph;
+use crate::optimizer::evaluation::calculate_chi2;
+use crate::optimizer::linear_system::calculate_H_b;
+use crate::optimizer::ordering::Permutation;
+use crate::optimizer::report::IterationStatistics;
+use crate::optimizer::solver::Solver;
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use crate::optimizer::{apply_solution, calc_update_norm, get_contents, set_contents};
+use nalgebra::DVector;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Parameters of the dogleg algorithm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DoglegParams {
+    /// The trust radius of the first iteration.
+    pub initial_trust_radius: f64,
+    /// The number of rejected steps after which an iteration gives up.
+    pub max_trials: usize,
+}
+
+impl Default for DoglegParams {
+    fn default() -> Self {
+        DoglegParams {
+            initial_trust_radius: 1e4,
+            max_trials: 10,
+        }
+    }
+}
+
+/// The kind of step proposed by the dogleg algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DoglegStepType {
+    /// The full Gauss-Newton step, which lies within the trust region.
+    GaussNewton,
+    /// The steepest descent step, cut off at the trust radius.
+    SteepestDescent,
+    /// The point on the line between the steepest descent and Gauss-Newton step which lies on the trust radius.
+    Interpolated,
+}
+
+/// The reason why a dogleg step was accepted or rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DoglegDecision {
+    /// The step decreased the total chi² and was kept.
+    Accepted,
+    /// The step did not decrease the total chi² and was rolled back.
+    RejectedNoDecrease,
+    /// The step produced a non-finite total chi² and was rolled back.
+    RejectedNotFinite,
+}
+
+/// A single step proposed within a dogleg iteration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DoglegTrial {
+    /// The kind of the proposed step.
+    pub step_type: DoglegStepType,
+    /// The trust radius the step was proposed with.
+    pub trust_radius: f64,
+    /// Whether the Gauss-Newton step could be calculated. If not, H is not positive-definite.
+    pub gauss_newton_available: bool,
+    /// The ratio between the actual and the predicted decrease of the total chi².
+    /// None if it is not finite, e.g. if the new total chi² is not finite.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain_ratio: Option<f64>,
+    /// Whether the step was accepted and why.
+    pub decision: DoglegDecision,
+}
+
+/// State of the dogleg algorithm which is kept across iterations.
+pub struct Dogleg<'a> {
+    params: &'a DoglegParams,
+    trust_radius: f64,
+}
+
+impl<'a> Dogleg<'a> {
+    pub fn new(params: &'a DoglegParams) -> Self {
+        Dogleg {
+            params,
+            trust_radius: params.initial_trust_radius,
+        }
+    }
+
+    /// Returns the current trust radius.
+    pub fn trust_radius(&self) -> f64 {
+        self.trust_radius
+    }
+
+    /// Performs a single iteration, shrinking the trust region until a step decreases the total chi².
+    /// Rejected steps are rolled back.
+    ///
+    /// All steps tried within this iteration are listed in the returned statistics.
+    ///
+    /// The linear system is reordered by the given permutation before it is solved by the given solver,
+    /// which may keep its state across iterations.
+    pub fn iterate(
+        &mut self,
+        factor_graph: &FactorGraph,
+        permutation: &Permutation,
+        solver: &mut dyn Solver,
+    ) -> IterationStatistics {
+        let build_start = Instant::now();
+        let (H, b) = calculate_H_b(factor_graph);
+        let linear_system_time = build_start.elapsed();
+        let chi2 = calculate_chi2(factor_graph);
+        let old_contents = get_contents(factor_graph);
+
+        let solve_start = Instant::now();
+        let gauss_newton_step = permutation.solve(solver, &H, &(&b * -1.0)).ok().map(DVector::from_vec);
+        let solver_time = solve_start.elapsed();
+        let b_norm_sq = b.norm_squared();
+        let alpha = if b_norm_sq > 0.0 {
+            b_norm_sq / b.dot(&(&H * &b))
+        } else {
+            0.0
+        };
+        let steepest_descent_step = &b * -alpha;
+
+        let mut statistics = IterationStatistics::new(chi2, b_norm_sq.sqrt());
+        statistics.linear_system_time = linear_system_time;
+        statistics.solver_time = solver_time;
+        statistics.solver_residual = solver.residual_norm();
+        for _trial in 0..self.params.max_trials {
+            let (step, step_type) = self.calc_step(&steepest_descent_step, gauss_newton_step.as_ref());
+            apply_solution(factor_graph, step.as_slice());
+            let new_chi2 = calculate_chi2(factor_graph);
+            let gain_ratio = (chi2 - new_chi2) / calc_predicted_decrease(&H, &b, &step);
+            let decision = if !new_chi2.is_finite() {
+                DoglegDecision::RejectedNotFinite
+            } else if gain_ratio > 0.0 {
+                DoglegDecision::Accepted
+            } else {
+                DoglegDecision::RejectedNoDecrease
+            };
+            statistics.trust_radius = Some(self.trust_radius);
+            statistics.dogleg_trials.push(DoglegTrial {
+                step_type,
+                trust_radius: self.trust_radius,
+                gauss_newton_available: gauss_newton_step.is_some(),
+                gain_ratio: Some(gain_ratio).filter(|gain_ratio| gain_ratio.is_finite()),
+                decision,
+            });
+            if decision == DoglegDecision::Accepted {
+                statistics.chi2_after = new_chi2;
+                statistics.update_norm = calc_update_norm(step.as_slice());
+                statistics.accepted = true;
+                if gain_ratio > 0.75 {
+                    self.trust_radius = self.trust_radius.max(3.0 * step.norm());
+                } else if gain_ratio < 0.25 {
+                    self.trust_radius *= 0.5;
+                }
+                break;
+            }
+            set_contents(factor_graph, &old_contents);
+            self.trust_radius *= 0.5;
+        }
+        statistics
+    }
+
+    fn calc_step(
+        &self,
+        steepest_descent_step: &DVector<f64>,
+        gauss_newton_step: Option<&DVector<f64>>,
+    ) -> (DVector<f64>, DoglegStepType) {
+        let radius = self.trust_radius;
+        let sd_norm = steepest_descent_step.norm();
+        let gn_step = match gauss_newton_step {
+            Some(gn_step) if gn_step.norm() <= radius => return (gn_step.clone(), DoglegStepType::GaussNewton),
+            Some(gn_step) if sd_norm < radius => gn_step,
+            _ => {
+                let scale = if sd_norm > radius { radius / sd_norm } else { 1.0 };
+                return (steepest_descent_step * scale, DoglegStepType::SteepestDescent);
+            }
+        };
+        // find beta such that |sd + beta * (gn - sd)| equals the trust radius
+        let diff = gn_step - steepest_descent_step;
+        let c = steepest_descent_step.dot(&diff);
+        let diff_norm_sq = diff.norm_squared();
+        let radius_gap = radius * radius - sd_norm * sd_norm;
+        let root = (c * c + diff_norm_sq * radius_gap).sqrt();
+        let beta = if c <= 0.0 {
+            (root - c) / diff_norm_sq
+        } else {
+            radius_gap / (root + c)
+        };
+        (steepest_descent_step + diff * beta, DoglegStepType::Interpolated)
+    }
+}
+
+fn calc_predicted_decrease(H: &SparseMatrix, b: &DVector<f64>, step: &DVector<f64>) -> f64 {
+    -2.0 * b.dot(step) - step.dot(&(H * step))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::ordering::VariableOrdering;
+    use crate::optimizer::solver::sparse_cholesky::SparseCholeskySolver;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+
+    use log::LevelFilter;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    fn test_chi2_never_increases(file_name: &str) {
+        init();
+        let factor_graph =
+            G2oParser::parse_file(&["data_files/optimizer_tests/", file_name, "_0.g2o"].concat()).unwrap();
+        let params = DoglegParams::default();
+        let mut dogleg = Dogleg::new(&params);
+        let permutation = Permutation::new(&factor_graph, VariableOrdering::MinimumDegree);
+        let mut solver = SparseCholeskySolver::default();
+        let initial_chi2 = calculate_chi2(&factor_graph);
+        let mut chi2 = initial_chi2;
+        for _i in 0..10 {
+            let iteration = dogleg.iterate(&factor_graph, &permutation, &mut solver);
+            let trials = &iteration.dogleg_trials;
+            let new_chi2 = calculate_chi2(&factor_graph);
+            assert_eq!(iteration.chi2_after, new_chi2);
+            assert_eq!(
+                iteration.accepted,
+                trials.last().unwrap().decision == DoglegDecision::Accepted
+            );
+            assert!(trials[..trials.len() - 1]
+                .iter()
+                .all(|trial| trial.decision != DoglegDecision::Accepted));
+            if trials.last().unwrap().decision == DoglegDecision::Accepted {
+                assert!(
+                    new_chi2 < chi2,
+                    "accepted step increased chi² from {} to {}",
+                    chi2,
+                    new_chi2
+                );
+            } else {
+                assert_eq!(new_chi2, chi2);
+            }
+            chi2 = new_chi2;
+        }
+        assert!(chi2 < initial_chi2);
+    }
+
+    #[test]
+    fn test_chi2_never_increases_2d() {
+        test_chi2_never_increases("full2d");
+    }
+
+    #[test]
+    fn test_chi2_never_increases_3d() {
+        test_chi2_never_increases("obs3d_mainly");
+    }
+
+    #[test]
+    fn test_step_types() {
+        let params = DoglegParams {
+            initial_trust_radius: 1.0,
+            max_trials: 1,
+        };
+        let dogleg = Dogleg::new(&params);
+        let sd = DVector::from_vec(vec![0.5, 0.0]);
+        let gn = DVector::from_vec(vec![0.5, 0.5]);
+        assert_eq!(
+            dogleg.calc_step(&sd, Some(&gn)),
+            (gn.clone(), DoglegStepType::GaussNewton)
+        );
+
+        let far_gn = DVector::from_vec(vec![0.5, 2.0]);
+        let (step, step_type) = dogleg.calc_step(&sd, Some(&far_gn));
+        assert_eq!(step_type, DoglegStepType::Interpolated);
+        assert!(approx::relative_eq!(step.norm(), 1.0, epsilon = 1e-10));
+        assert!(approx::relative_eq!(step[0], 0.5, epsilon = 1e-10));
+
+        let far_sd = DVector::from_vec(vec![4.0, 0.0]);
+        let (step, step_type) = dogleg.calc_step(&far_sd, Some(&far_gn));
+        assert_eq!(step_type, DoglegStepType::SteepestDescent);
+        assert_eq!(step, DVector::from_vec(vec![1.0, 0.0]));
+
+        let (step, step_type) = dogleg.calc_step(&sd, None);
+        assert_eq!(step_type, DoglegStepType::SteepestDescent);
+        assert_eq!(step, sd);
+    }
+}
@@ -0,0 +1,224 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Evaluation of how well a factor graph's variables fit its measurements.
+
+use crate::factor_graph::factor::{Factor, FactorType};
+use crate::factor_graph::FactorGraph;
+use crate::optimizer::linear_system::{calc_chi2, calc_error};
+
+/// Structure containing the residual of a single factor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FactorEvaluation {
+    /// The factor's type.
+    pub factor_type: FactorType,
+    /// The custom IDs of the factor's variables, as stated in the parsed file.
+    pub vertex_ids: Vec<usize>,
+    /// The error vector between the factor's constraint and the measurement predicted by its variables.
+    pub error: Vec<f64>,
+    /// The error weighted by the factor's information matrix, i.e. errorᵀ * information_matrix * error.
+    pub chi2: f64,
+    /// The Mahalanobis distance of the error, i.e. the square root of chi².
+    pub mahalanobis_distance: f64,
+    /// The chi² after applying the factor's robust kernel. Equals chi2 if the factor has no robust kernel.
+    pub robust_chi2: f64,
+    /// The weight of the factor's information matrix applied by its robust kernel. Equals 1 if the factor has no
+    /// robust kernel. For DCS, this is the squared scaling factor, with values near 0 indicating a rejected factor.
+    pub weight: f64,
+}
+
+/// Structure containing the residuals of all factors of a factor graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphEvaluation {
+    /// The evaluations of all factors in the order of FactorGraph::factors().
+    pub factors: Vec<FactorEvaluation>,
+    /// The total chi² of the factor graph, i.e. the sum of all factors' chi².
+    pub chi2: f64,
+    /// The total robust chi² of the factor graph, i.e. the sum of all factors' robust chi², which is minimized by the optimizer.
+    pub robust_chi2: f64,
+}
+
+/// Evaluates every factor of the given factor graph at the variables' current contents.
+pub fn evaluate(factor_graph: &FactorGraph) -> GraphEvaluation {
+    let factors: Vec<FactorEvaluation> = factor_graph
+        .factors()
+        .map(|(factor, indices)| evaluate_factor(factor_graph, factor, &indices))
+        .collect();
+    let chi2 = factors.iter().map(|factor| factor.chi2).sum();
+    let robust_chi2 = factors.iter().map(|factor| factor.robust_chi2).sum();
+    GraphEvaluation {
+        factors,
+        chi2,
+        robust_chi2,
+    }
+}
+
+/// Returns the total robust chi² of the given factor graph at the variables' current contents.
+///
+/// Equals the total chi² if no robust kernels are used.
+pub fn calculate_chi2(factor_graph: &FactorGraph) -> f64 {
+    factor_graph
+        .factors()
+        .map(|(factor, indices)| {
+            let chi2 = calc_chi2(factor, &calc_error(factor_graph, factor, &indices));
+            calc_robust_chi2(factor_graph, factor, chi2)
+        })
+        .sum()
+}
+
+fn evaluate_factor(factor_graph: &FactorGraph, factor: &Factor, indices: &[usize]) -> FactorEvaluation {
+    // the switch variable of a switchable factor is not listed
+    let var_count = indices.len() - factor.switch_index.iter().count();
+    let vertex_ids = indices[..var_count]
+        .iter()
+        .map(|index| factor_graph.get_var(*index).get_id())
+        .collect();
+    let error = calc_error(factor_graph, factor, indices);
+    let chi2 = calc_chi2(factor, &error);
+    let (robust_chi2, weight) = factor_graph
+        .get_robust_kernel(factor)
+        .map_or((chi2, 1.0), |kernel| kernel.robustify(chi2));
+    FactorEvaluation {
+        factor_type: factor.factor_type.clone(),
+        vertex_ids,
+        error,
+        chi2,
+        mahalanobis_distance: chi2.sqrt(),
+        robust_chi2,
+        weight,
+    }
+}
+
+fn calc_robust_chi2(factor_graph: &FactorGraph, factor: &Factor, chi2: f64) -> f64 {
+    factor_graph
+        .get_robust_kernel(factor)
+        .map_or(chi2, |kernel| kernel.robustify(chi2).0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factor_graph::factor::robust_kernel::RobustKernel;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+    use std::convert::TryInto;
+
+    use log::LevelFilter;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    #[test]
+    fn test_single_factors() {
+        init();
+        let factor_graph: FactorGraph = G2oParser::parse_string_to_model(
+            "VERTEX_SE2 0 1.0 2.0 0.0\n\
+             VERTEX_XY 1 2.0 2.0\n\
+             EDGE_PRIOR_SE2 0 1.0 1.0 0.0 4.0 0.0 0.0 4.0 0.0 4.0\n\
+             EDGE_SE2_XY 0 1 1.0 0.0 2.0 0.0 2.0\n",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let evaluation = evaluate(&factor_graph);
+        assert_eq!(evaluation.factors.len(), 2);
+
+        let prior = &evaluation.factors[0];
+        assert_eq!(prior.factor_type, FactorType::Position2D);
+        assert_eq!(prior.vertex_ids, vec![0]);
+        assert_eq!(prior.error, vec![0.0, 1.0, 0.0]);
+        assert_eq!(prior.chi2, 4.0);
+        assert_eq!(prior.mahalanobis_distance, 2.0);
+        assert_eq!(prior.weight, 1.0);
+
+        let observation = &evaluation.factors[1];
+        assert_eq!(observation.factor_type, FactorType::Observation2D);
+        assert_eq!(observation.vertex_ids, vec![0, 1]);
+        assert_eq!(observation.chi2, 0.0);
+
+        assert_eq!(evaluation.chi2, 4.0);
+        assert_eq!(calculate_chi2(&factor_graph), 4.0);
+    }
+
+    #[test]
+    fn test_robust_kernel_per_factor_type() {
+        init();
+        let mut factor_graph: FactorGraph = G2oParser::parse_string_to_model(
+            "VERTEX_SE2 0 1.0 3.0 0.0\n\
+             EDGE_PRIOR_SE2 0 1.0 1.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        assert_eq!(calculate_chi2(&factor_graph), 4.0);
+
+        factor_graph
+            .robust_kernels
+            .insert(FactorType::Position2D, RobustKernel::Huber(1.0));
+        let evaluation = evaluate(&factor_graph);
+        assert_eq!(evaluation.chi2, 4.0);
+        assert_eq!(evaluation.factors[0].robust_chi2, 3.0);
+        assert_eq!(evaluation.factors[0].weight, 0.5);
+        assert_eq!(evaluation.robust_chi2, 3.0);
+        assert_eq!(calculate_chi2(&factor_graph), 3.0);
+    }
+
+    #[test]
+    fn test_dcs_rejects_outlier() {
+        init();
+        let mut factor_graph: FactorGraph = G2oParser::parse_string_to_model(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             FIX 0\n\
+             VERTEX_SE2 1 1.0 0.0 0.0\n\
+             VERTEX_SE2 2 2.0 0.0 0.0\n\
+             EDGE_SE2 0 1 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2 1 2 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2 0 2 5.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        factor_graph
+            .robust_kernels
+            .insert(FactorType::Odometry2D, RobustKernel::Dcs(1.0));
+        crate::optimizer::optimize(&factor_graph, 10).unwrap();
+        evaluate(&factor_graph).factors.iter().for_each(|factor| {
+            if factor.vertex_ids == vec![0, 2] {
+                assert!(factor.weight < 0.1, "outlier weight {}", factor.weight);
+            } else {
+                assert!(factor.weight > 0.9, "inlier weight {}", factor.weight);
+            }
+        });
+    }
+
+    #[test]
+    fn test_all_factor_types() {
+        init();
+        [
+            "data_files/full_demos/all_2d_types.g2o",
+            "data_files/full_demos/all_3d_types.g2o",
+        ]
+        .iter()
+        .for_each(|file_path| {
+            let factor_graph = G2oParser::parse_file(file_path).unwrap();
+            let evaluation = evaluate(&factor_graph);
+            assert_eq!(evaluation.factors.len(), factor_graph.csr.edge_count());
+            assert!(evaluation.factors.iter().all(|factor| factor.chi2 >= 0.0
+                && factor.mahalanobis_distance * factor.mahalanobis_distance - factor.chi2 < 1e-9));
+            assert!(approx::relative_eq!(evaluation.chi2, calculate_chi2(&factor_graph)));
+        });
+    }
+}
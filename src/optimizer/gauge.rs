@@ -0,0 +1,303 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Handling of gauge freedom, i.e. of parts of a factor graph which can be moved as a whole without changing
+//! the total chi², as they are neither anchored by a fixed variable nor by an absolute factor. H is singular then.
+
+#![allow(non_snake_case)]
+
+use crate::error::Error;
+use crate::factor_graph::factor::FactorType;
+use crate::factor_graph::variable::{FixedType, Variable};
+use crate::factor_graph::FactorGraph;
+use crate::optimizer::ordering::Permutation;
+use crate::optimizer::solver::Solver;
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::DVector;
+
+/// Handling of connected parts of a factor graph which are not anchored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GaugePolicy {
+    /// Keeps the first vehicle pose of each part at its current content, as if it was fixed.
+    FixFirstPose,
+    /// Adds the given information to the diagonal of H for the first vehicle pose of each part,
+    /// softly anchoring it at its current content in every iteration.
+    WeakPrior(f64),
+    /// Leaves H singular, which usually makes the solver fail.
+    Ignore,
+}
+
+impl Default for GaugePolicy {
+    fn default() -> Self {
+        GaugePolicy::FixFirstPose
+    }
+}
+
+/// Returns the internal CSR index of the first variable of each connected part of the factor graph which contains
+/// neither a fixed variable nor a Position2D, Position3D or linearized prior factor.
+///
+/// Vehicle poses are preferred over other variables. Parts only consisting of switch variables are ignored,
+/// as switch variables are anchored by their priors.
+pub fn find_unanchored_variables(factor_graph: &FactorGraph) -> Vec<usize> {
+    let mut parents: Vec<usize> = (0..factor_graph.node_indices.len()).collect();
+    let mut anchored: Vec<bool> = factor_graph
+        .node_indices
+        .iter()
+        .map(|i| factor_graph.get_var(*i).get_fixed_type() == &FixedType::Fixed)
+        .collect();
+    factor_graph.factors().for_each(|(factor, indices)| {
+        let root = find_root(&mut parents, indices[0]);
+        indices[1..].iter().for_each(|index| {
+            let other_root = find_root(&mut parents, *index);
+            parents[other_root] = root;
+            anchored[root] |= anchored[other_root];
+        });
+        if matches!(
+            factor.factor_type,
+            FactorType::Position2D | FactorType::Position3D | FactorType::LinearizedPrior
+        ) {
+            anchored[root] = true;
+        }
+    });
+
+    // vehicle poses are ranked first and switch variables last
+    let rank = |index: usize| match factor_graph.get_var(index) {
+        Variable::Vehicle2D(_) | Variable::Vehicle3D(_) => 0,
+        Variable::Switch(_) => 2,
+        _ => 1,
+    };
+    let mut first_variables: Vec<Option<usize>> = vec![None; parents.len()];
+    for index in &factor_graph.node_indices {
+        let root = find_root(&mut parents, *index);
+        first_variables[root] = match first_variables[root] {
+            Some(first) if rank(first) <= rank(*index) => Some(first),
+            _ => Some(*index),
+        };
+    }
+    let mut unanchored: Vec<usize> = first_variables
+        .iter()
+        .enumerate()
+        .filter(|(root, _)| !anchored[*root])
+        .filter_map(|(_, first)| *first)
+        .filter(|first| rank(*first) < 2)
+        .collect();
+    unanchored.sort_unstable();
+    unanchored
+}
+
+/// Returns the rows of H within the reordered linear system which are anchored according to the given policy,
+/// together with a warning for each connected part of the factor graph which is not anchored.
+pub fn get_anchored_rows(
+    factor_graph: &FactorGraph,
+    permutation: &Permutation,
+    policy: &GaugePolicy,
+) -> (Vec<usize>, Vec<String>) {
+    let mut rows = vec![];
+    let mut warnings = vec![];
+    for index in find_unanchored_variables(factor_graph) {
+        let var = factor_graph.get_var(index);
+        warnings.push(match policy {
+            GaugePolicy::FixFirstPose => format!(
+                "Variable {} is kept at its content, as its part of the factor graph is not anchored",
+                var.get_id()
+            ),
+            GaugePolicy::WeakPrior(_) => format!(
+                "Variable {} is anchored by a weak prior, as its part of the factor graph is not anchored",
+                var.get_id()
+            ),
+            GaugePolicy::Ignore => format!(
+                "The part of the factor graph containing variable {} is not anchored, so H is singular",
+                var.get_id()
+            ),
+        });
+        if let FixedType::NonFixed(range) = var.get_fixed_type() {
+            rows.extend(range.clone().map(|row| permutation.permute_index(row)));
+        }
+    }
+    (rows, warnings)
+}
+
+fn find_root(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    let mut current = index;
+    while parents[current] != root {
+        let next = parents[current];
+        parents[current] = root;
+        current = next;
+    }
+    root
+}
+
+/// Solver anchoring the given rows of H according to the gauge policy before solving with an inner solver.
+pub struct GaugeSolver<'a> {
+    inner: &'a mut dyn Solver,
+    rows: Vec<usize>,
+    policy: GaugePolicy,
+}
+
+impl<'a> GaugeSolver<'a> {
+    /// Returns a solver anchoring the given rows of the linear systems passed to it.
+    pub fn new(inner: &'a mut dyn Solver, rows: Vec<usize>, policy: GaugePolicy) -> Self {
+        GaugeSolver { inner, rows, policy }
+    }
+}
+
+impl<'a> Solver for GaugeSolver<'a> {
+    fn solve(&mut self, H: &SparseMatrix, b: &DVector<f64>) -> Result<Vec<f64>, Error> {
+        if self.rows.is_empty() {
+            return self.inner.solve(H, b);
+        }
+        match self.policy {
+            GaugePolicy::FixFirstPose => {
+                // replacing the anchored rows and columns by those of the identity keeps their solution at zero
+                let mut is_anchored = vec![false; H.dim()];
+                self.rows.iter().for_each(|row| is_anchored[*row] = true);
+                let mut anchored_H = SparseMatrix::new(H.dim());
+                H.entries()
+                    .filter(|(row, col, _)| !is_anchored[*row] && !is_anchored[*col])
+                    .for_each(|(row, col, value)| anchored_H.add(row, col, value));
+                self.rows.iter().for_each(|row| anchored_H.add(*row, *row, 1.0));
+                anchored_H.set_blocks(H.blocks().to_vec());
+                let mut anchored_b = b.clone();
+                self.rows.iter().for_each(|row| anchored_b[*row] = 0.0);
+                self.inner.solve(&anchored_H, &anchored_b)
+            }
+            GaugePolicy::WeakPrior(information) => {
+                let mut anchored_H = H.clone();
+                self.rows.iter().for_each(|row| anchored_H.add(*row, *row, information));
+                self.inner.solve(&anchored_H, b)
+            }
+            GaugePolicy::Ignore => self.inner.solve(H, b),
+        }
+    }
+
+    fn residual_norm(&self) -> Option<f64> {
+        self.inner.residual_norm()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::evaluation::calculate_chi2;
+    use crate::optimizer::levenberg_marquardt::LevenbergMarquardtParams;
+    use crate::optimizer::{optimize, optimize_with_settings, Algorithm, OptimizerSettings};
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+    use log::LevelFilter;
+    use std::convert::TryInto;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    fn parse_without_fixed_vertices(file_path: &str) -> FactorGraph {
+        let mut model = G2oParser::parse_file_to_model(file_path).unwrap();
+        model.fixed_vertices.clear();
+        model.try_into().unwrap()
+    }
+
+    #[test]
+    fn test_find_unanchored_variables() {
+        init();
+        let factor_graph: FactorGraph = G2oParser::parse_string_to_model(
+            "VERTEX_XY 0 1.0 1.0\n\
+             VERTEX_SE2 1 0.0 0.0 0.0\n\
+             VERTEX_SE2 2 1.0 0.0 0.0\n\
+             VERTEX_SE2 3 0.0 0.0 0.0\n\
+             VERTEX_SE2 4 1.0 0.0 0.0\n\
+             FIX 3\n\
+             VERTEX_SE2 5 0.0 0.0 0.0\n\
+             VERTEX_XY 6 1.0 1.0\n\
+             VERTEX_XY 7 1.0 1.0\n\
+             EDGE_SE2_XY 1 0 1.0 1.0 1.0 0.0 1.0\n\
+             EDGE_SE2 1 2 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2 3 4 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_PRIOR_SE2 5 0.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2_XY 5 6 1.0 1.0 1.0 0.0 1.0\n",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let ids: Vec<usize> = find_unanchored_variables(&factor_graph)
+            .iter()
+            .map(|index| factor_graph.get_var(*index).get_id())
+            .collect();
+        assert_eq!(ids, vec![1, 7]);
+    }
+
+    #[test]
+    fn test_fix_first_pose() {
+        init();
+        ["odo2d_only_0", "odo3d_only_0"].iter().for_each(|file_name| {
+            let file_path = ["data_files/optimizer_tests/", file_name, ".g2o"].concat();
+            let fixed_graph = G2oParser::parse_file(&file_path).unwrap();
+            let factor_graph = parse_without_fixed_vertices(&file_path);
+            assert!(optimize(&fixed_graph, 5).unwrap().warnings.is_empty());
+            let report = optimize(&factor_graph, 5).unwrap();
+            assert_eq!(report.warnings.len(), 1);
+            assert_eq!(report.iterations.len(), 5);
+            assert_eq!(report.final_chi2, calculate_chi2(&fixed_graph));
+        });
+    }
+
+    #[test]
+    fn test_weak_prior() {
+        init();
+        let file_path = "data_files/optimizer_tests/obs2d_mainly_0.g2o";
+        let fixed_graph = G2oParser::parse_file(file_path).unwrap();
+        let factor_graph = parse_without_fixed_vertices(file_path);
+        let settings = OptimizerSettings {
+            iterations: 5,
+            gauge_policy: GaugePolicy::WeakPrior(1e-6),
+            ..Default::default()
+        };
+        let fixed_report = optimize_with_settings(&fixed_graph, &settings).unwrap();
+        let report = optimize_with_settings(&factor_graph, &settings).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(approx::relative_eq!(
+            report.final_chi2,
+            fixed_report.final_chi2,
+            max_relative = 1e-6
+        ));
+    }
+
+    #[test]
+    fn test_ignore() {
+        init();
+        let factor_graph = parse_without_fixed_vertices("data_files/optimizer_tests/odo2d_only_0.g2o");
+        let settings = OptimizerSettings {
+            gauge_policy: GaugePolicy::Ignore,
+            ..Default::default()
+        };
+        let initial_chi2 = calculate_chi2(&factor_graph);
+        assert!(matches!(
+            optimize_with_settings(&factor_graph, &settings),
+            Err(Error::SolverFailure(_))
+        ));
+        assert_eq!(calculate_chi2(&factor_graph), initial_chi2);
+
+        let settings = OptimizerSettings {
+            algorithm: Algorithm::LevenbergMarquardt(LevenbergMarquardtParams::default()),
+            ..settings
+        };
+        let report = optimize_with_settings(&factor_graph, &settings).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.final_chi2 < initial_chi2);
+    }
+}
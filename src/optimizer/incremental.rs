@@ -0,0 +1,537 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Incremental smoothing for online SLAM, updating the optimization result whenever new measurements arrive.
+//!
+//! Follows the ideas of iSAM2: The Cholesky factor of H is kept between updates and only recomputed for the
+//! variables affected by new factors or relinearization. Variables are only relinearized once their update exceeds
+//! a threshold, so that the linear system of all other variables stays unchanged. Only the factors of new and
+//! relinearized variables are linearized again, while the contributions of all other factors to H and b are reused.
+
+#![allow(non_snake_case)]
+
+use crate::error::Error;
+use crate::factor_graph::factor::Factor;
+use crate::factor_graph::variable::{FixedType, Variable};
+use crate::factor_graph::FactorGraph;
+use crate::optimizer::linear_system::update_H_b;
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use crate::optimizer::{apply_solution, update_var};
+use nalgebra::DVector;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+
+/// Settings of the incremental optimizer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncrementalSettings {
+    /// Variables are relinearized once the absolute value of an entry of their update reaches this threshold.
+    pub relinearize_threshold: f64,
+    /// Variables are only checked for relinearization in every n-th update.
+    pub relinearize_skip: usize,
+}
+
+impl Default for IncrementalSettings {
+    fn default() -> Self {
+        IncrementalSettings {
+            relinearize_threshold: 0.1,
+            relinearize_skip: 10,
+        }
+    }
+}
+
+/// Optimizer updating its estimate incrementally as variables and factors are added to its factor graph.
+///
+/// The variables of the factor graph contain the linearization point, whereas the current estimate is the
+/// linearization point updated by the solution of the linear system.
+pub struct IncrementalOptimizer {
+    factor_graph: FactorGraph,
+    settings: IncrementalSettings,
+    factorization: Factorization,
+    /// The contributions of all factors to H and b.
+    linearized_factors: HashMap<FactorKey, LinearizedFactor>,
+    delta: DVector<f64>,
+    updates_since_relinearization: usize,
+    refactorized: Range<usize>,
+}
+
+impl IncrementalOptimizer {
+    /// Returns an optimizer starting with the given factor graph, which may be empty.
+    pub fn new(factor_graph: FactorGraph, settings: IncrementalSettings) -> Self {
+        let delta = DVector::zeros(factor_graph.matrix_dim);
+        IncrementalOptimizer {
+            factor_graph,
+            settings,
+            factorization: Factorization::default(),
+            linearized_factors: HashMap::new(),
+            delta,
+            updates_since_relinearization: 0,
+            refactorized: 0..0,
+        }
+    }
+
+    /// Adds the given factors and variables and returns the updated estimate of all variables by their custom IDs.
+    ///
+    /// The factors are given together with the custom IDs of their variables as in FactorGraph::add_factor().
+    /// New variables are linearized at their given contents. Variables and factors added before an error occurred
+    /// are kept.
+    pub fn update(
+        &mut self,
+        new_factors: Vec<(Factor, Vec<usize>)>,
+        new_variables: Vec<Variable>,
+    ) -> Result<HashMap<usize, Vec<f64>>, Error> {
+        self.updates_since_relinearization += 1;
+        let relinearized = if self.updates_since_relinearization >= self.settings.relinearize_skip {
+            self.updates_since_relinearization = 0;
+            self.relinearize()
+        } else {
+            HashSet::new()
+        };
+        let added = self.add(new_factors, new_variables);
+        // new variables follow all other variables in H and start at their linearization point
+        self.delta.resize_vertically_mut(self.factor_graph.matrix_dim, 0.0);
+        added?;
+
+        let (H, b) = self.calculate_H_b(&relinearized);
+        let first_column = self.factorization.update(get_lower_columns(&H))?;
+        self.refactorized = first_column..H.dim();
+        self.delta = self.factorization.solve(&(b * -1.0));
+        Ok(self.estimate())
+    }
+
+    /// Returns the current estimate of all variables by their custom IDs.
+    pub fn estimate(&self) -> HashMap<usize, Vec<f64>> {
+        self.factor_graph
+            .node_indices
+            .iter()
+            .map(|i| {
+                let var = self.factor_graph.get_var(*i).clone();
+                update_var(&var, self.delta.as_slice());
+                (var.get_id(), var.get_content())
+            })
+            .collect()
+    }
+
+    /// Returns the rows of H whose columns of the Cholesky factor were recomputed during the last update.
+    pub fn refactorized_rows(&self) -> Range<usize> {
+        self.refactorized.clone()
+    }
+
+    /// Returns the factor graph with its variables at the linearization point.
+    pub fn factor_graph(&self) -> &FactorGraph {
+        &self.factor_graph
+    }
+
+    /// Returns the factor graph with its variables at the current estimate.
+    pub fn into_factor_graph(self) -> FactorGraph {
+        apply_solution(&self.factor_graph, self.delta.as_slice());
+        self.factor_graph
+    }
+
+    /// Adds the given variables and factors to the factor graph, stopping at the first error.
+    fn add(&mut self, new_factors: Vec<(Factor, Vec<usize>)>, new_variables: Vec<Variable>) -> Result<(), Error> {
+        for variable in new_variables {
+            self.factor_graph.add_variable(variable)?;
+        }
+        for (factor, variable_ids) in new_factors {
+            self.factor_graph.add_factor(factor, &variable_ids)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the linearization point of all variables whose update reaches the threshold to their current estimate.
+    /// Returns the internal CSR indices of these variables.
+    fn relinearize(&mut self) -> HashSet<usize> {
+        let threshold = self.settings.relinearize_threshold;
+        let delta = &self.delta;
+        let factor_graph = &self.factor_graph;
+        factor_graph
+            .node_indices
+            .iter()
+            .copied()
+            .filter(|i| {
+                let var = factor_graph.get_var(*i);
+                let relinearize = match var.get_fixed_type() {
+                    FixedType::NonFixed(range) => delta.rows_range(range.clone()).amax() >= threshold,
+                    FixedType::Fixed => false,
+                };
+                if relinearize {
+                    update_var(var, delta.as_slice());
+                }
+                relinearize
+            })
+            .collect()
+    }
+
+    /// Returns H and b at the linearization point. Only new factors and factors of the given relinearized variables
+    /// are linearized, the contributions of all other factors are reused from previous updates.
+    fn calculate_H_b(&mut self, relinearized: &HashSet<usize>) -> (SparseMatrix, DVector<f64>) {
+        let factor_graph = &self.factor_graph;
+        let dim = factor_graph.matrix_dim;
+        let mut H = SparseMatrix::new(dim);
+        let mut b = DVector::zeros(dim);
+        let mut factor_b = DVector::zeros(dim);
+        let edge_count = factor_graph.csr.edge_count();
+        for (position, (factor, indices)) in factor_graph.factors().enumerate() {
+            // factors of the CSR representation are unique per source and target, linearized priors are only appended
+            let key = if position < edge_count {
+                FactorKey::Edge(indices[0], *indices.get(1).unwrap_or(&indices[0]))
+            } else {
+                FactorKey::Prior(position - edge_count)
+            };
+            let is_relinearized = indices.iter().any(|i| relinearized.contains(i));
+            if is_relinearized || !self.linearized_factors.contains_key(&key) {
+                let mut factor_H = SparseMatrix::new(dim);
+                update_H_b(factor_graph, &mut factor_H, &mut factor_b, factor, &indices);
+                let rows: BTreeSet<usize> = factor_H.entries().map(|(row, _, _)| row).collect();
+                let b_entries = rows
+                    .into_iter()
+                    .map(|row| (row, std::mem::replace(&mut factor_b[row], 0.0)))
+                    .collect();
+                self.linearized_factors.insert(
+                    key,
+                    LinearizedFactor {
+                        H_entries: factor_H.entries().collect(),
+                        b_entries,
+                    },
+                );
+            }
+            let linearized_factor = &self.linearized_factors[&key];
+            linearized_factor
+                .H_entries
+                .iter()
+                .for_each(|(row, col, value)| H.add(*row, *col, *value));
+            linearized_factor
+                .b_entries
+                .iter()
+                .for_each(|(row, value)| b[*row] += value);
+        }
+        (H, b)
+    }
+}
+
+/// Identifies a factor by the internal CSR indices of its source and target, or a linearized prior by its position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum FactorKey {
+    Edge(usize, usize),
+    Prior(usize),
+}
+
+/// Contribution of a single factor to H and b, kept until one of its variables is relinearized.
+#[derive(Debug)]
+struct LinearizedFactor {
+    H_entries: Vec<(usize, usize, f64)>,
+    b_entries: Vec<(usize, f64)>,
+}
+
+/// Sparse Cholesky factor L of H = L * Lᵀ, which can be recomputed starting from any column.
+///
+/// If H only changes from a column onwards, the columns of L before it stay unchanged.
+#[derive(Debug, Default)]
+struct Factorization {
+    /// The lower triangle of the factorized H, column by column with ascending rows.
+    H_columns: Vec<Vec<(usize, f64)>>,
+    /// The columns of L with ascending rows, each starting with its diagonal entry.
+    L_columns: Vec<Vec<(usize, f64)>>,
+    /// For every row of L, the columns left of the diagonal with an entry in that row in ascending order.
+    L_rows: Vec<Vec<usize>>,
+}
+
+impl Factorization {
+    /// Factorizes the given lower triangle of H, only recomputing the columns starting with the first column which
+    /// differs from the previously factorized H. Returns that column.
+    fn update(&mut self, H_columns: Vec<Vec<(usize, f64)>>) -> Result<usize, Error> {
+        let dim = H_columns.len();
+        let first = (0..dim)
+            .find(|j| self.H_columns.get(*j) != Some(&H_columns[*j]))
+            .unwrap_or(dim);
+        self.H_columns = H_columns;
+        self.L_columns.truncate(first);
+        self.L_rows.resize(dim, vec![]);
+        self.L_rows[first..]
+            .iter_mut()
+            .for_each(|row| row.retain(|column| *column < first));
+
+        let mut values = vec![0.0; dim];
+        let mut is_nonzero = vec![false; dim];
+        for j in first..dim {
+            let mut pattern = vec![];
+            for (row, value) in &self.H_columns[j] {
+                values[*row] += value;
+                if !is_nonzero[*row] {
+                    is_nonzero[*row] = true;
+                    pattern.push(*row);
+                }
+            }
+            for k in 0..self.L_rows[j].len() {
+                let column = &self.L_columns[self.L_rows[j][k]];
+                let start = column.binary_search_by_key(&j, |(row, _)| *row).unwrap();
+                let factor = column[start].1;
+                for (row, value) in &column[start..] {
+                    values[*row] -= value * factor;
+                    if !is_nonzero[*row] {
+                        is_nonzero[*row] = true;
+                        pattern.push(*row);
+                    }
+                }
+            }
+            pattern.sort_unstable();
+            let diagonal = values[j];
+            if diagonal.is_nan() || diagonal <= 0.0 {
+                *self = Factorization::default();
+                return Err(Error::SolverFailure(String::from("H is not positive-definite")));
+            }
+            let diagonal = diagonal.sqrt();
+            let column = pattern
+                .iter()
+                .map(|row| {
+                    let entry = (*row, values[*row] / diagonal);
+                    values[*row] = 0.0;
+                    is_nonzero[*row] = false;
+                    entry
+                })
+                .collect::<Vec<(usize, f64)>>();
+            column[1..].iter().for_each(|(row, _)| self.L_rows[*row].push(j));
+            self.L_columns.push(column);
+        }
+        Ok(first)
+    }
+
+    /// Returns the solution x of H * x = b.
+    fn solve(&self, b: &DVector<f64>) -> DVector<f64> {
+        let mut x = b.clone();
+        for (j, column) in self.L_columns.iter().enumerate() {
+            x[j] /= column[0].1;
+            column[1..].iter().for_each(|(row, value)| x[*row] -= value * x[j]);
+        }
+        for (j, column) in self.L_columns.iter().enumerate().rev() {
+            x[j] -= column[1..].iter().map(|(row, value)| value * x[*row]).sum::<f64>();
+            x[j] /= column[0].1;
+        }
+        x
+    }
+}
+
+/// Returns the lower triangle of H column by column, summing up entries at identical positions.
+fn get_lower_columns(H: &SparseMatrix) -> Vec<Vec<(usize, f64)>> {
+    let mut columns: Vec<Vec<(usize, f64)>> = vec![vec![]; H.dim()];
+    H.entries()
+        .filter(|(row, col, _)| row >= col)
+        .for_each(|(row, col, value)| columns[col].push((row, value)));
+    columns.iter_mut().for_each(|column| {
+        column.sort_by_key(|(row, _)| *row);
+        column.dedup_by(|(row, value), (previous_row, previous_value)| {
+            let duplicate = row == previous_row;
+            if duplicate {
+                *previous_value += *value;
+            }
+            duplicate
+        });
+    });
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factor_graph::factor::{FactorType, InformationMatrix};
+    use crate::factor_graph::variable::VehicleVariable2D;
+    use crate::optimizer::evaluation::calculate_chi2;
+    use crate::optimizer::linear_system::calculate_H_b;
+    use crate::optimizer::optimize;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+    use log::LevelFilter;
+    use nalgebra::DMatrix;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    type StreamItem = (Variable, Vec<(Factor, Vec<usize>)>);
+
+    /// Returns the variables of the given factor graph in their order, each together with all factors
+    /// whose variables have been added once the variable is added.
+    fn get_stream(factor_graph: &FactorGraph) -> Vec<StreamItem> {
+        let mut stream: Vec<StreamItem> = factor_graph
+            .node_indices
+            .iter()
+            .map(|i| (factor_graph.get_var(*i).clone(), vec![]))
+            .collect();
+        factor_graph.factors().for_each(|(factor, indices)| {
+            let ids = indices.iter().map(|i| factor_graph.get_var(*i).get_id()).collect();
+            stream[*indices.iter().max().unwrap()].1.push((factor.clone(), ids));
+        });
+        stream
+    }
+
+    fn optimize_incrementally(file_path: &str, settings: IncrementalSettings) -> IncrementalOptimizer {
+        let factor_graph = G2oParser::parse_file(file_path).unwrap();
+        let mut optimizer = IncrementalOptimizer::new(FactorGraph::default(), settings);
+        for (variable, factors) in get_stream(&factor_graph) {
+            optimizer.update(factors, vec![variable]).unwrap();
+        }
+        optimizer
+    }
+
+    /// Relinearizing all variables in every update turns the incremental optimizer into Gauss-Newton, so it reaches
+    /// the same estimate as the batch optimizer once both have converged. For 2D graphs with odometry, the batch
+    /// optimizer starting from all contents of the file converges to a different local minimum, see
+    /// test_close_to_batch_optimization().
+    #[test]
+    fn test_equal_to_batch_optimization() {
+        init();
+        let settings = IncrementalSettings {
+            relinearize_threshold: 0.0,
+            relinearize_skip: 1,
+        };
+        [
+            "data_files/optimizer_tests/obs2d_mainly_0.g2o",
+            "data_files/optimizer_tests/obs3d_mainly_0.g2o",
+            "data_files/optimizer_tests/odo3d_only_0.g2o",
+            "data_files/optimizer_tests/pos2d_only_0.g2o",
+            "data_files/optimizer_tests/pos3d_only_0.g2o",
+        ]
+        .iter()
+        .for_each(|file_path| {
+            let mut optimizer = optimize_incrementally(file_path, settings.clone());
+            (0..100).for_each(|_| {
+                optimizer.update(vec![], vec![]).unwrap();
+            });
+            let estimate = optimizer.estimate();
+            let batch_graph = G2oParser::parse_file(file_path).unwrap();
+            optimize(&batch_graph, 100).unwrap();
+            batch_graph.node_indices.iter().for_each(|i| {
+                let var = batch_graph.get_var(*i);
+                estimate[&var.get_id()]
+                    .iter()
+                    .zip(var.get_content().iter())
+                    .for_each(|(incremental, batch)| {
+                        assert!(
+                            approx::relative_eq!(incremental, batch, epsilon = 1e-9),
+                            "{}: variable {} is {:?} instead of {:?}",
+                            file_path,
+                            var.get_id(),
+                            estimate[&var.get_id()],
+                            var.get_content()
+                        )
+                    });
+            });
+        });
+    }
+
+    /// Optimizing the variables as they arrive avoids the local minimum the batch optimizer runs into for 2D graphs
+    /// with odometry, so only the total chi² is compared. It is at most slightly above the batch optimizer's even if
+    /// variables are only relinearized occasionally.
+    #[test]
+    fn test_close_to_batch_optimization() {
+        init();
+        [
+            "data_files/optimizer_tests/full2d_0.g2o",
+            "data_files/optimizer_tests/odo2d_only_0.g2o",
+        ]
+        .iter()
+        .for_each(|file_path| {
+            let mut optimizer = optimize_incrementally(file_path, IncrementalSettings::default());
+            let refactorized_rows = optimizer.refactorized_rows();
+            assert!(refactorized_rows.start > 0 && refactorized_rows.len() < refactorized_rows.end / 2);
+            (0..20).for_each(|_| {
+                optimizer.update(vec![], vec![]).unwrap();
+            });
+            let incremental_chi2 = calculate_chi2(&optimizer.into_factor_graph());
+
+            let batch_graph = G2oParser::parse_file(file_path).unwrap();
+            optimize(&batch_graph, 10).unwrap();
+            let batch_chi2 = calculate_chi2(&batch_graph);
+            assert!(
+                incremental_chi2 < 1.01 * batch_chi2,
+                "{}: incremental chi2 {} exceeds batch chi2 {}",
+                file_path,
+                incremental_chi2,
+                batch_chi2
+            );
+        });
+    }
+
+    #[test]
+    fn test_start_from_non_empty_graph() {
+        init();
+        let factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+        let settings = IncrementalSettings {
+            relinearize_skip: 1,
+            ..IncrementalSettings::default()
+        };
+        let mut optimizer = IncrementalOptimizer::new(factor_graph, settings);
+        (0..3).for_each(|_| {
+            optimizer.update(vec![], vec![]).unwrap();
+        });
+
+        let new_variable =
+            |id| Variable::Vehicle2D(VehicleVariable2D::new(id, 1.0, 2.0, 0.0, FixedType::NonFixed(0..0)));
+        assert!(optimizer
+            .update(vec![], vec![new_variable(100), new_variable(0)])
+            .is_err());
+        assert_eq!(optimizer.estimate()[&100], vec![1.0, 2.0, 0.0]);
+        let prior = Factor {
+            factor_type: FactorType::Position2D,
+            constraint: vec![1.0, 2.0, 0.0],
+            information_matrix: InformationMatrix {
+                content: DMatrix::identity(3, 3),
+            },
+            robust_kernel: None,
+            switch_index: None,
+            prior_indices: vec![],
+        };
+        let estimate = optimizer.update(vec![(prior, vec![100])], vec![]).unwrap();
+        assert!(approx::relative_eq!(estimate[&100][0], 1.0, epsilon = 1e-9));
+    }
+
+    #[test]
+    fn test_reuses_linearized_factors() {
+        init();
+        let file_path = "data_files/optimizer_tests/odo2d_only_0.g2o";
+        let mut optimizer = optimize_incrementally(file_path, IncrementalSettings::default());
+        let (H, b) = optimizer.calculate_H_b(&HashSet::new());
+        let (expected_H, expected_b) = calculate_H_b(optimizer.factor_graph());
+        assert_eq!(H.to_dense(), expected_H.to_dense());
+        assert_eq!(b, expected_b);
+    }
+
+    #[test]
+    fn test_partial_refactorization() {
+        init();
+        let mut factorization = Factorization::default();
+        #[rustfmt::skip]
+        let H = vec![
+            vec![(0, 4.0), (1, 2.0)],
+            vec![(1, 5.0), (2, 1.0)],
+            vec![(2, 3.0)],
+        ];
+        assert_eq!(factorization.update(H.clone()).unwrap(), 0);
+        let b = DVector::from_vec(vec![1.0, 2.0, 3.0]);
+        let x = factorization.solve(&b);
+
+        let mut changed_H = H.clone();
+        changed_H[2][0].1 = 4.0;
+        assert_eq!(factorization.update(changed_H.clone()).unwrap(), 2);
+        let mut fresh_factorization = Factorization::default();
+        fresh_factorization.update(changed_H).unwrap();
+        assert_eq!(factorization.L_columns, fresh_factorization.L_columns);
+        assert_ne!(factorization.solve(&b), x);
+
+        let mut not_positive_definite_H = H;
+        not_positive_definite_H[1][0].1 = 1.0;
+        assert!(factorization.update(not_positive_definite_H).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Initialization of variable contents from relative measurements, giving the optimizer a starting point close to
+//! the solution if the contents stated in a file are inaccurate, e.g. all zeros.
+
+use crate::factor_graph::factor::{Factor, FactorType};
+use crate::factor_graph::variable::{FixedType, Variable};
+use crate::factor_graph::FactorGraph;
+use crate::optimizer::linear_system::iso3d_gradients::get_isometry;
+use nalgebra::{Isometry3, Point3, Rotation2, Vector2};
+use petgraph::visit::EdgeRef;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Sets the contents of all non-fixed vehicle and landmark variables by composing measurements along a spanning tree
+/// of the factor graph.
+///
+/// The spanning tree is built breadth-first over Odometry2D, Odometry3D, Observation2D and Observation3D factors.
+/// It starts at all fixed variables and at all variables with a Position2D or Position3D factor, which are set to the
+/// measured pose. Odometry factors are followed in both directions, observation factors only from the vehicle to the
+/// landmark. Switchable odometry factors are not followed, as they may be outliers. Each connected part without such
+/// a starting variable is started at its first vehicle pose, which keeps its content.
+///
+/// Returns the custom IDs of the landmark variables which are not observed and therefore keep their content.
+pub fn initialize_from_measurements(factor_graph: &FactorGraph) -> Vec<usize> {
+    let variable_count = factor_graph.node_indices.len();
+    let is_fixed = |index: usize| factor_graph.get_var(index).get_fixed_type() == &FixedType::Fixed;
+    let mut visited: Vec<bool> = (0..variable_count).map(is_fixed).collect();
+    let mut queue: VecDeque<usize> = (0..variable_count).filter(|index| visited[*index]).collect();
+
+    // neighbors are stored with the connecting factor and whether it is followed from its source to its target
+    let mut neighbors: Vec<Vec<(usize, &Factor, bool)>> = vec![vec![]; variable_count];
+    for edge in factor_graph
+        .node_indices
+        .iter()
+        .flat_map(|index| factor_graph.csr.edges(*index))
+    {
+        let factor = edge.weight();
+        match factor.factor_type {
+            FactorType::Odometry2D | FactorType::Odometry3D => {
+                neighbors[edge.source()].push((edge.target(), factor, true));
+                neighbors[edge.target()].push((edge.source(), factor, false));
+            }
+            FactorType::Observation2D | FactorType::Observation3D => {
+                neighbors[edge.source()].push((edge.target(), factor, true));
+            }
+            FactorType::Position2D | FactorType::Position3D if !visited[edge.source()] => {
+                factor_graph
+                    .get_var(edge.source())
+                    .set_content(normalize_pose(&factor.constraint));
+                visited[edge.source()] = true;
+                queue.push_back(edge.source());
+            }
+            _ => {}
+        }
+    }
+
+    loop {
+        while let Some(index) = queue.pop_front() {
+            let content = factor_graph.get_var(index).get_content();
+            for (neighbor, factor, forward) in &neighbors[index] {
+                if !visited[*neighbor] {
+                    visited[*neighbor] = true;
+                    factor_graph
+                        .get_var(*neighbor)
+                        .set_content(compose(&content, factor, *forward));
+                    queue.push_back(*neighbor);
+                }
+            }
+        }
+        let next_root = (0..variable_count).find(|index| {
+            !visited[*index]
+                && matches!(
+                    factor_graph.get_var(*index),
+                    Variable::Vehicle2D(_) | Variable::Vehicle3D(_)
+                )
+        });
+        match next_root {
+            Some(root) => {
+                visited[root] = true;
+                queue.push_back(root);
+            }
+            None => break,
+        }
+    }
+
+    (0..variable_count)
+        .filter(|index| !visited[*index])
+        .map(|index| factor_graph.get_var(index))
+        .filter(|var| matches!(var, Variable::Landmark2D(_) | Variable::Landmark3D(_)))
+        .map(|var| var.get_id())
+        .collect()
+}
+
+/// Returns the content of the variable at the other end of the factor, given the content of the variable it is
+/// followed from. If it is not followed forward, the given content is the target's and the source's is returned.
+fn compose(content: &[f64], factor: &Factor, forward: bool) -> Vec<f64> {
+    let measurement = &factor.constraint;
+    match factor.factor_type {
+        FactorType::Odometry2D | FactorType::Observation2D => {
+            let position = Vector2::new(content[0], content[1]);
+            let measured_position = Vector2::new(measurement[0], measurement[1]);
+            if factor.factor_type == FactorType::Observation2D {
+                let landmark = position + Rotation2::new(content[2]) * measured_position;
+                return vec![landmark.x, landmark.y];
+            }
+            let rotation = if forward {
+                content[2] + measurement[2]
+            } else {
+                content[2] - measurement[2]
+            };
+            let position = if forward {
+                position + Rotation2::new(content[2]) * measured_position
+            } else {
+                position - Rotation2::new(rotation) * measured_position
+            };
+            vec![position.x, position.y, normalize_angle(rotation)]
+        }
+        FactorType::Odometry3D => {
+            let measured_isometry = get_isometry(measurement);
+            let isometry = if forward {
+                get_isometry(content) * measured_isometry
+            } else {
+                get_isometry(content) * measured_isometry.inverse()
+            };
+            get_pose_3d(&isometry)
+        }
+        FactorType::Observation3D => {
+            let landmark = get_isometry(content) * Point3::new(measurement[0], measurement[1], measurement[2]);
+            vec![landmark.x, landmark.y, landmark.z]
+        }
+        _ => unreachable!("Only odometry and observation factors are followed"),
+    }
+}
+
+/// Returns the given 2D or 3D pose with a normalized rotation angle or quaternion.
+fn normalize_pose(pose: &[f64]) -> Vec<f64> {
+    if pose.len() == 3 {
+        vec![pose[0], pose[1], normalize_angle(pose[2])]
+    } else {
+        get_pose_3d(&get_isometry(pose))
+    }
+}
+
+fn normalize_angle(angle: f64) -> f64 {
+    let angle = angle % (2.0 * PI);
+    if angle > PI {
+        angle - 2.0 * PI
+    } else if angle < -PI {
+        angle + 2.0 * PI
+    } else {
+        angle
+    }
+}
+
+fn get_pose_3d(isometry: &Isometry3<f64>) -> Vec<f64> {
+    let translation = isometry.translation.vector.iter();
+    translation
+        .chain(isometry.rotation.quaternion().coords.iter())
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::evaluation::calculate_chi2;
+    use crate::optimizer::optimize;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+    use log::LevelFilter;
+    use std::convert::TryFrom;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    fn parse(s: &str) -> FactorGraph {
+        FactorGraph::try_from(G2oParser::parse_string_to_model(s).unwrap()).unwrap()
+    }
+
+    fn get_content(factor_graph: &FactorGraph, id: usize) -> Vec<f64> {
+        factor_graph
+            .get_var(factor_graph.custom_to_csr_id_map[&id])
+            .get_content()
+    }
+
+    fn assert_content_eq(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        actual.iter().zip(expected.iter()).for_each(|(a, e)| {
+            assert!(
+                approx::abs_diff_eq!(a, e, epsilon = 1e-9),
+                "{:?} != {:?}",
+                actual,
+                expected
+            )
+        });
+    }
+
+    #[test]
+    fn test_initialize_2d() {
+        init();
+        let factor_graph = parse(
+            "VERTEX_SE2 0 1.0 2.0 1.5707963267948966\n\
+             FIX 0\n\
+             VERTEX_SE2 1 0.0 0.0 0.0\n\
+             VERTEX_SE2 2 0.0 0.0 0.0\n\
+             VERTEX_XY 3 0.0 0.0\n\
+             VERTEX_XY 4 0.0 0.0\n\
+             EDGE_SE2 0 1 1.0 0.0 1.5707963267948966 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2 2 1 0.0 2.0 -1.5707963267948966 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2_XY 2 3 1.0 0.0 1.0 0.0 1.0\n",
+        );
+        assert_eq!(initialize_from_measurements(&factor_graph), vec![4]);
+        assert_content_eq(&get_content(&factor_graph, 0), &[1.0, 2.0, PI / 2.0]);
+        assert_content_eq(&get_content(&factor_graph, 1), &[1.0, 3.0, PI]);
+        assert_content_eq(&get_content(&factor_graph, 2), &[-1.0, 3.0, -PI / 2.0]);
+        assert_content_eq(&get_content(&factor_graph, 3), &[-1.0, 2.0]);
+        assert_content_eq(&get_content(&factor_graph, 4), &[0.0, 0.0]);
+        assert!(calculate_chi2(&factor_graph) < 1e-18);
+    }
+
+    #[test]
+    fn test_initialize_3d() {
+        init();
+        let factor_graph = parse(
+            "VERTEX_SE3:QUAT 0 0.0 0.0 0.0 0.0 0.0 0.0 1.0\n\
+             VERTEX_SE3:QUAT 1 0.0 0.0 0.0 0.0 0.0 0.0 1.0\n\
+             VERTEX_SE3:QUAT 2 0.0 0.0 0.0 0.0 0.0 0.0 1.0\n\
+             VERTEX_TRACKXYZ 3 0.0 0.0 0.0\n\
+             EDGE_SE3_PRIOR 1 0 1.0 2.0 3.0 0.0 0.0 0.7071067811865476 0.7071067811865476 \
+             1.0 0.0 0.0 0.0 0.0 0.0 1.0 0.0 0.0 0.0 0.0 1.0 0.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE3:QUAT 0 1 1.0 0.0 0.0 0.0 0.0 0.7071067811865476 0.7071067811865476 \
+             1.0 0.0 0.0 0.0 0.0 0.0 1.0 0.0 0.0 0.0 0.0 1.0 0.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE3:QUAT 1 2 0.0 0.0 1.0 0.7071067811865476 0.0 0.0 0.7071067811865476 \
+             1.0 0.0 0.0 0.0 0.0 0.0 1.0 0.0 0.0 0.0 0.0 1.0 0.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE3_TRACKXYZ 2 3 0 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n",
+        );
+        assert!(initialize_from_measurements(&factor_graph).is_empty());
+        let s = 0.5f64.sqrt();
+        assert_content_eq(&get_content(&factor_graph, 0), &[0.0, 2.0, 3.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_content_eq(&get_content(&factor_graph, 1), &[1.0, 2.0, 3.0, 0.0, 0.0, s, s]);
+        assert_content_eq(&get_content(&factor_graph, 2), &[1.0, 2.0, 4.0, 0.5, 0.5, 0.5, 0.5]);
+        assert_content_eq(&get_content(&factor_graph, 3), &[1.0, 3.0, 4.0]);
+        assert!(calculate_chi2(&factor_graph) < 1e-18);
+    }
+
+    #[test]
+    fn test_initialize_files() {
+        init();
+        ["odo2d_only_0", "full2d_0", "odo3d_only_0", "obs3d_mainly_0"]
+            .iter()
+            .for_each(|file_name| {
+                let file_path = ["data_files/optimizer_tests/", file_name, ".g2o"].concat();
+                let expected_graph = G2oParser::parse_file(&file_path).unwrap();
+                let mut model = G2oParser::parse_file_to_model(&file_path).unwrap();
+                for vertex in model.vertices.iter_mut() {
+                    if !model.fixed_vertices.contains(&vertex.id) {
+                        let rotation_w = if vertex.vertex_type == "Vehicle3D" {
+                            vec![1.0]
+                        } else {
+                            vec![]
+                        };
+                        vertex.content = vec![0.0; vertex.content.len() - rotation_w.len()];
+                        vertex.content.extend(rotation_w);
+                    }
+                }
+                let factor_graph = FactorGraph::try_from(model).unwrap();
+                assert!(initialize_from_measurements(&factor_graph).is_empty());
+                let report = optimize(&factor_graph, 10).unwrap();
+                let expected_report = optimize(&expected_graph, 10).unwrap();
+                assert!(report.final_chi2 < expected_report.final_chi2 + 1e-12);
+            });
+    }
+}
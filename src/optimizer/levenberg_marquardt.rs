@@ -0,0 +1,191 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Levenberg-Marquardt iterations with adaptive damping.
+
+#![allow(non_snake_case)]
+
+use crate::factor_graph::FactorGraph;
+use crate::optimizer::evaluation::calculate_chi2;
+use crate::optimizer::linear_system::calculate_H_b;
+use crate::optimizer::ordering::Permutation;
+use crate::optimizer::report::IterationStatistics;
+use crate::optimizer::solver::Solver;
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use crate::optimizer::{apply_solution, calc_update_norm, get_contents, set_contents};
+use nalgebra::DVector;
+use std::time::Instant;
+
+/// Parameters of the Levenberg-Marquardt algorithm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevenbergMarquardtParams {
+    /// The damping factor of the first iteration.
+    /// If None, it is derived from the largest diagonal entry of H multiplied by tau.
+    pub initial_lambda: Option<f64>,
+    /// Scale of the largest diagonal entry of H used to derive the initial damping factor.
+    pub tau: f64,
+    /// The number of rejected steps after which an iteration gives up.
+    pub max_trials: usize,
+}
+
+impl Default for LevenbergMarquardtParams {
+    fn default() -> Self {
+        LevenbergMarquardtParams {
+            initial_lambda: None,
+            tau: 1e-5,
+            max_trials: 10,
+        }
+    }
+}
+
+/// State of the Levenberg-Marquardt algorithm which is kept across iterations.
+pub struct LevenbergMarquardt<'a> {
+    params: &'a LevenbergMarquardtParams,
+    lambda: Option<f64>,
+    ni: f64,
+}
+
+impl<'a> LevenbergMarquardt<'a> {
+    pub fn new(params: &'a LevenbergMarquardtParams) -> Self {
+        LevenbergMarquardt {
+            params,
+            lambda: params.initial_lambda,
+            ni: 2.0,
+        }
+    }
+
+    /// Performs a single iteration, increasing the damping until a step decreases the total chi².
+    /// Rejected steps are rolled back.
+    ///
+    /// The returned statistics are marked as not accepted if no such step could be found within the maximum number of trials.
+    ///
+    /// The linear system is reordered by the given permutation before it is solved by the given solver,
+    /// which may keep its state across iterations.
+    pub fn iterate(
+        &mut self,
+        factor_graph: &FactorGraph,
+        permutation: &Permutation,
+        solver: &mut dyn Solver,
+    ) -> IterationStatistics {
+        let build_start = Instant::now();
+        let (H, b) = calculate_H_b(factor_graph);
+        let linear_system_time = build_start.elapsed();
+        let chi2 = calculate_chi2(factor_graph);
+        let tau = self.params.tau;
+        let mut lambda = *self.lambda.get_or_insert_with(|| tau * H.diagonal().max());
+        let old_contents = get_contents(factor_graph);
+        let mut statistics = IterationStatistics::new(chi2, b.norm());
+        statistics.linear_system_time = linear_system_time;
+
+        for _trial in 0..self.params.max_trials {
+            statistics.damping = Some(lambda);
+            let solve_start = Instant::now();
+            let solve_output = permutation.solve(solver, &damp(&H, lambda), &(&b * -1.0));
+            statistics.solver_time += solve_start.elapsed();
+            statistics.solver_residual = solver.residual_norm();
+            if let Ok(sol) = solve_output {
+                apply_solution(factor_graph, &sol);
+                let new_chi2 = calculate_chi2(factor_graph);
+                let update_norm = calc_update_norm(&sol);
+                let step = DVector::from_vec(sol);
+                let predicted_decrease = step.dot(&(&step * lambda - &b));
+                let rho = (chi2 - new_chi2) / predicted_decrease;
+                if new_chi2.is_finite() && rho > 0.0 {
+                    self.lambda = Some(lambda * (1.0 / 3.0f64).max(1.0 - (2.0 * rho - 1.0).powi(3)));
+                    self.ni = 2.0;
+                    statistics.chi2_after = new_chi2;
+                    statistics.update_norm = update_norm;
+                    statistics.accepted = true;
+                    return statistics;
+                }
+                set_contents(factor_graph, &old_contents);
+            }
+            lambda *= self.ni;
+            self.ni *= 2.0;
+        }
+        self.lambda = Some(lambda);
+        statistics
+    }
+}
+
+fn damp(H: &SparseMatrix, lambda: f64) -> SparseMatrix {
+    let mut damped = H.clone();
+    damped.add_diagonal(lambda);
+    damped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::ordering::VariableOrdering;
+    use crate::optimizer::solver::sparse_cholesky::SparseCholeskySolver;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+    use nalgebra::DMatrix;
+
+    use log::LevelFilter;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    fn test_chi2_never_increases(file_name: &str) {
+        init();
+        let factor_graph =
+            G2oParser::parse_file(&["data_files/optimizer_tests/", file_name, "_0.g2o"].concat()).unwrap();
+        let params = LevenbergMarquardtParams::default();
+        let mut levenberg_marquardt = LevenbergMarquardt::new(&params);
+        let permutation = Permutation::new(&factor_graph, VariableOrdering::MinimumDegree);
+        let mut solver = SparseCholeskySolver::default();
+        let mut chi2 = calculate_chi2(&factor_graph);
+        for _i in 0..10 {
+            let contents = get_contents(&factor_graph);
+            let iteration = levenberg_marquardt.iterate(&factor_graph, &permutation, &mut solver);
+            let new_chi2 = calculate_chi2(&factor_graph);
+            assert_eq!(iteration.chi2_before, chi2);
+            assert_eq!(iteration.chi2_after, new_chi2);
+            if iteration.accepted {
+                assert!(
+                    new_chi2 < chi2,
+                    "accepted step increased chi² from {} to {}",
+                    chi2,
+                    new_chi2
+                );
+            } else {
+                assert_eq!(get_contents(&factor_graph), contents);
+            }
+            chi2 = new_chi2;
+        }
+    }
+
+    #[test]
+    fn test_chi2_never_increases_2d() {
+        test_chi2_never_increases("full2d");
+    }
+
+    #[test]
+    fn test_chi2_never_increases_3d() {
+        test_chi2_never_increases("odo3d_only");
+    }
+
+    #[test]
+    fn test_damping_only_changes_diagonal() {
+        let H = SparseMatrix::from(DMatrix::from_vec(2, 2, vec![2.0, -1.0, -1.0, 2.0]));
+        assert_eq!(
+            damp(&H, 0.5).to_dense(),
+            DMatrix::from_vec(2, 2, vec![2.5, -1.0, -1.0, 2.5])
+        );
+    }
+}
@@ -0,0 +1,100 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+#![allow(non_snake_case)]
+
+use crate::factor_graph::factor::Factor;
+use crate::factor_graph::variable::{FixedType, Variable};
+use crate::optimizer::linear_system::iso3d_gradients::get_isometry;
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::DVector;
+use std::f64::consts::PI;
+
+/// Updates H and b with a linearized prior, whose error equals the difference between its variables and its mean.
+///
+/// The difference is expressed in the same local parametrization as the optimizer's updates,
+/// so the Jacobian of the error with respect to the variables is approximated by the identity.
+pub fn update_H_b(H: &mut SparseMatrix, b: &mut DVector<f64>, factor: &Factor, vars: &[&Variable]) {
+    let information = &factor.information_matrix.content;
+    let b_updates = information * DVector::from_vec(calc_error(factor, vars));
+    let blocks: Vec<(&FixedType, usize)> = vars
+        .iter()
+        .scan(0, |offset, var| {
+            *offset += var.get_dim();
+            Some((var.get_fixed_type(), *offset - var.get_dim()))
+        })
+        .collect();
+    for (row_type, row_offset) in &blocks {
+        let row_range = if let FixedType::NonFixed(range) = row_type {
+            range
+        } else {
+            continue;
+        };
+        for (col_type, col_offset) in &blocks {
+            if let FixedType::NonFixed(col_range) = col_type {
+                H.add_block(
+                    row_range.start,
+                    col_range.start,
+                    &information.slice((*row_offset, *col_offset), (row_range.len(), col_range.len())),
+                );
+            }
+        }
+        let updated_subvector =
+            &(b.rows(row_range.start, row_range.len()) + b_updates.rows(*row_offset, row_range.len()));
+        b.rows_mut(row_range.start, row_range.len())
+            .copy_from(updated_subvector);
+    }
+}
+
+pub fn calc_error(factor: &Factor, vars: &[&Variable]) -> Vec<f64> {
+    let mut offset = 0;
+    vars.iter()
+        .flat_map(|var| {
+            let content_len = var.get_content().len();
+            offset += content_len;
+            calc_difference(var, &factor.constraint[offset - content_len..offset])
+        })
+        .collect()
+}
+
+/// Returns the difference between the variable's content and the given mean, i.e. the update which would have to be
+/// applied to the mean by the optimizer to obtain the content.
+pub fn calc_difference(var: &Variable, mean: &[f64]) -> Vec<f64> {
+    match var {
+        Variable::Vehicle2D(var) => {
+            let pose = var.pose();
+            let mut err_rot = pose[2] - mean[2];
+            if err_rot > PI {
+                err_rot -= 2.0 * PI;
+            } else if err_rot < -PI {
+                err_rot += 2.0 * PI;
+            }
+            vec![pose[0] - mean[0], pose[1] - mean[1], err_rot]
+        }
+        Variable::Vehicle3D(var) => {
+            let difference = get_isometry(mean).inverse() * get_isometry(&var.pose());
+            let mut quaternion = *difference.rotation.quaternion();
+            if quaternion.w < 0.0 {
+                quaternion = -quaternion;
+            }
+            let mut err_vec: Vec<f64> = difference.translation.vector.iter().copied().collect();
+            err_vec.extend(quaternion.imag().iter().map(|value| value / quaternion.w));
+            err_vec
+        }
+        _ => var
+            .get_content()
+            .iter()
+            .zip(mean.iter())
+            .map(|(value, mean)| value - mean)
+            .collect(),
+    }
+}
@@ -10,12 +10,13 @@
 // This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
 //
 
-use crate::factor_graph::factor::{Factor, FactorType::*};
+use crate::factor_graph::factor::robust_kernel::RobustKernel;
+use crate::factor_graph::factor::{Factor, FactorType::*, InformationMatrix};
+use crate::factor_graph::variable::{FixedType, SwitchVariable, Variable};
 use crate::factor_graph::FactorGraph;
-use nalgebra::{DMatrix, DVector};
-use petgraph::csr::EdgeReference;
-use petgraph::visit::EdgeRef;
-use petgraph::Directed;
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::DVector;
+use std::ops::Range;
 
 mod obs2d_handler;
 mod odo2d_handler;
@@ -26,38 +27,229 @@ mod obs3d_handler;
 mod odo3d_handler;
 mod pos3d_handler;
 
-pub fn calculate_H_b(factor_graph: &FactorGraph) -> (DMatrix<f64>, DVector<f64>) {
+mod switch_prior_handler;
+mod switchable_handler;
+
+pub mod linearized_prior_handler;
+
+/// The number of factors assembled together by a single task during parallel assembly.
+#[cfg(feature = "rayon")]
+const ASSEMBLY_CHUNK_SIZE: usize = 256;
+
+/// Returns H and b of the linear system approximating the factor graph at the variables' current contents.
+///
+/// With the `rayon` feature enabled, the factors are assembled in parallel.
+pub fn calculate_H_b(factor_graph: &FactorGraph) -> (SparseMatrix, DVector<f64>) {
+    let factors: Vec<(&Factor, Vec<usize>)> = factor_graph.factors().collect();
+    #[cfg(feature = "rayon")]
+    let (mut H, b) = assemble_parallel(factor_graph, &factors);
+    #[cfg(not(feature = "rayon"))]
+    let (mut H, b) = assemble(factor_graph, &factors);
+    H.set_blocks(get_blocks(factor_graph));
+
+    (H, b)
+}
+
+/// Sequentially adds the contributions of the given factors to an initially empty H and b.
+fn assemble(factor_graph: &FactorGraph, factors: &[(&Factor, Vec<usize>)]) -> (SparseMatrix, DVector<f64>) {
     let dim = factor_graph.matrix_dim;
-    let mut H = DMatrix::from_vec(dim, dim, vec![0.0; dim * dim]);
+    let mut H = SparseMatrix::new(dim);
     let mut b = DVector::from_vec(vec![0.0; dim]);
+    factors
+        .iter()
+        .for_each(|(factor, indices)| update_H_b(factor_graph, &mut H, &mut b, factor, indices));
+    (H, b)
+}
 
-    factor_graph
+/// Assembles chunks of factors in parallel and merges the partial results in the order of the chunks.
+///
+/// As the chunks do not depend on the number of threads, the result is deterministic.
+#[cfg(feature = "rayon")]
+fn assemble_parallel(factor_graph: &FactorGraph, factors: &[(&Factor, Vec<usize>)]) -> (SparseMatrix, DVector<f64>) {
+    use rayon::prelude::*;
+    let dim = factor_graph.matrix_dim;
+    let chunks: Vec<(SparseMatrix, DVector<f64>)> = factors
+        .par_chunks(ASSEMBLY_CHUNK_SIZE)
+        .map(|chunk| assemble(factor_graph, chunk))
+        .collect();
+    chunks.into_iter().fold(
+        (SparseMatrix::new(dim), DVector::from_vec(vec![0.0; dim])),
+        |(mut H, b), (chunk_H, chunk_b)| {
+            H.append(chunk_H);
+            (H, b + chunk_b)
+        },
+    )
+}
+
+/// Returns the ranges of all non-fixed variables within H, ordered by their position.
+fn get_blocks(factor_graph: &FactorGraph) -> Vec<Range<usize>> {
+    let mut blocks: Vec<Range<usize>> = factor_graph
         .node_indices
         .iter()
-        .map(|i| factor_graph.csr.edges(*i))
-        .for_each(|edges| edges.for_each(|edge| update_H_b(factor_graph, &mut H, &mut b, edge)));
-
-    (H, b)
+        .filter_map(|i| match factor_graph.get_var(*i).get_fixed_type() {
+            FixedType::NonFixed(range) => Some(range.clone()),
+            FixedType::Fixed => None,
+        })
+        .collect();
+    blocks.sort_by_key(|range| range.start);
+    blocks
 }
 
-fn update_H_b(
+/// Adds the contribution of the given factor to H and b. The factor's variables are given by their internal CSR
+/// indices as returned by FactorGraph::factors().
+pub fn update_H_b(
     factor_graph: &FactorGraph,
-    H: &mut DMatrix<f64>,
+    H: &mut SparseMatrix,
     b: &mut DVector<f64>,
-    edge: EdgeReference<Factor, Directed, usize>,
+    factor: &Factor,
+    indices: &[usize],
 ) {
     use crate::factor_graph::variable::Variable::*;
-    let factor = edge.weight();
-    let var_i = &factor_graph.get_var(edge.source());
-    let var_j = &factor_graph.get_var(edge.target());
-
-    match (&factor.factor_type, var_i, var_j) {
-        (Position2D, Vehicle2D(var_i), _) => pos2d_handler::update_H_b(H, b, factor, var_i),
-        (Odometry2D, Vehicle2D(var_i), Vehicle2D(var_j)) => odo2d_handler::update_H_b(H, b, factor, var_i, var_j),
-        (Observation2D, Vehicle2D(var_i), Landmark2D(var_j)) => obs2d_handler::update_H_b(H, b, factor, var_i, var_j),
-        (Position3D, Vehicle3D(var_i), _) => pos3d_handler::update_H_b(H, b, factor, var_i),
-        (Odometry3D, Vehicle3D(var_i), Vehicle3D(var_j)) => odo3d_handler::update_H_b(H, b, factor, var_i, var_j),
-        (Observation3D, Vehicle3D(var_i), Landmark3D(var_j)) => obs3d_handler::update_H_b(H, b, factor, var_i, var_j),
-        _ => unreachable!("No valid edge."),
+    let robust_factor;
+    let factor = match factor_graph.get_robust_kernel(factor) {
+        Some(kernel) => {
+            robust_factor = calc_robust_factor(factor_graph, factor, indices, &kernel);
+            &robust_factor
+        }
+        None => factor,
+    };
+    let (var_i, var_j) = get_source_target(factor_graph, indices);
+
+    match (&factor.factor_type, var_i, var_j, get_switch(factor_graph, factor)) {
+        (Position2D, Vehicle2D(var_i), _, _) => pos2d_handler::update_H_b(H, b, factor, var_i),
+        (Odometry2D, Vehicle2D(var_i), Vehicle2D(var_j), _) => odo2d_handler::update_H_b(H, b, factor, var_i, var_j),
+        (Observation2D, Vehicle2D(var_i), Landmark2D(var_j), _) => {
+            obs2d_handler::update_H_b(H, b, factor, var_i, var_j)
+        }
+        (Position3D, Vehicle3D(var_i), _, _) => pos3d_handler::update_H_b(H, b, factor, var_i),
+        (Odometry3D, Vehicle3D(var_i), Vehicle3D(var_j), _) => odo3d_handler::update_H_b(H, b, factor, var_i, var_j),
+        (Observation3D, Vehicle3D(var_i), Landmark3D(var_j), _) => {
+            obs3d_handler::update_H_b(H, b, factor, var_i, var_j)
+        }
+        (SwitchableOdometry2D, Vehicle2D(var_i), Vehicle2D(var_j), Some(switch)) => switchable_handler::update_H_b(
+            H,
+            b,
+            factor,
+            (&var_i.fixed_type, &var_j.fixed_type),
+            switch,
+            &odo2d_handler::calc_jacobian(factor, var_i, var_j),
+            &odo2d_handler::calc_error(factor, var_i, var_j),
+        ),
+        (SwitchableOdometry3D, Vehicle3D(var_i), Vehicle3D(var_j), Some(switch)) => switchable_handler::update_H_b(
+            H,
+            b,
+            factor,
+            (&var_i.fixed_type, &var_j.fixed_type),
+            switch,
+            &odo3d_handler::calc_jacobian(factor, var_i, var_j),
+            &odo3d_handler::calc_error(factor, var_i, var_j),
+        ),
+        (SwitchPrior, Switch(var), _, _) => switch_prior_handler::update_H_b(H, b, factor, var),
+        (LinearizedPrior, _, _, _) => {
+            linearized_prior_handler::update_H_b(H, b, factor, &get_prior_vars(factor_graph, factor))
+        }
+        // combinations of factor and variable types are rejected when adding the factor, so there is nothing to add
+        _ => {}
+    }
+}
+
+/// Returns a copy of the factor with its information matrix weighted as in iteratively reweighted least squares.
+fn calc_robust_factor(factor_graph: &FactorGraph, factor: &Factor, indices: &[usize], kernel: &RobustKernel) -> Factor {
+    let chi2 = calc_chi2(factor, &calc_error(factor_graph, factor, indices));
+    let (_, weight) = kernel.robustify(chi2);
+    Factor {
+        information_matrix: InformationMatrix {
+            content: &factor.information_matrix.content * weight,
+        },
+        ..factor.clone()
+    }
+}
+
+pub fn calc_chi2(factor: &Factor, error: &[f64]) -> f64 {
+    let err = DVector::from_column_slice(error);
+    err.dot(&(&factor.information_matrix.content * &err))
+}
+
+/// Returns the error of the given factor, whose variables are given by their internal CSR indices as returned by
+/// FactorGraph::factors().
+pub fn calc_error(factor_graph: &FactorGraph, factor: &Factor, indices: &[usize]) -> Vec<f64> {
+    use crate::factor_graph::variable::Variable::*;
+    let (var_i, var_j) = get_source_target(factor_graph, indices);
+
+    match (&factor.factor_type, var_i, var_j, get_switch(factor_graph, factor)) {
+        (Position2D, Vehicle2D(var_i), _, _) => pos2d_handler::calc_error(factor, var_i),
+        (Odometry2D, Vehicle2D(var_i), Vehicle2D(var_j), _) => odo2d_handler::calc_error(factor, var_i, var_j),
+        (Observation2D, Vehicle2D(var_i), Landmark2D(var_j), _) => obs2d_handler::calc_error(factor, var_i, var_j),
+        (Position3D, Vehicle3D(var_i), _, _) => pos3d_handler::calc_error(factor, var_i),
+        (Odometry3D, Vehicle3D(var_i), Vehicle3D(var_j), _) => odo3d_handler::calc_error(factor, var_i, var_j),
+        (Observation3D, Vehicle3D(var_i), Landmark3D(var_j), _) => obs3d_handler::calc_error(factor, var_i, var_j),
+        (SwitchableOdometry2D, Vehicle2D(var_i), Vehicle2D(var_j), Some(switch)) => {
+            switchable_handler::calc_error(switch, odo2d_handler::calc_error(factor, var_i, var_j))
+        }
+        (SwitchableOdometry3D, Vehicle3D(var_i), Vehicle3D(var_j), Some(switch)) => {
+            switchable_handler::calc_error(switch, odo3d_handler::calc_error(factor, var_i, var_j))
+        }
+        (SwitchPrior, Switch(var), _, _) => switch_prior_handler::calc_error(factor, var),
+        (LinearizedPrior, _, _, _) => {
+            linearized_prior_handler::calc_error(factor, &get_prior_vars(factor_graph, factor))
+        }
+        // combinations of factor and variable types are rejected when adding the factor, so there is no error
+        _ => vec![0.0; factor.information_matrix.content.nrows()],
+    }
+}
+
+/// Returns the first two variables of a factor, or its only variable twice.
+fn get_source_target<'a>(factor_graph: &'a FactorGraph, indices: &[usize]) -> (&'a Variable, &'a Variable) {
+    let source = factor_graph.get_var(indices[0]);
+    (
+        source,
+        indices.get(1).map_or(source, |target| factor_graph.get_var(*target)),
+    )
+}
+
+/// Returns the switch variable of a switchable factor, or None for all other factors.
+fn get_switch<'a>(factor_graph: &'a FactorGraph, factor: &Factor) -> Option<&'a SwitchVariable> {
+    match factor.switch_index.map(|index| factor_graph.get_var(index)) {
+        Some(Variable::Switch(switch)) => Some(switch),
+        _ => None,
+    }
+}
+
+fn get_prior_vars<'a>(factor_graph: &'a FactorGraph, factor: &Factor) -> Vec<&'a Variable> {
+    factor
+        .prior_indices
+        .iter()
+        .map(|index| factor_graph.get_var(*index))
+        .collect()
+}
+
+#[cfg(all(test, feature = "rayon"))]
+mod tests {
+    use super::*;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+    use log::LevelFilter;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    #[test]
+    fn test_parallel_assembly() {
+        init();
+        let factor_graph = G2oParser::parse_file("data_files/benchmark_input/MIT_2D.g2o").unwrap();
+        let factors: Vec<(&Factor, Vec<usize>)> = factor_graph.factors().collect();
+        assert!(factors.len() > ASSEMBLY_CHUNK_SIZE);
+        let (H, b) = assemble(&factor_graph, &factors);
+        let (parallel_H, parallel_b) = assemble_parallel(&factor_graph, &factors);
+        assert_eq!(parallel_H, H);
+        assert!(parallel_b
+            .iter()
+            .zip(b.iter())
+            .all(|(parallel, sequential)| approx::relative_eq!(parallel, sequential, epsilon = 1e-9)));
+        assert_eq!(assemble_parallel(&factor_graph, &factors), (parallel_H, parallel_b));
     }
 }
@@ -15,22 +15,21 @@
 
 use crate::factor_graph::factor::Factor;
 use crate::factor_graph::variable::{FixedType, LandmarkVariable2D, VehicleVariable2D};
+use crate::optimizer::sparse_matrix::SparseMatrix;
 use nalgebra::{
-    DMatrix, DVector, Dynamic, Matrix, Matrix2x5, Matrix5x2, Rotation2, RowVector2, SliceStorage, Vector, Vector2, U1,
-    U5,
+    DVector, Dynamic, Matrix, Matrix2x5, Matrix5x2, Rotation2, RowVector2, SliceStorage, Vector, Vector2, U1, U5,
 };
 use nalgebra::storage::Storage;
 
 pub fn update_H_b(
-    H: &mut DMatrix<f64>,
+    H: &mut SparseMatrix,
     b: &mut DVector<f64>,
     factor: &Factor,
     var_i: &VehicleVariable2D,
     var_j: &LandmarkVariable2D,
 ) {
-    let (pos_i, rot_i) = get_pos_and_rot(&*var_i.pose.borrow());
-    let pos_j = get_pos(&*var_j.position.borrow());
-    let pos_ij = get_pos(&factor.constraint);
+    let (pos_i, rot_i) = get_pos_and_rot(&var_i.pose());
+    let pos_j = get_pos(&var_j.position());
     let (jacobi, jacobi_T) = calc_jacobians(&pos_i, rot_i, &pos_j);
     let right_mult = &factor.information_matrix.content * jacobi;
 
@@ -40,13 +39,20 @@ pub fn update_H_b(
     update_H_submatrix(H, &H_updates.index((3.., ..3)), &var_j.fixed_type, &var_i.fixed_type);
     update_H_submatrix(H, &H_updates.index((3.., 3..)), &var_j.fixed_type, &var_j.fixed_type);
 
-    let err_pos: Vector2<f64> = Rotation2::new(-rot_i) * (pos_j - pos_i) - pos_ij;
-    let err_vec = err_pos.data.as_slice().to_vec();
+    let err_vec = calc_error(factor, var_i, var_j);
     let b_updates = (RowVector2::from_vec(err_vec) * &right_mult).transpose();
     update_b_subvector(b, &b_updates.index((..3, ..)), &var_i.fixed_type);
     update_b_subvector(b, &b_updates.index((3.., ..)), &var_j.fixed_type);
 }
 
+pub fn calc_error(factor: &Factor, var_i: &VehicleVariable2D, var_j: &LandmarkVariable2D) -> Vec<f64> {
+    let (pos_i, rot_i) = get_pos_and_rot(&var_i.pose());
+    let pos_j = get_pos(&var_j.position());
+    let pos_ij = get_pos(&factor.constraint);
+    let err_pos: Vector2<f64> = Rotation2::new(-rot_i) * (pos_j - pos_i) - pos_ij;
+    err_pos.data.as_slice().to_vec()
+}
+
 fn calc_jacobians(pos_i: &Vector2<f64>, rot_i: f64, pos_j: &Vector2<f64>) -> (Matrix2x5<f64>, Matrix5x2<f64>) {
     let delta_pos_vec = pos_j - pos_i;
     let delta_pos = delta_pos_vec.data.as_slice();
@@ -64,15 +70,13 @@ fn calc_jacobians(pos_i: &Vector2<f64>, rot_i: f64, pos_j: &Vector2<f64>) -> (Ma
 }
 
 fn update_H_submatrix(
-    H: &mut DMatrix<f64>,
+    H: &mut SparseMatrix,
     added_matrix: &Matrix<f64, Dynamic, Dynamic, SliceStorage<f64, Dynamic, Dynamic, U1, U5>>,
     var_row: &FixedType,
     var_col: &FixedType,
 ) {
     if let (FixedType::NonFixed(row_range), FixedType::NonFixed(col_range)) = (var_row, var_col) {
-        let updated_submatrix = &(H.index((row_range.to_owned(), col_range.to_owned())) + added_matrix);
-        H.index_mut((row_range.to_owned(), col_range.to_owned()))
-            .copy_from(updated_submatrix);
+        H.add_block(row_range.start, col_range.start, added_matrix);
     }
 }
 
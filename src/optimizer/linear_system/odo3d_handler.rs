@@ -18,6 +18,7 @@ use crate::factor_graph::variable::{FixedType, VehicleVariable3D};
 use crate::optimizer::linear_system::iso3d_gradients::{
     calc_dq_dR, get_isometry, skew_matr_T_and_mult_parts, skew_matr_and_mult_parts, skew_trans,
 };
+use crate::optimizer::sparse_matrix::SparseMatrix;
 use nalgebra::{
     DMatrix, DVector, Dynamic, Isometry3, Matrix, Matrix3, Matrix6, MatrixMN, RowVector6, SliceStorage, Vector, U1,
     U12, U6,
@@ -25,14 +26,14 @@ use nalgebra::{
 use nalgebra::storage::Storage;
 
 pub fn update_H_b(
-    H: &mut DMatrix<f64>,
+    H: &mut SparseMatrix,
     b: &mut DVector<f64>,
     factor: &Factor,
     var_i: &VehicleVariable3D,
     var_j: &VehicleVariable3D,
 ) {
-    let iso_i = get_isometry(&*var_i.pose.borrow());
-    let iso_j = get_isometry(&*var_j.pose.borrow());
+    let iso_i = get_isometry(&var_i.pose());
+    let iso_j = get_isometry(&var_j.pose());
     let iso_ij = get_isometry(&factor.constraint);
     let (jacobi, jacobi_T) = calc_jacobians(&iso_i, &iso_j, &iso_ij);
     let right_mult = &factor.information_matrix.content * jacobi;
@@ -43,14 +44,30 @@ pub fn update_H_b(
     update_H_submatrix(H, &H_updates.index((6.., ..6)), &var_j.fixed_type, &var_i.fixed_type);
     update_H_submatrix(H, &H_updates.index((6.., 6..)), &var_j.fixed_type, &var_j.fixed_type);
 
-    let err = iso_ij.inverse() * iso_i.inverse() * iso_j;
-    let mut err_vec = err.translation.vector.data.as_slice().to_vec();
-    err_vec.extend_from_slice(&err.rotation.quaternion().coords.data.as_slice().to_vec()[..3]);
+    let err_vec = calc_error(factor, var_i, var_j);
     let b_updates = (RowVector6::from_vec(err_vec) * &right_mult).transpose();
     update_b_subvector(b, &b_updates.index((..6, ..)), &var_i.fixed_type);
     update_b_subvector(b, &b_updates.index((6.., ..)), &var_j.fixed_type);
 }
 
+pub fn calc_error(factor: &Factor, var_i: &VehicleVariable3D, var_j: &VehicleVariable3D) -> Vec<f64> {
+    let iso_i = get_isometry(&var_i.pose());
+    let iso_j = get_isometry(&var_j.pose());
+    let iso_ij = get_isometry(&factor.constraint);
+    let err = iso_ij.inverse() * iso_i.inverse() * iso_j;
+    let mut err_vec = err.translation.vector.data.as_slice().to_vec();
+    err_vec.extend_from_slice(&err.rotation.quaternion().coords.data.as_slice().to_vec()[..3]);
+    err_vec
+}
+
+pub fn calc_jacobian(factor: &Factor, var_i: &VehicleVariable3D, var_j: &VehicleVariable3D) -> DMatrix<f64> {
+    let iso_i = get_isometry(&var_i.pose());
+    let iso_j = get_isometry(&var_j.pose());
+    let iso_ij = get_isometry(&factor.constraint);
+    let (jacobi, _) = calc_jacobians(&iso_i, &iso_j, &iso_ij);
+    DMatrix::from_column_slice(6, 12, jacobi.as_slice())
+}
+
 fn calc_jacobians(
     iso_i: &Isometry3<f64>,
     iso_j: &Isometry3<f64>,
@@ -85,15 +102,13 @@ fn calc_jacobians(
 }
 
 fn update_H_submatrix(
-    H: &mut DMatrix<f64>,
+    H: &mut SparseMatrix,
     added_matrix: &Matrix<f64, Dynamic, Dynamic, SliceStorage<f64, Dynamic, Dynamic, U1, U12>>,
     row_type: &FixedType,
     col_type: &FixedType,
 ) {
     if let (FixedType::NonFixed(row_range), FixedType::NonFixed(col_range)) = (row_type, col_type) {
-        let (row_range, col_range) = (row_range.to_owned(), col_range.to_owned());
-        let updated_submatrix = &(H.index((row_range.clone(), col_range.clone())) + added_matrix);
-        H.index_mut((row_range, col_range)).copy_from(updated_submatrix);
+        H.add_block(row_range.start, col_range.start, added_matrix);
     }
 }
 
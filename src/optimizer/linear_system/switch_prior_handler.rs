@@ -0,0 +1,35 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+#![allow(non_snake_case)]
+
+use crate::factor_graph::factor::Factor;
+use crate::factor_graph::variable::{FixedType, SwitchVariable};
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::DVector;
+
+pub fn update_H_b(H: &mut SparseMatrix, b: &mut DVector<f64>, factor: &Factor, var: &SwitchVariable) {
+    let index = if let FixedType::NonFixed(range) = &var.fixed_type {
+        range.start
+    } else {
+        return;
+    };
+
+    // the Jacobian of the error with respect to the switch value equals 1
+    let information = factor.information_matrix.content[(0, 0)];
+    H.add(index, index, information);
+    b[index] += information * calc_error(factor, var)[0];
+}
+
+pub fn calc_error(factor: &Factor, var: &SwitchVariable) -> Vec<f64> {
+    vec![var.value() - factor.constraint[0]]
+}
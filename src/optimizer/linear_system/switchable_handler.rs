@@ -0,0 +1,87 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+#![allow(non_snake_case)]
+
+use crate::factor_graph::factor::Factor;
+use crate::factor_graph::variable::{FixedType, SwitchVariable};
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::{DMatrix, DVector};
+use std::ops::Range;
+
+/// Updates H and b with a switchable factor, whose error equals the error of the underlying factor scaled by the switch value.
+///
+/// The given Jacobian and error belong to the underlying factor with respect to its two variables.
+pub fn update_H_b(
+    H: &mut SparseMatrix,
+    b: &mut DVector<f64>,
+    factor: &Factor,
+    (var_i, var_j): (&FixedType, &FixedType),
+    switch: &SwitchVariable,
+    jacobian: &DMatrix<f64>,
+    err: &[f64],
+) {
+    let switch_value = switch.value();
+    let dim = jacobian.ncols() / 2;
+    let err = DVector::from_column_slice(err);
+    let mut jacobian_s = DMatrix::zeros(err.len(), 2 * dim + 1);
+    jacobian_s
+        .index_mut((.., ..2 * dim))
+        .copy_from(&(jacobian * switch_value));
+    jacobian_s.index_mut((.., 2 * dim..)).copy_from(&err);
+    let right_mult = &factor.information_matrix.content * &jacobian_s;
+
+    let H_updates = jacobian_s.transpose() * &right_mult;
+    let b_updates = right_mult.transpose() * (err * switch_value);
+    let blocks = [
+        (var_i, 0..dim),
+        (var_j, dim..2 * dim),
+        (&switch.fixed_type, 2 * dim..2 * dim + 1),
+    ];
+    for (row_type, row_block) in blocks.iter() {
+        for (col_type, col_block) in blocks.iter() {
+            update_H_submatrix(H, &H_updates, row_block, col_block, row_type, col_type);
+        }
+        update_b_subvector(b, &b_updates, row_block, row_type);
+    }
+}
+
+/// Returns the error of the underlying factor scaled by the switch value.
+pub fn calc_error(switch: &SwitchVariable, err: Vec<f64>) -> Vec<f64> {
+    let switch_value = switch.value();
+    err.iter().map(|val| val * switch_value).collect()
+}
+
+fn update_H_submatrix(
+    H: &mut SparseMatrix,
+    H_updates: &DMatrix<f64>,
+    row_block: &Range<usize>,
+    col_block: &Range<usize>,
+    row_type: &FixedType,
+    col_type: &FixedType,
+) {
+    if let (FixedType::NonFixed(row_range), FixedType::NonFixed(col_range)) = (row_type, col_type) {
+        H.add_block(
+            row_range.start,
+            col_range.start,
+            &H_updates.index((row_block.to_owned(), col_block.to_owned())),
+        );
+    }
+}
+
+fn update_b_subvector(b: &mut DVector<f64>, b_updates: &DVector<f64>, block: &Range<usize>, fixed_type: &FixedType) {
+    if let FixedType::NonFixed(range) = fixed_type {
+        let range = range.to_owned();
+        let updated_subvector = &(b.index((range.clone(), ..)) + b_updates.index((block.to_owned(), ..)));
+        b.index_mut((range, ..)).copy_from(updated_subvector);
+    }
+}
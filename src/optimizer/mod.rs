@@ -14,34 +14,242 @@
 
 #![allow(non_snake_case)]
 
+use crate::error::Error;
 use crate::factor_graph::variable::{FixedType, Variable};
 use crate::factor_graph::FactorGraph;
+use crate::optimizer::dogleg::{Dogleg, DoglegParams};
+use crate::optimizer::evaluation::calculate_chi2;
+use crate::optimizer::gauge::{get_anchored_rows, GaugePolicy, GaugeSolver};
+use crate::optimizer::levenberg_marquardt::{LevenbergMarquardt, LevenbergMarquardtParams};
 use crate::optimizer::linear_system::calculate_H_b;
 use crate::optimizer::linear_system::iso3d_gradients::{get_isometry, get_isometry_normalized};
-use crate::optimizer::solver::sparse_cholesky::SparseCholeskySolver;
-use crate::optimizer::solver::Solver;
+use crate::optimizer::ordering::{Permutation, VariableOrdering};
+use crate::optimizer::report::{IterationStatistics, OptimizationReport};
+use crate::optimizer::solver::schur_complement::SchurComplementSolver;
+use crate::optimizer::solver::{Solver, SolverType};
+use crate::optimizer::termination::{TerminationCriteria, TerminationReason};
 use std::f64::consts::PI;
+use std::time::Instant;
 use nalgebra::storage::Storage;
 
+pub mod covariance;
+pub mod dogleg;
+pub mod evaluation;
+pub mod gauge;
+pub mod incremental;
+pub mod initialization;
+pub mod levenberg_marquardt;
 mod linear_system;
-mod solver;
+pub mod ordering;
+pub mod report;
+pub mod sliding_window;
+pub mod solver;
+pub mod sparse_matrix;
+pub mod termination;
 
-/// Optimizes a factor graph with the given number of iterations.
-pub fn optimize(graph: &FactorGraph, iterations: usize) {
-    for _i in 0..iterations {
-        update_once(graph);
+/// Algorithm used to calculate and apply the update of an iteration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Algorithm {
+    /// Applies the Gauss-Newton step in every iteration, regardless of its effect on the error.
+    GaussNewton,
+    /// Damps the Gauss-Newton step adaptively and only applies steps decreasing the total chi².
+    LevenbergMarquardt(LevenbergMarquardtParams),
+    /// Blends the steepest descent and Gauss-Newton steps within an adaptive trust region
+    /// and only applies steps decreasing the total chi².
+    Dogleg(DoglegParams),
+}
+
+/// Settings of an optimization run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizerSettings {
+    /// The maximum number of iterations.
+    pub iterations: usize,
+    /// The algorithm used in every iteration.
+    pub algorithm: Algorithm,
+    /// Criteria for stopping before the maximum number of iterations is reached.
+    pub termination_criteria: TerminationCriteria,
+    /// Order in which the variables are eliminated when solving the linear system of an iteration.
+    pub ordering: VariableOrdering,
+    /// The solver used for the linear system of every iteration. Ignored by optimize_with_solver().
+    pub solver: SolverType,
+    /// Whether to eliminate all landmark variables via the Schur complement before solving the linear system,
+    /// which only leaves the much smaller system of the remaining variables to the solver.
+    /// Speeds up graphs dominated by observations.
+    pub schur_complement: bool,
+    /// Handling of parts of the factor graph which are neither anchored by a fixed variable nor by an absolute factor.
+    pub gauge_policy: GaugePolicy,
+}
+
+impl Default for OptimizerSettings {
+    fn default() -> Self {
+        OptimizerSettings {
+            iterations: 10,
+            algorithm: Algorithm::GaussNewton,
+            termination_criteria: TerminationCriteria::default(),
+            ordering: VariableOrdering::default(),
+            solver: SolverType::default(),
+            schur_complement: false,
+            gauge_policy: GaugePolicy::default(),
+        }
+    }
+}
+
+/// Optimizes a factor graph with the given number of Gauss-Newton iterations.
+pub fn optimize(graph: &FactorGraph, iterations: usize) -> Result<OptimizationReport, Error> {
+    optimize_with_settings(
+        graph,
+        &OptimizerSettings {
+            iterations,
+            ..Default::default()
+        },
+    )
+}
+
+/// Optimizes a factor graph with the given settings.
+///
+/// Stops early if an iteration cannot decrease the total chi² or one of the termination criteria is met.
+/// Returns an error if the linear system of a Gauss-Newton iteration cannot be solved, in which case the variables
+/// keep the estimates of the previous iteration.
+pub fn optimize_with_settings(graph: &FactorGraph, settings: &OptimizerSettings) -> Result<OptimizationReport, Error> {
+    optimize_with_solver(graph, settings, settings.solver.create().as_mut())
+}
+
+/// Optimizes a factor graph with the given settings, using the given solver instead of the one stated in the settings.
+///
+/// The solver is used for all iterations, so it can keep internal state such as caches across them.
+pub fn optimize_with_solver(
+    graph: &FactorGraph,
+    settings: &OptimizerSettings,
+    solver: &mut dyn Solver,
+) -> Result<OptimizationReport, Error> {
+    let start = Instant::now();
+    let initial_chi2 = calculate_chi2(graph);
+    let permutation = Permutation::new(graph, settings.ordering);
+    let mut schur_complement_solver;
+    let (permutation, solver): (Permutation, &mut dyn Solver) = if settings.schur_complement {
+        let (permutation, other_dim) = permutation.with_landmarks_last(graph);
+        schur_complement_solver = SchurComplementSolver::new(solver, other_dim);
+        (permutation, &mut schur_complement_solver)
+    } else {
+        (permutation, solver)
+    };
+    let (anchored_rows, warnings) = get_anchored_rows(graph, &permutation, &settings.gauge_policy);
+    let mut gauge_solver = GaugeSolver::new(solver, anchored_rows, settings.gauge_policy.clone());
+    let solver: &mut dyn Solver = &mut gauge_solver;
+    let mut algorithm_state = AlgorithmState::new(&settings.algorithm, initial_chi2);
+    let mut iterations = vec![];
+    let mut termination_reason = TerminationReason::MaxIterations;
+    for _i in 0..settings.iterations {
+        let iteration = algorithm_state.iterate(graph, &permutation, solver)?;
+        let reason = if !iteration.accepted {
+            Some(TerminationReason::NoImprovement)
+        } else {
+            settings.termination_criteria.check(&iteration, start.elapsed())
+        };
+        iterations.push(iteration);
+        if let Some(reason) = reason {
+            termination_reason = reason;
+            break;
+        }
+    }
+    Ok(OptimizationReport {
+        initial_chi2,
+        final_chi2: iterations.last().map_or(initial_chi2, |iteration| iteration.chi2_after),
+        iterations,
+        termination_reason,
+        total_time: start.elapsed(),
+        warnings,
+    })
+}
+
+enum AlgorithmState<'a> {
+    /// Keeps the chi² of the current estimates, so it is not recomputed at the start of the next iteration.
+    GaussNewton { chi2: f64 },
+    LevenbergMarquardt(LevenbergMarquardt<'a>),
+    Dogleg(Dogleg<'a>),
+}
+
+impl<'a> AlgorithmState<'a> {
+    fn new(algorithm: &'a Algorithm, initial_chi2: f64) -> Self {
+        match algorithm {
+            Algorithm::GaussNewton => AlgorithmState::GaussNewton { chi2: initial_chi2 },
+            Algorithm::LevenbergMarquardt(params) => {
+                AlgorithmState::LevenbergMarquardt(LevenbergMarquardt::new(params))
+            }
+            Algorithm::Dogleg(params) => AlgorithmState::Dogleg(Dogleg::new(params)),
+        }
+    }
+
+    fn iterate(
+        &mut self,
+        graph: &FactorGraph,
+        permutation: &Permutation,
+        solver: &mut dyn Solver,
+    ) -> Result<IterationStatistics, Error> {
+        match self {
+            AlgorithmState::GaussNewton { chi2 } => {
+                let statistics = update_once(graph, *chi2, permutation, solver)?;
+                *chi2 = statistics.chi2_after;
+                Ok(statistics)
+            }
+            AlgorithmState::LevenbergMarquardt(levenberg_marquardt) => {
+                Ok(levenberg_marquardt.iterate(graph, permutation, solver))
+            }
+            AlgorithmState::Dogleg(dogleg) => Ok(dogleg.iterate(graph, permutation, solver)),
+        }
     }
 }
 
-fn update_once(factor_graph: &FactorGraph) {
-    let (H, b) = calculate_H_b(&factor_graph);
-    // TODO @Daniel: clumsy, since the solver transforms the arguments back to nalgebra matrices
-    let sol = SparseCholeskySolver::solve(H, &(b * -1.0)).unwrap();
+fn update_once(
+    factor_graph: &FactorGraph,
+    chi2_before: f64,
+    permutation: &Permutation,
+    solver: &mut dyn Solver,
+) -> Result<IterationStatistics, Error> {
+    let build_start = Instant::now();
+    let (H, b) = calculate_H_b(factor_graph);
+    let linear_system_time = build_start.elapsed();
+    let solve_start = Instant::now();
+    let solve_output = permutation.solve(solver, &H, &(&b * -1.0));
+    let solver_time = solve_start.elapsed();
+    let mut statistics = IterationStatistics::new(chi2_before, b.norm());
+    statistics.linear_system_time = linear_system_time;
+    statistics.solver_time = solver_time;
+    statistics.solver_residual = solver.residual_norm();
+    let sol = solve_output?;
+    apply_solution(factor_graph, sol.as_slice());
+    statistics.chi2_after = calculate_chi2(factor_graph);
+    statistics.update_norm = calc_update_norm(&sol);
+    statistics.accepted = true;
+    Ok(statistics)
+}
+
+fn calc_update_norm(update: &[f64]) -> f64 {
+    update.iter().fold(0.0, |max, val| val.abs().max(max))
+}
+
+fn apply_solution(factor_graph: &FactorGraph, solution: &[f64]) {
     factor_graph
         .node_indices
         .iter()
         .map(|i| factor_graph.get_var(*i))
-        .for_each(|var| update_var(var, sol.as_slice()));
+        .for_each(|var| update_var(var, solution));
+}
+
+fn get_contents(factor_graph: &FactorGraph) -> Vec<Vec<f64>> {
+    factor_graph
+        .node_indices
+        .iter()
+        .map(|i| factor_graph.get_var(*i).get_content())
+        .collect()
+}
+
+fn set_contents(factor_graph: &FactorGraph, contents: &[Vec<f64>]) {
+    factor_graph
+        .node_indices
+        .iter()
+        .zip(contents.iter())
+        .for_each(|(i, content)| factor_graph.get_var(*i).set_content(content.to_owned()));
 }
 
 fn update_var(var: &Variable, solution: &[f64]) {
@@ -54,8 +262,7 @@ fn update_var(var: &Variable, solution: &[f64]) {
     let updated_content = match var {
         Variable::Vehicle2D(var) => {
             let mut updated_content: Vec<f64> = var
-                .pose
-                .borrow()
+                .pose()
                 .iter()
                 .zip(correction.iter())
                 .map(|(old, cor)| old + cor)
@@ -69,14 +276,13 @@ fn update_var(var: &Variable, solution: &[f64]) {
             updated_content
         }
         Variable::Landmark2D(var) => var
-            .position
-            .borrow()
+            .position()
             .iter()
             .zip(correction.iter())
             .map(|(old, cor)| old + cor)
             .collect(),
         Variable::Vehicle3D(var) => {
-            let old_iso = get_isometry(&*var.pose.borrow());
+            let old_iso = get_isometry(&var.pose());
             let cor_iso = get_isometry_normalized(correction);
             let new_iso = old_iso * cor_iso;
             let mut updated_content = new_iso.translation.vector.data.as_slice().to_vec();
@@ -84,12 +290,12 @@ fn update_var(var: &Variable, solution: &[f64]) {
             updated_content
         }
         Variable::Landmark3D(var) => var
-            .position
-            .borrow()
+            .position()
             .iter()
             .zip(correction.iter())
             .map(|(old, cor)| old + cor)
             .collect(),
+        Variable::Switch(var) => vec![(var.value() + correction[0]).clamp(0.0, 1.0)],
     };
     var.set_content(updated_content);
 }
@@ -97,11 +303,16 @@ fn update_var(var: &Variable, solution: &[f64]) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::optimizer::solver::conjugate_gradient::ConjugateGradientParams;
+    use crate::optimizer::solver::sparse_cholesky::SparseCholeskySolver;
+    use crate::optimizer::sparse_matrix::SparseMatrix;
     use crate::parser::g2o::G2oParser;
     use crate::parser::model::FactorGraphModel;
     use crate::parser::Parser;
+    use std::convert::TryInto;
 
     use log::LevelFilter;
+    use nalgebra::{DMatrix, DVector};
 
     fn init() {
         let _ = env_logger::builder()
@@ -114,8 +325,12 @@ mod tests {
         init();
         let test_factor_graph =
             G2oParser::parse_file(&["data_files/optimizer_tests/", file_name, "_0.g2o"].concat()).unwrap();
-        optimize(&test_factor_graph, iterations);
-        let test_model = FactorGraphModel::from(&test_factor_graph);
+        optimize(&test_factor_graph, iterations).unwrap();
+        test_valid_optimization_result(&test_factor_graph, file_name, iterations);
+    }
+
+    fn test_valid_optimization_result(test_factor_graph: &FactorGraph, file_name: &str, iterations: usize) {
+        let test_model = FactorGraphModel::from(test_factor_graph);
         let expected_model = G2oParser::parse_file_to_model(
             &[
                 "data_files/optimizer_tests/",
@@ -246,6 +461,186 @@ mod tests {
         test_valid_optimization("full2d", 25);
     }
 
+    #[test]
+    fn test_termination_by_convergence() {
+        init();
+        let factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+        let settings = OptimizerSettings {
+            iterations: 100,
+            termination_criteria: TerminationCriteria {
+                min_relative_chi2_decrease: Some(1e-6),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = optimize_with_settings(&factor_graph, &settings).unwrap();
+        assert_eq!(report.termination_reason, TerminationReason::RelativeChi2Decrease);
+        assert!(report.iterations.len() < 100);
+        assert!(report.final_chi2 < report.initial_chi2);
+    }
+
+    #[test]
+    fn test_all_solver_types() {
+        init();
+        [
+            SolverType::DenseLdlt,
+            SolverType::SparseCholesky,
+            SolverType::ConjugateGradient(ConjugateGradientParams {
+                tolerance: 1e-14,
+                max_iterations: Some(10000),
+                ..Default::default()
+            }),
+        ]
+        .iter()
+        .for_each(|solver| {
+            let factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+            let settings = OptimizerSettings {
+                iterations: 1,
+                solver: solver.clone(),
+                ..Default::default()
+            };
+            let report = optimize_with_settings(&factor_graph, &settings).unwrap();
+            test_valid_optimization_result(&factor_graph, "full2d", 1);
+            assert_eq!(
+                report.iterations[0].solver_residual.is_some(),
+                matches!(solver, SolverType::ConjugateGradient(_))
+            );
+
+            // positive-definite with pivots from 2e6 down to about 1.3e-6
+            #[rustfmt::skip]
+            let H = DMatrix::from_vec(3, 3, vec![
+                2e6, -1e3, 0.0, // transposed H is displayed
+                -1e3, 2.0, -1e-3,
+                0.0, -1e-3, 2e-6,
+            ]);
+            let expected = DVector::from_vec(vec![1.0, 2.0, 3.0]);
+            let x = solver.create().solve(&SparseMatrix::from(H.clone()), &(H * &expected)).unwrap();
+            (0..3).for_each(|i| assert!(approx::relative_eq!(x[i], expected[i], max_relative = 1e-6)));
+        });
+    }
+
+    #[test]
+    fn test_schur_complement() {
+        init();
+        ["obs2d_mainly", "obs3d_mainly", "full2d"].iter().for_each(|file_name| {
+            let factor_graph =
+                G2oParser::parse_file(&["data_files/optimizer_tests/", file_name, "_0.g2o"].concat()).unwrap();
+            let settings = OptimizerSettings {
+                iterations: 1,
+                schur_complement: true,
+                ..Default::default()
+            };
+            optimize_with_settings(&factor_graph, &settings).unwrap();
+            test_valid_optimization_result(&factor_graph, file_name, 1);
+        });
+    }
+
+    #[test]
+    fn test_custom_solver_instance() {
+        init();
+        let factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+        let mut solver = SparseCholeskySolver::default();
+        optimize_with_solver(&factor_graph, &OptimizerSettings::default(), &mut solver).unwrap();
+        let (H, _) = calculate_H_b(&factor_graph);
+        assert!(solver.is_analyzed(&H));
+    }
+
+    #[test]
+    fn test_termination_by_time_budget() {
+        init();
+        let factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+        let settings = OptimizerSettings {
+            iterations: 100,
+            termination_criteria: TerminationCriteria {
+                time_budget: Some(std::time::Duration::from_secs(0)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = optimize_with_settings(&factor_graph, &settings).unwrap();
+        assert_eq!(report.termination_reason, TerminationReason::TimeBudget);
+        assert_eq!(report.iterations.len(), 1);
+        test_valid_optimization_result(&factor_graph, "full2d", 1);
+    }
+
+    fn optimize_outlier_graph(kernel_line: &str) -> f64 {
+        let factor_graph: FactorGraph = G2oParser::parse_string_to_model(
+            &[
+                "VERTEX_SE2 0 0.0 0.0 0.0\n\
+                 FIX 0\n\
+                 VERTEX_SE2 1 1.0 0.0 0.0\n\
+                 VERTEX_SE2 2 2.0 0.0 0.0\n\
+                 EDGE_SE2 0 1 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+                 EDGE_SE2 1 2 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+                 EDGE_SE2 0 2 5.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n",
+                kernel_line,
+            ]
+            .concat(),
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        optimize(&factor_graph, 20).unwrap();
+        factor_graph
+            .get_var(factor_graph.custom_to_csr_id_map[&2])
+            .get_content()[0]
+    }
+
+    #[test]
+    fn test_robust_kernel_reduces_outlier_influence() {
+        init();
+        let plain_x = optimize_outlier_graph("");
+        assert!(approx::relative_eq!(plain_x, 4.0, epsilon = 1e-6));
+        ["Huber", "Cauchy", "Tukey", "GemanMcClure", "DCS"]
+            .iter()
+            .for_each(|kernel_type| {
+                let robust_x = optimize_outlier_graph(&["ROBUST_KERNEL ", kernel_type, " 0.5\n"].concat());
+                assert!(
+                    robust_x < 3.0,
+                    "{} kernel moved vertex 2 to x = {}",
+                    kernel_type,
+                    robust_x
+                );
+            });
+    }
+
+    #[test]
+    fn test_switchable_constraint_disables_outlier() {
+        init();
+        let factor_graph: FactorGraph = G2oParser::parse_string_to_model(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             FIX 0\n\
+             VERTEX_SE2 1 1.0 0.0 0.0\n\
+             VERTEX_SE2 2 2.0 0.0 0.0\n\
+             VERTEX_SWITCH 3 1.0\n\
+             EDGE_SE2 0 1 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2 1 2 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2_SWITCHABLE 0 2 3 5.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SWITCH_PRIOR 3 1.0 1.0\n",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let initial_chi2 = calculate_chi2(&factor_graph);
+        let report = optimize_with_settings(
+            &factor_graph,
+            &OptimizerSettings {
+                iterations: 20,
+                algorithm: Algorithm::LevenbergMarquardt(LevenbergMarquardtParams::default()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(report.final_chi2 < initial_chi2);
+        let get_content = |id: usize| {
+            factor_graph
+                .get_var(factor_graph.custom_to_csr_id_map[&id])
+                .get_content()
+        };
+        assert!(get_content(3)[0] < 0.5, "switch value {}", get_content(3)[0]);
+        assert!(get_content(2)[0] < 3.0, "vertex 2 at x = {}", get_content(2)[0]);
+    }
+
     #[test]
     fn test_only_pos3d_factors() {
         test_valid_optimization("pos3d_only", 1);
@@ -0,0 +1,294 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Fill-reducing orderings of the variables, applied to the linear system before it is solved.
+
+#![allow(non_snake_case)]
+
+use crate::error::Error;
+use crate::factor_graph::variable::{FixedType, Variable};
+use crate::factor_graph::FactorGraph;
+use crate::optimizer::solver::Solver;
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::DVector;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap};
+use std::ops::Range;
+
+/// Order in which the variables are eliminated when decomposing H.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VariableOrdering {
+    /// Keeps the order in which the variables were parsed. Default, as it reproduces previous results exactly.
+    Natural,
+    /// Greedily eliminates the variable with the fewest neighbors first. Reduces the fill-in of the Cholesky factor,
+    /// especially for graphs with loop closures or landmarks observed from many poses.
+    MinimumDegree,
+}
+
+impl Default for VariableOrdering {
+    fn default() -> Self {
+        VariableOrdering::Natural
+    }
+}
+
+/// Permutation of the rows and columns of a factor graph's linear system.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Permutation {
+    new_indices: Vec<usize>,
+}
+
+impl Permutation {
+    /// Calculates the permutation of the given factor graph's linear system using the given ordering.
+    ///
+    /// Only depends on the structure of the factor graph, so it can be reused across iterations.
+    pub fn new(factor_graph: &FactorGraph, ordering: VariableOrdering) -> Self {
+        let (ranges, block_indices) = get_blocks(factor_graph);
+        let block_order = match ordering {
+            VariableOrdering::Natural => (0..ranges.len()).collect(),
+            VariableOrdering::MinimumDegree => {
+                calc_minimum_degree_order(get_adjacency(factor_graph, &block_indices, ranges.len()))
+            }
+        };
+        let mut new_indices = vec![0; factor_graph.matrix_dim];
+        block_order
+            .into_iter()
+            .flat_map(|block| ranges[block].clone())
+            .enumerate()
+            .for_each(|(new_index, old_index)| new_indices[old_index] = new_index);
+        Permutation { new_indices }
+    }
+
+    /// Returns the permutation with the rows of all non-fixed landmark variables moved behind those of all other
+    /// variables, keeping the relative order within both groups. Also returns the number of rows of the other variables.
+    pub fn with_landmarks_last(&self, factor_graph: &FactorGraph) -> (Permutation, usize) {
+        let mut is_landmark = vec![false; self.new_indices.len()];
+        factor_graph
+            .node_indices
+            .iter()
+            .map(|i| factor_graph.get_var(*i))
+            .filter(|var| matches!(var, Variable::Landmark2D(_) | Variable::Landmark3D(_)))
+            .for_each(|var| {
+                if let FixedType::NonFixed(range) = var.get_fixed_type() {
+                    range.clone().for_each(|i| is_landmark[i] = true);
+                }
+            });
+        let mut old_indices: Vec<usize> = (0..self.new_indices.len()).collect();
+        old_indices.sort_by_key(|i| (is_landmark[*i], self.new_indices[*i]));
+        let mut new_indices = vec![0; self.new_indices.len()];
+        old_indices
+            .iter()
+            .enumerate()
+            .for_each(|(new_index, old_index)| new_indices[*old_index] = new_index);
+        let other_dim = is_landmark.iter().filter(|is_landmark| !**is_landmark).count();
+        (Permutation { new_indices }, other_dim)
+    }
+
+    /// Returns H with its rows and columns reordered.
+    pub fn permute_matrix(&self, H: &SparseMatrix) -> SparseMatrix {
+        H.permuted(&self.new_indices)
+    }
+
+    /// Returns b with its entries reordered.
+    pub fn permute_vector(&self, b: &DVector<f64>) -> DVector<f64> {
+        let mut permuted = DVector::zeros(b.len());
+        self.new_indices
+            .iter()
+            .enumerate()
+            .for_each(|(old_index, new_index)| permuted[*new_index] = b[old_index]);
+        permuted
+    }
+
+    /// Returns the index of the given row of H within the reordered linear system.
+    pub fn permute_index(&self, index: usize) -> usize {
+        self.new_indices[index]
+    }
+
+    /// Returns a solution of the reordered linear system in the original order.
+    pub fn restore(&self, solution: &[f64]) -> Vec<f64> {
+        self.new_indices.iter().map(|new_index| solution[*new_index]).collect()
+    }
+
+    /// Solves H*x = b with the given solver after reordering the linear system. Returns x in the original order.
+    pub fn solve(&self, solver: &mut dyn Solver, H: &SparseMatrix, b: &DVector<f64>) -> Result<Vec<f64>, Error> {
+        solver
+            .solve(&self.permute_matrix(H), &self.permute_vector(b))
+            .map(|solution| self.restore(&solution))
+    }
+}
+
+/// Returns the ranges of all non-fixed variables ordered by their position in H,
+/// as well as the index of each variable's range by the variable's CSR index.
+fn get_blocks(factor_graph: &FactorGraph) -> (Vec<Range<usize>>, Vec<Option<usize>>) {
+    let mut ranges: Vec<(Range<usize>, usize)> = factor_graph
+        .node_indices
+        .iter()
+        .filter_map(|i| match factor_graph.get_var(*i).get_fixed_type() {
+            FixedType::NonFixed(range) => Some((range.clone(), *i)),
+            FixedType::Fixed => None,
+        })
+        .collect();
+    ranges.sort_by_key(|(range, _)| range.start);
+    let mut block_indices = vec![None; factor_graph.csr.node_count()];
+    ranges
+        .iter()
+        .enumerate()
+        .for_each(|(block, (_, i))| block_indices[*i] = Some(block));
+    (ranges.into_iter().map(|(range, _)| range).collect(), block_indices)
+}
+
+/// Returns the neighbors of every block, i.e. the non-zero off-diagonal blocks of H.
+fn get_adjacency(
+    factor_graph: &FactorGraph,
+    block_indices: &[Option<usize>],
+    block_count: usize,
+) -> Vec<BTreeSet<usize>> {
+    let mut adjacency = vec![BTreeSet::new(); block_count];
+    factor_graph.factors().for_each(|(_, indices)| {
+        let blocks: Vec<usize> = indices.iter().filter_map(|i| block_indices[*i]).collect();
+        for block_a in &blocks {
+            for block_b in blocks.iter().filter(|block_b| *block_b != block_a) {
+                adjacency[*block_a].insert(*block_b);
+            }
+        }
+    });
+    adjacency
+}
+
+/// Eliminates the blocks one by one, always choosing the block with the fewest remaining neighbors.
+/// The neighbors of an eliminated block become pairwise connected, mirroring the fill-in of the Cholesky factor.
+fn calc_minimum_degree_order(mut adjacency: Vec<BTreeSet<usize>>) -> Vec<usize> {
+    let mut eliminated = vec![false; adjacency.len()];
+    let mut queue: BinaryHeap<Reverse<(usize, usize)>> = adjacency
+        .iter()
+        .enumerate()
+        .map(|(block, neighbors)| Reverse((neighbors.len(), block)))
+        .collect();
+    let mut order = Vec::with_capacity(adjacency.len());
+    while let Some(Reverse((degree, block))) = queue.pop() {
+        // the queue may contain outdated degrees, which are skipped
+        if eliminated[block] || degree != adjacency[block].len() {
+            continue;
+        }
+        eliminated[block] = true;
+        order.push(block);
+        let neighbors = std::mem::take(&mut adjacency[block]);
+        for neighbor in &neighbors {
+            adjacency[*neighbor].remove(&block);
+            adjacency[*neighbor].extend(neighbors.iter().filter(|other| *other != neighbor));
+            queue.push(Reverse((adjacency[*neighbor].len(), *neighbor)));
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::linear_system::calculate_H_b;
+    use crate::optimizer::solver::sparse_cholesky::SparseCholeskySolver;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+    use nalgebra::CsCholesky;
+    use std::convert::TryInto;
+
+    use log::LevelFilter;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    fn get_cholesky_size(H: &SparseMatrix) -> usize {
+        CsCholesky::new(&H.to_cs_matrix()).l().unwrap().len()
+    }
+
+    #[test]
+    fn test_natural_is_identity() {
+        init();
+        let factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+        let (H, b) = calculate_H_b(&factor_graph);
+        let permutation = Permutation::new(&factor_graph, VariableOrdering::Natural);
+        assert_eq!(permutation.permute_matrix(&H), H);
+        assert_eq!(permutation.permute_vector(&b), b);
+    }
+
+    #[test]
+    fn test_landmarks_last() {
+        init();
+        let factor_graph: FactorGraph = G2oParser::parse_string_to_model(
+            "VERTEX_SE2 0 0.0 0.0 0.0\n\
+             VERTEX_XY 1 1.0 1.0\n\
+             VERTEX_SE2 2 1.0 0.0 0.0\n\
+             EDGE_SE2 0 2 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2_XY 0 1 1.0 1.0 1.0 0.0 1.0\n\
+             EDGE_SE2_XY 2 1 0.0 1.0 1.0 0.0 1.0\n",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+        let natural = Permutation::new(&factor_graph, VariableOrdering::Natural);
+        let (permutation, other_dim) = natural.with_landmarks_last(&factor_graph);
+        assert_eq!(other_dim, 6);
+        assert_eq!(permutation.new_indices, vec![0, 1, 2, 6, 7, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_minimum_degree_order() {
+        // a star with center 0 and a chain 3 - 4
+        let adjacency = vec![
+            [1, 2, 3].iter().copied().collect(),
+            [0].iter().copied().collect(),
+            [0].iter().copied().collect(),
+            [0, 4].iter().copied().collect(),
+            [3].iter().copied().collect(),
+        ];
+        assert_eq!(calc_minimum_degree_order(adjacency), vec![1, 2, 0, 3, 4]);
+    }
+
+    #[test]
+    fn test_minimum_degree_reduces_fill_in() {
+        init();
+        // a landmark parsed before all poses observing it
+        let mut g2o = String::from("VERTEX_XY 0 1.0 1.0\n");
+        (1..=5).for_each(|i| {
+            g2o.push_str(&format!("VERTEX_SE2 {} {}.0 0.0 0.0\n", i, i));
+            g2o.push_str(&format!(
+                "EDGE_PRIOR_SE2 {} {}.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\n",
+                i, i
+            ));
+            g2o.push_str(&format!("EDGE_SE2_XY {} 0 1.0 1.0 1.0 0.0 1.0\n", i));
+        });
+        let factor_graph: FactorGraph = G2oParser::parse_string_to_model(&g2o).unwrap().try_into().unwrap();
+        let (H, b) = calculate_H_b(&factor_graph);
+        let natural = Permutation::new(&factor_graph, VariableOrdering::Natural);
+        let minimum_degree = Permutation::new(&factor_graph, VariableOrdering::MinimumDegree);
+
+        let natural_size = get_cholesky_size(&natural.permute_matrix(&H));
+        let minimum_degree_size = get_cholesky_size(&minimum_degree.permute_matrix(&H));
+        assert!(
+            minimum_degree_size < natural_size,
+            "{} entries with minimum degree ordering versus {} with natural ordering",
+            minimum_degree_size,
+            natural_size
+        );
+
+        let mut solver = SparseCholeskySolver::default();
+        let natural_solution = natural.solve(&mut solver, &H, &b).unwrap();
+        let minimum_degree_solution = minimum_degree.solve(&mut solver, &H, &b).unwrap();
+        natural_solution
+            .iter()
+            .zip(minimum_degree_solution.iter())
+            .for_each(|(a, b)| assert!(approx::relative_eq!(a, b, epsilon = 1e-10)));
+    }
+}
@@ -0,0 +1,146 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Serializable statistics of an optimization run.
+
+use crate::optimizer::dogleg::DoglegTrial;
+use crate::optimizer::termination::TerminationReason;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Structure summarizing an optimization run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimizationReport {
+    /// The total chi² before the first iteration.
+    pub initial_chi2: f64,
+    /// The total chi² after the last iteration.
+    pub final_chi2: f64,
+    /// The statistics of all performed iterations.
+    pub iterations: Vec<IterationStatistics>,
+    /// The reason why the optimization stopped.
+    pub termination_reason: TerminationReason,
+    /// The duration of the entire optimization.
+    pub total_time: Duration,
+    /// Warnings about the factor graph, e.g. about parts of it which had to be anchored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Structure containing the statistics of a single iteration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IterationStatistics {
+    /// The total chi² before the iteration.
+    pub chi2_before: f64,
+    /// The total chi² after the iteration.
+    pub chi2_after: f64,
+    /// The largest absolute entry of the applied update. Zero if no update has been applied.
+    pub update_norm: f64,
+    /// The norm of b, i.e. of the gradient, at the start of the iteration.
+    pub gradient_norm: f64,
+    /// Whether an update has been applied.
+    pub accepted: bool,
+    /// The damping factor lambda of the last step tried by Levenberg-Marquardt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub damping: Option<f64>,
+    /// The trust radius of the last step tried by dogleg.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust_radius: Option<f64>,
+    /// All steps tried by dogleg. Only the last step may have been accepted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dogleg_trials: Vec<DoglegTrial>,
+    /// The time spent on building the linear system.
+    pub linear_system_time: Duration,
+    /// The time spent on solving the linear system, summed up over all steps tried.
+    pub solver_time: Duration,
+    /// The norm of the residual of the last linear system solved within the iteration, if reported by the solver.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub solver_residual: Option<f64>,
+}
+
+impl IterationStatistics {
+    /// Returns the statistics of an iteration which has not applied an update.
+    pub fn new(chi2: f64, gradient_norm: f64) -> Self {
+        IterationStatistics {
+            chi2_before: chi2,
+            chi2_after: chi2,
+            update_norm: 0.0,
+            gradient_norm,
+            accepted: false,
+            damping: None,
+            trust_radius: None,
+            dogleg_trials: vec![],
+            linear_system_time: Duration::default(),
+            solver_time: Duration::default(),
+            solver_residual: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::dogleg::{DoglegDecision, DoglegStepType};
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut iteration = IterationStatistics::new(10.0, 2.0);
+        iteration.chi2_after = 5.0;
+        iteration.update_norm = 0.5;
+        iteration.accepted = true;
+        iteration.trust_radius = Some(1.0);
+        iteration.dogleg_trials.push(DoglegTrial {
+            step_type: DoglegStepType::Interpolated,
+            trust_radius: 1.0,
+            gauss_newton_available: true,
+            gain_ratio: Some(0.8),
+            decision: DoglegDecision::Accepted,
+        });
+        iteration.solver_time = Duration::from_millis(3);
+        let report = OptimizationReport {
+            initial_chi2: 10.0,
+            final_chi2: 5.0,
+            iterations: vec![iteration],
+            termination_reason: TerminationReason::MaxIterations,
+            total_time: Duration::from_millis(7),
+            warnings: vec![],
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.contains("\"terminationReason\":\"MaxIterations\""));
+        assert!(!json.contains("damping"));
+        assert_eq!(serde_json::from_str::<OptimizationReport>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn test_json_round_trip_without_gain_ratio() {
+        let mut iteration = IterationStatistics::new(10.0, 2.0);
+        iteration.dogleg_trials.push(DoglegTrial {
+            step_type: DoglegStepType::GaussNewton,
+            trust_radius: 1.0,
+            gauss_newton_available: true,
+            gain_ratio: None,
+            decision: DoglegDecision::RejectedNotFinite,
+        });
+        let report = OptimizationReport {
+            initial_chi2: 10.0,
+            final_chi2: 10.0,
+            iterations: vec![iteration],
+            termination_reason: TerminationReason::NoImprovement,
+            total_time: Duration::from_millis(7),
+            warnings: vec![],
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(!json.contains("gainRatio"));
+        assert_eq!(serde_json::from_str::<OptimizationReport>(&json).unwrap(), report);
+    }
+}
@@ -0,0 +1,511 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Fixed-lag smoothing over the most recent vehicle poses for long-running robots.
+//!
+//! Vehicle poses leaving the window are marginalized into a linearized prior on the remaining variables,
+//! which is the Schur complement of the marginalized variables in H, so that the factor graph does not grow over time.
+
+#![allow(non_snake_case)]
+
+use crate::error::Error;
+use crate::factor_graph::factor::{Factor, FactorType, InformationMatrix};
+use crate::factor_graph::variable::{FixedType, Variable};
+use crate::factor_graph::FactorGraph;
+use crate::optimizer::linear_system::calculate_H_b;
+use crate::optimizer::report::OptimizationReport;
+use crate::optimizer::{optimize_with_settings, update_var, OptimizerSettings};
+use nalgebra::{DMatrix, DVector};
+use std::collections::BTreeSet;
+
+/// Singular values of H below this threshold are treated as zero when inverting during marginalization.
+const PSEUDO_INVERSE_EPSILON: f64 = 1e-9;
+
+/// Settings of the sliding window optimizer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlidingWindowSettings {
+    /// The maximum number of vehicle poses kept in the window.
+    pub window_size: usize,
+    /// The settings of the optimization after each advance of the window. Eliminating the landmarks via the Schur
+    /// complement fails as soon as a linearized prior connects several landmarks.
+    pub optimizer_settings: OptimizerSettings,
+}
+
+impl Default for SlidingWindowSettings {
+    fn default() -> Self {
+        SlidingWindowSettings {
+            window_size: 10,
+            optimizer_settings: OptimizerSettings::default(),
+        }
+    }
+}
+
+/// Optimizer keeping only the most recent vehicle poses of its factor graph, together with the variables connected
+/// to them and a linearized prior summarizing all marginalized variables.
+pub struct SlidingWindowOptimizer {
+    factor_graph: FactorGraph,
+    settings: SlidingWindowSettings,
+}
+
+impl SlidingWindowOptimizer {
+    /// Returns an optimizer starting with the given factor graph, which may be empty.
+    pub fn new(factor_graph: FactorGraph, settings: SlidingWindowSettings) -> Self {
+        SlidingWindowOptimizer { factor_graph, settings }
+    }
+
+    /// Adds the given factors and variables, optimizes the factor graph and then marginalizes the oldest vehicle poses
+    /// exceeding the window size.
+    ///
+    /// The factors are given together with the custom IDs of their variables as in FactorGraph::add_factor(),
+    /// so they must not refer to marginalized variables.
+    pub fn advance(
+        &mut self,
+        new_factors: Vec<(Factor, Vec<usize>)>,
+        new_variables: Vec<Variable>,
+    ) -> Result<OptimizationReport, Error> {
+        for variable in new_variables {
+            self.factor_graph.add_variable(variable)?;
+        }
+        for (factor, variable_ids) in new_factors {
+            self.factor_graph.add_factor(factor, &variable_ids)?;
+        }
+        let report = optimize_with_settings(&self.factor_graph, &self.settings.optimizer_settings)?;
+        let window = self.window();
+        if window.len() > self.settings.window_size {
+            marginalize(
+                &mut self.factor_graph,
+                &window[..window.len() - self.settings.window_size],
+            )?;
+        }
+        Ok(report)
+    }
+
+    /// Returns the custom IDs of the vehicle poses within the window, from the oldest to the most recent one.
+    pub fn window(&self) -> Vec<usize> {
+        self.factor_graph
+            .node_indices
+            .iter()
+            .map(|i| self.factor_graph.get_var(*i))
+            .filter(|var| matches!(var, Variable::Vehicle2D(_) | Variable::Vehicle3D(_)))
+            .map(|var| var.get_id())
+            .collect()
+    }
+
+    /// Returns the factor graph of the window.
+    pub fn factor_graph(&self) -> &FactorGraph {
+        &self.factor_graph
+    }
+
+    /// Consumes the optimizer and returns the factor graph of the window.
+    pub fn into_factor_graph(self) -> FactorGraph {
+        self.factor_graph
+    }
+}
+
+/// Marginalizes the variables with the given custom IDs at their current contents.
+///
+/// All factors of the marginalized variables, including existing linearized priors, are replaced by a single linearized
+/// prior on their remaining non-fixed variables. Other variables which are no vehicle poses and would only be
+/// connected to the marginalized variables, such as landmarks only observed from them, are marginalized as well.
+pub fn marginalize(factor_graph: &mut FactorGraph, ids: &[usize]) -> Result<(), Error> {
+    let mut marginalized = ids
+        .iter()
+        .map(|id| {
+            factor_graph
+                .custom_to_csr_id_map
+                .get(id)
+                .copied()
+                .ok_or(Error::UnknownVertexId(*id))
+        })
+        .collect::<Result<BTreeSet<usize>, Error>>()?;
+    add_dangling_variables(factor_graph, &mut marginalized);
+
+    let factors: Vec<(Factor, Vec<usize>)> = factor_graph
+        .factors()
+        .filter(|(_, indices)| indices.iter().any(|index| marginalized.contains(index)))
+        .map(|(factor, indices)| {
+            let factor = Factor {
+                robust_kernel: factor_graph.get_robust_kernel(factor),
+                ..factor.clone()
+            };
+            (factor, indices)
+        })
+        .collect();
+    let involved: BTreeSet<usize> = factors.iter().flat_map(|(_, indices)| indices.clone()).collect();
+    let remaining: Vec<usize> = involved
+        .iter()
+        .copied()
+        .filter(|index| !marginalized.contains(index))
+        .filter(|index| factor_graph.get_var(*index).get_fixed_type() != &FixedType::Fixed)
+        .collect();
+
+    // the remaining variables are added first to occupy the upper left block of the local H
+    let mut local_graph = FactorGraph::default();
+    for index in remaining
+        .iter()
+        .chain(involved.iter().filter(|index| !remaining.contains(index)))
+    {
+        local_graph.add_variable(factor_graph.get_var(*index).clone())?;
+    }
+    for (factor, indices) in factors {
+        let ids: Vec<usize> = indices
+            .iter()
+            .map(|index| factor_graph.get_var(*index).get_id())
+            .collect();
+        local_graph.add_factor(factor, &ids)?;
+    }
+    let prior = if remaining.is_empty() {
+        None
+    } else {
+        Some(calc_linearized_prior(&local_graph, remaining.len())?)
+    };
+
+    // the internal indices change when removing variables, but the custom IDs do not
+    let remaining_ids: Vec<usize> = remaining
+        .iter()
+        .map(|index| factor_graph.get_var(*index).get_id())
+        .collect();
+    let marginalized_ids: Vec<usize> = marginalized
+        .iter()
+        .map(|index| factor_graph.get_var(*index).get_id())
+        .collect();
+    for id in marginalized_ids {
+        factor_graph.remove_variable(id)?;
+    }
+    if let Some(prior) = prior {
+        factor_graph.add_factor(prior, &remaining_ids)?;
+    }
+    Ok(())
+}
+
+/// Returns the linearized prior on the given number of leading variables of the local factor graph, which have to be
+/// non-fixed and occupy the leading rows of H, after marginalizing all other variables.
+fn calc_linearized_prior(local_graph: &FactorGraph, prior_var_count: usize) -> Result<Factor, Error> {
+    let (H, b) = calculate_H_b(local_graph);
+    let H = H.to_dense();
+    let prior_vars: Vec<&Variable> = local_graph.node_indices[..prior_var_count]
+        .iter()
+        .map(|i| local_graph.get_var(*i))
+        .collect();
+    let dim: usize = prior_vars.iter().map(|var| var.get_dim()).sum();
+    let other_dim = H.nrows() - dim;
+    let mut prior_H = H.slice((0, 0), (dim, dim)).into_owned();
+    let mut prior_b = b.rows(0, dim).into_owned();
+    if other_dim > 0 {
+        let H_mm_inv = pseudo_inverse(H.slice((dim, dim), (other_dim, other_dim)).into_owned())?;
+        let H_rm = H.slice((0, dim), (dim, other_dim));
+        let H_rm_H_mm_inv = H_rm * H_mm_inv;
+        prior_H -= &H_rm_H_mm_inv * H_rm.transpose();
+        prior_b -= H_rm_H_mm_inv * b.rows(dim, other_dim);
+    }
+    let prior_H = project_to_positive_semi_definite(prior_H);
+
+    // the mean is the linearization point shifted by the update minimizing the marginalized system
+    let shift: DVector<f64> = -(pseudo_inverse(prior_H.clone())? * prior_b);
+    let mut solution = vec![0.0; H.nrows()];
+    solution[..dim]
+        .iter_mut()
+        .zip(shift.iter())
+        .for_each(|(entry, value)| *entry = *value);
+    let mean = prior_vars
+        .iter()
+        .flat_map(|var| {
+            let var = (*var).clone();
+            update_var(&var, &solution);
+            var.get_content()
+        })
+        .collect();
+    Ok(Factor {
+        factor_type: FactorType::LinearizedPrior,
+        constraint: mean,
+        information_matrix: InformationMatrix { content: prior_H },
+        robust_kernel: None,
+        switch_index: None,
+        prior_indices: vec![],
+    })
+}
+
+/// Symmetrizes the given matrix and sets its negative eigenvalues, which stem from rounding errors, to zero.
+fn project_to_positive_semi_definite(matrix: DMatrix<f64>) -> DMatrix<f64> {
+    let eigen = ((&matrix + matrix.transpose()) * 0.5).symmetric_eigen();
+    let eigenvalues = eigen.eigenvalues.map(|value| value.max(0.0));
+    let projected = &eigen.eigenvectors * DMatrix::from_diagonal(&eigenvalues) * eigen.eigenvectors.transpose();
+    (&projected + projected.transpose()) * 0.5
+}
+
+fn pseudo_inverse(matrix: DMatrix<f64>) -> Result<DMatrix<f64>, Error> {
+    matrix
+        .pseudo_inverse(PSEUDO_INVERSE_EPSILON)
+        .map_err(|message| Error::SolverFailure(String::from(message)))
+}
+
+/// Adds all variables which are no vehicle poses and whose factors, apart from linearized priors,
+/// only connect them to marginalized variables.
+fn add_dangling_variables(factor_graph: &FactorGraph, marginalized: &mut BTreeSet<usize>) {
+    let var_count = factor_graph.node_indices.len();
+    let (mut touches_marginalized, mut touches_others) = (vec![false; var_count], vec![false; var_count]);
+    factor_graph
+        .factors()
+        .filter(|(factor, _)| factor.factor_type != FactorType::LinearizedPrior)
+        .for_each(|(_, indices)| {
+            for index in &indices {
+                for other in indices.iter().filter(|other| *other != index) {
+                    if marginalized.contains(other) {
+                        touches_marginalized[*index] = true;
+                    } else {
+                        touches_others[*index] = true;
+                    }
+                }
+            }
+        });
+    let dangling: Vec<usize> = factor_graph
+        .node_indices
+        .iter()
+        .copied()
+        .filter(|index| touches_marginalized[*index] && !touches_others[*index])
+        .filter(|index| {
+            !matches!(
+                factor_graph.get_var(*index),
+                Variable::Vehicle2D(_) | Variable::Vehicle3D(_)
+            )
+        })
+        .collect();
+    marginalized.extend(dangling);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::covariance::CovarianceRecovery;
+    use crate::optimizer::evaluation::calculate_chi2;
+    use crate::optimizer::optimize;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::model::FactorGraphModel;
+    use crate::parser::Parser;
+    use log::LevelFilter;
+    use std::convert::TryFrom;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    type Step = (Vec<(Factor, Vec<usize>)>, Vec<Variable>);
+
+    /// Splits the given factor graph into the steps of a robot driving along its vehicle poses. Each step contains a new
+    /// vehicle pose, the variables connected to it for the first time and all factors between these and earlier ones.
+    fn get_steps(factor_graph: &FactorGraph) -> Vec<Step> {
+        let is_vehicle = |index: usize| {
+            matches!(
+                factor_graph.get_var(index),
+                Variable::Vehicle2D(_) | Variable::Vehicle3D(_)
+            )
+        };
+        let mut step_indices: Vec<Option<usize>> = vec![None; factor_graph.node_indices.len()];
+        factor_graph
+            .node_indices
+            .iter()
+            .filter(|index| is_vehicle(**index))
+            .enumerate()
+            .for_each(|(step, index)| step_indices[*index] = Some(step));
+        let edges: Vec<(&Factor, Vec<usize>)> = factor_graph.factors().collect();
+        edges.iter().for_each(|(_, indices)| {
+            let step = indices.iter().filter_map(|index| step_indices[*index]).max();
+            indices
+                .iter()
+                .filter(|index| !is_vehicle(**index))
+                .for_each(|index| step_indices[*index] = step_indices[*index].min(step).or(step));
+        });
+        let mut steps: Vec<Step> = vec![(vec![], vec![]); step_indices.iter().flatten().max().unwrap() + 1];
+        factor_graph.node_indices.iter().for_each(|index| {
+            steps[step_indices[*index].unwrap()]
+                .1
+                .push(factor_graph.get_var(*index).clone())
+        });
+        edges.into_iter().for_each(|(factor, indices)| {
+            let step = indices.iter().map(|index| step_indices[*index].unwrap()).max().unwrap();
+            let ids = indices
+                .iter()
+                .map(|index| factor_graph.get_var(*index).get_id())
+                .collect();
+            steps[step].0.push((factor.clone(), ids));
+        });
+        steps
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        init();
+        let factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+        let settings = SlidingWindowSettings {
+            window_size: 5,
+            ..Default::default()
+        };
+        let mut optimizer = SlidingWindowOptimizer::new(FactorGraph::default(), settings);
+        let mut batch_graph = FactorGraph::default();
+        for (factors, variables) in get_steps(&factor_graph) {
+            // factors of marginalized variables, e.g. loop closures, cannot be added anymore
+            let known_ids: Vec<usize> = optimizer
+                .factor_graph()
+                .custom_to_csr_id_map
+                .keys()
+                .copied()
+                .chain(variables.iter().map(|var| var.get_id()))
+                .collect();
+            let factors: Vec<(Factor, Vec<usize>)> = factors
+                .into_iter()
+                .filter(|(_, ids)| ids.iter().all(|id| known_ids.contains(id)))
+                .collect();
+            variables.iter().for_each(|var| {
+                batch_graph.add_variable(var.clone()).unwrap();
+            });
+            factors.iter().for_each(|(factor, ids)| {
+                batch_graph.add_factor(factor.clone(), ids).unwrap();
+            });
+            optimizer.advance(factors, variables).unwrap();
+            assert!(optimizer.window().len() <= 5);
+            assert!(optimizer.factor_graph().node_indices.len() < 15);
+        }
+        optimize(&batch_graph, 10).unwrap();
+        let window_graph = optimizer.factor_graph();
+        assert_eq!(optimizer.window(), vec![46, 47, 48, 49, 50]);
+        optimizer.window().iter().for_each(|id| {
+            let window_content = get_content(window_graph, *id);
+            let batch_content = get_content(&batch_graph, *id);
+            assert_approx_equal(&window_content, &batch_content, 1e-3);
+        });
+    }
+
+    /// Checks that marginalizing the given variables of the optimized factor graph neither changes the covariances
+    /// nor the optimum of the given remaining variables.
+    fn check_marginalization(file_path: &str, marginalized_ids: &[usize], remaining_ids: &[usize]) -> FactorGraph {
+        let mut factor_graph = G2oParser::parse_file(file_path).unwrap();
+        optimize(&factor_graph, 10).unwrap();
+        let recovery = CovarianceRecovery::new(&factor_graph).unwrap();
+        let covariances: Vec<DMatrix<f64>> = remaining_ids
+            .iter()
+            .map(|id| recovery.marginal_covariance(*id).unwrap())
+            .collect();
+        let contents: Vec<Vec<f64>> = remaining_ids.iter().map(|id| get_content(&factor_graph, *id)).collect();
+
+        marginalize(&mut factor_graph, marginalized_ids).unwrap();
+        let recovery = CovarianceRecovery::new(&factor_graph).unwrap();
+        remaining_ids
+            .iter()
+            .zip(covariances.iter())
+            .for_each(|(id, covariance)| {
+                let marginalized_covariance = recovery.marginal_covariance(*id).unwrap();
+                assert_approx_equal(marginalized_covariance.as_slice(), covariance.as_slice(), 1e-7);
+            });
+        optimize(&factor_graph, 10).unwrap();
+        remaining_ids
+            .iter()
+            .zip(contents.iter())
+            .for_each(|(id, content)| assert_approx_equal(&get_content(&factor_graph, *id), content, 1e-6));
+        factor_graph
+    }
+
+    #[test]
+    fn test_marginalization_2d() {
+        init();
+        let factor_graph = check_marginalization(
+            "data_files/optimizer_tests/full2d_0.g2o",
+            &[40, 41, 42],
+            &[39, 43, 53, 63],
+        );
+        // landmarks only observed from the marginalized vehicle poses are marginalized as well
+        [40, 41, 42, 51, 52, 60, 61, 62]
+            .iter()
+            .for_each(|id| assert!(!factor_graph.custom_to_csr_id_map.contains_key(id)));
+        assert_eq!(factor_graph.node_indices.len(), 56);
+    }
+
+    #[test]
+    fn test_marginalization_3d() {
+        init();
+        let factor_graph = check_marginalization(
+            "data_files/optimizer_tests/obs3d_mainly_0.g2o",
+            &(2..40).collect::<Vec<usize>>(),
+            &[1, 40, 63],
+        );
+        assert_eq!(factor_graph.node_indices.len(), 26);
+    }
+
+    #[test]
+    fn test_model_with_linearized_prior() {
+        init();
+        let mut factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+        optimize(&factor_graph, 10).unwrap();
+        marginalize(&mut factor_graph, &[0, 1]).unwrap();
+        let model = FactorGraphModel::from(&factor_graph);
+        assert_eq!(
+            model
+                .edges
+                .iter()
+                .filter(|edge| edge.edge_type == "LinearizedPrior")
+                .map(|edge| edge.vertices.clone())
+                .collect::<Vec<Vec<usize>>>(),
+            vec![vec![2]]
+        );
+        let parsed_graph = FactorGraph::try_from(model).unwrap();
+        assert_eq!(parsed_graph.csr.edge_count(), factor_graph.csr.edge_count());
+        assert_eq!(parsed_graph.linearized_priors.len(), 1);
+        assert!(approx::relative_eq!(
+            calculate_chi2(&parsed_graph),
+            calculate_chi2(&factor_graph),
+            epsilon = 1e-9
+        ));
+        let (H, b) = calculate_H_b(&factor_graph);
+        let (parsed_H, parsed_b) = calculate_H_b(&parsed_graph);
+        assert_approx_equal(parsed_H.to_dense().as_slice(), H.to_dense().as_slice(), 1e-9);
+        assert_approx_equal(parsed_b.as_slice(), b.as_slice(), 1e-9);
+
+        let g2o_string = G2oParser::compose_model_to_string((&factor_graph).into()).unwrap();
+        let g2o_graph = FactorGraph::try_from(G2oParser::parse_string_to_model(&g2o_string).unwrap()).unwrap();
+        assert_eq!(g2o_graph.linearized_priors.len(), 1);
+        assert!(approx::relative_eq!(
+            calculate_chi2(&g2o_graph),
+            calculate_chi2(&factor_graph),
+            epsilon = 1e-9
+        ));
+
+        // the prior on vehicle pose 2 is kept when removing another variable, but not when removing pose 2 itself
+        factor_graph.remove_variable(3).unwrap();
+        assert_eq!(factor_graph.linearized_priors.len(), 1);
+        assert_eq!(
+            factor_graph.linearized_priors[0].prior_indices,
+            vec![factor_graph.custom_to_csr_id_map[&2]]
+        );
+        factor_graph.remove_variable(2).unwrap();
+        assert!(factor_graph.linearized_priors.is_empty());
+    }
+
+    fn get_content(factor_graph: &FactorGraph, id: usize) -> Vec<f64> {
+        factor_graph
+            .get_var(factor_graph.custom_to_csr_id_map[&id])
+            .get_content()
+    }
+
+    fn assert_approx_equal(actual: &[f64], expected: &[f64], epsilon: f64) {
+        assert_eq!(actual.len(), expected.len());
+        actual.iter().zip(expected.iter()).for_each(|(a, e)| {
+            assert!(
+                approx::relative_eq!(a, e, epsilon = epsilon, max_relative = 1e-6),
+                "{:?} instead of {:?}",
+                actual,
+                expected
+            )
+        });
+    }
+}
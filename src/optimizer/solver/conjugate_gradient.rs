@@ -0,0 +1,307 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Iterative solver for linear systems using the (preconditioned) method of conjugate gradients.
+
+#![allow(non_snake_case)]
+
+use crate::error::Error;
+use crate::optimizer::solver::{check_dimensions, Solver};
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::{DMatrix, DVector};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Approximation of the inverse of H applied to the residual in every iteration, improving convergence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preconditioner {
+    /// No preconditioning, i.e. the plain method of conjugate gradients.
+    Identity,
+    /// Inverts the diagonal block of every variable, i.e. the 3x3 or 6x6 blocks of 2D or 3D poses.
+    /// Falls back to the diagonal entries if H is not partitioned into blocks.
+    BlockJacobi,
+}
+
+/// Parameters of the conjugate gradient method.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConjugateGradientParams {
+    /// Stops as soon as the norm of the residual H*x - b is smaller than this fraction of the norm of b.
+    pub tolerance: f64,
+    /// The maximum number of iterations. If None, the dimension of H is used, which suffices in exact arithmetic,
+    /// but may need to be exceeded for badly conditioned H due to rounding errors.
+    pub max_iterations: Option<usize>,
+    /// The preconditioner applied in every iteration.
+    pub preconditioner: Preconditioner,
+}
+
+impl Default for ConjugateGradientParams {
+    fn default() -> Self {
+        ConjugateGradientParams {
+            tolerance: 1e-12,
+            max_iterations: None,
+            preconditioner: Preconditioner::BlockJacobi,
+        }
+    }
+}
+
+/// Structure describing how well the last linear system has been solved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConjugateGradientStatistics {
+    /// The number of performed iterations.
+    pub iterations: usize,
+    /// The norm of the residual H*x - b of the returned solution.
+    pub residual_norm: f64,
+    /// Whether the residual fell below the tolerance before the maximum number of iterations was reached.
+    pub converged: bool,
+}
+
+/// Implements the solver using the method of conjugate gradients, which never decomposes H.
+///
+/// Memory is linear in the number of non-zero entries of H. The solution is approximated iteratively,
+/// so it is less accurate than the solution of a direct solver.
+#[derive(Debug, Default)]
+pub struct ConjugateGradientSolver {
+    params: ConjugateGradientParams,
+    statistics: Option<ConjugateGradientStatistics>,
+}
+
+impl ConjugateGradientSolver {
+    pub fn new(params: ConjugateGradientParams) -> Self {
+        ConjugateGradientSolver {
+            params,
+            statistics: None,
+        }
+    }
+
+    /// Returns the statistics of the last call of solve(), or None if it has not been called successfully yet.
+    pub fn statistics(&self) -> Option<&ConjugateGradientStatistics> {
+        self.statistics.as_ref()
+    }
+}
+
+impl Solver for ConjugateGradientSolver {
+    /// Assumes that H is symmetric. Returns an error if H turns out not to be positive-definite.
+    fn solve(&mut self, H: &SparseMatrix, b: &DVector<f64>) -> Result<Vec<f64>, Error> {
+        check_dimensions(H, b)?;
+        self.statistics = None;
+        let preconditioner = match self.params.preconditioner {
+            Preconditioner::Identity => None,
+            Preconditioner::BlockJacobi => Some(BlockJacobi::new(H)?),
+        };
+        let precondition = |residual: &DVector<f64>| match &preconditioner {
+            Some(preconditioner) => preconditioner.apply(residual),
+            None => residual.clone(),
+        };
+        let threshold = self.params.tolerance * b.norm();
+        let max_iterations = self.params.max_iterations.unwrap_or_else(|| H.dim());
+        let mut x = DVector::zeros(b.len());
+        let mut residual = b.clone();
+        let mut preconditioned = precondition(&residual);
+        let mut direction = preconditioned.clone();
+        let mut residual_dot = residual.dot(&preconditioned);
+        let mut iterations = 0;
+        while residual.norm() > threshold && iterations < max_iterations {
+            let H_direction = H * &direction;
+            let curvature = direction.dot(&H_direction);
+            if curvature <= 0.0 {
+                return Err(Error::SolverFailure(String::from("H is not positive-definite")));
+            }
+            let alpha = residual_dot / curvature;
+            x += &direction * alpha;
+            residual -= H_direction * alpha;
+            preconditioned = precondition(&residual);
+            let new_residual_dot = residual.dot(&preconditioned);
+            direction = &preconditioned + direction * (new_residual_dot / residual_dot);
+            residual_dot = new_residual_dot;
+            iterations += 1;
+        }
+        self.statistics = Some(ConjugateGradientStatistics {
+            iterations,
+            residual_norm: residual.norm(),
+            converged: residual.norm() <= threshold,
+        });
+        Ok(x.data.into())
+    }
+
+    fn residual_norm(&self) -> Option<f64> {
+        self.statistics.as_ref().map(|statistics| statistics.residual_norm)
+    }
+}
+
+/// The inverted diagonal blocks of H.
+struct BlockJacobi {
+    blocks: Vec<(Range<usize>, DMatrix<f64>)>,
+}
+
+impl BlockJacobi {
+    fn new(H: &SparseMatrix) -> Result<Self, Error> {
+        let ranges: Vec<Range<usize>> = if H.blocks().is_empty() {
+            (0..H.dim()).map(|i| i..i + 1).collect()
+        } else {
+            H.blocks().to_vec()
+        };
+        let blocks = ranges
+            .into_iter()
+            .zip(H.diagonal_blocks())
+            .map(|(range, block)| match block.cholesky() {
+                Some(cholesky) => Ok((range, cholesky.inverse())),
+                None => Err(Error::SolverFailure(String::from("H is not positive-definite"))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(BlockJacobi { blocks })
+    }
+
+    fn apply(&self, residual: &DVector<f64>) -> DVector<f64> {
+        let mut preconditioned = DVector::zeros(residual.len());
+        self.blocks.iter().for_each(|(range, inverse)| {
+            preconditioned
+                .rows_mut(range.start, range.len())
+                .copy_from(&(inverse * residual.rows(range.start, range.len())));
+        });
+        preconditioned
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use approx::relative_eq;
+    use log::LevelFilter;
+    use nalgebra::{DMatrix, DVector};
+
+    use crate::optimizer::linear_system::calculate_H_b;
+    use crate::optimizer::solver::conjugate_gradient::{
+        ConjugateGradientParams, ConjugateGradientSolver, Preconditioner,
+    };
+    use crate::optimizer::solver::Solver;
+    use crate::optimizer::sparse_matrix::SparseMatrix;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::Parser;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    #[test]
+    fn solver_positive_definite_test() {
+        #[allow(non_snake_case)]
+        #[rustfmt::skip]
+        let positive_definite_H = vec![
+            2.0, -1.0, 0.0, // transposed H is displayed
+            -1.0, 2.0, -1.0,
+            0.0, -1.0, 2.0,
+        ];
+        let x = ConjugateGradientSolver::default()
+            .solve(
+                &SparseMatrix::from(DMatrix::from_vec(3, 3, positive_definite_H)),
+                &DVector::from_vec(vec![6.0, 6.0, 6.0]),
+            )
+            .unwrap();
+        assert!(relative_eq!(x[0], 9.0, epsilon = 1e-10));
+        assert!(relative_eq!(x[1], 12.0, epsilon = 1e-10));
+        assert!(relative_eq!(x[2], 9.0, epsilon = 1e-10));
+    }
+
+    #[test]
+    fn solver_not_positive_definite_test() {
+        #[allow(non_snake_case)]
+        #[rustfmt::skip]
+        let not_positive_definite_H = vec![
+            1.0, 0.0, 0.0, // transposed H is displayed
+            0.0, -1.0, 0.0,
+            0.0, 0.0, 1.0,
+        ];
+        let solve_output = ConjugateGradientSolver::default().solve(
+            &SparseMatrix::from(DMatrix::from_vec(3, 3, not_positive_definite_H)),
+            &DVector::from_vec(vec![1.0, 1.0, 1.0]),
+        );
+        assert!(solve_output.is_err());
+    }
+
+    #[test]
+    fn solver_max_iterations_test() {
+        let params = ConjugateGradientParams {
+            max_iterations: Some(0),
+            ..Default::default()
+        };
+        let mut solver = ConjugateGradientSolver::new(params);
+        let x = solver
+            .solve(
+                &SparseMatrix::from(DMatrix::identity(2, 2)),
+                &DVector::from_vec(vec![1.0, 1.0]),
+            )
+            .unwrap();
+        assert_eq!(x, vec![0.0, 0.0]);
+        let statistics = solver.statistics().unwrap();
+        assert_eq!(statistics.iterations, 0);
+        assert!(!statistics.converged);
+        assert_eq!(solver.residual_norm(), Some(2.0f64.sqrt()));
+    }
+
+    #[test]
+    fn solver_block_jacobi_test() {
+        #[allow(non_snake_case)]
+        #[rustfmt::skip]
+        let H = DMatrix::from_vec(4, 4, vec![
+            4.0, 1.0, 0.5, 0.0,
+            1.0, 3.0, 0.0, 0.5,
+            0.5, 0.0, 100.0, 10.0,
+            0.0, 0.5, 10.0, 50.0,
+        ]);
+        let b = DVector::from_vec(vec![1.0, 2.0, 3.0, 4.0]);
+        let expected = H.clone().cholesky().unwrap().solve(&b);
+        let mut sparse = SparseMatrix::from(H);
+        sparse.set_blocks(vec![0..2, 2..4]);
+        [Preconditioner::Identity, Preconditioner::BlockJacobi]
+            .iter()
+            .for_each(|preconditioner| {
+                let mut solver = ConjugateGradientSolver::new(ConjugateGradientParams {
+                    preconditioner: *preconditioner,
+                    ..Default::default()
+                });
+                let x = solver.solve(&sparse, &b).unwrap();
+                (0..4).for_each(|i| assert!(relative_eq!(x[i], expected[i], epsilon = 1e-10)));
+                let statistics = solver.statistics().unwrap();
+                assert!(statistics.converged);
+                assert!(statistics.residual_norm <= 1e-12 * b.norm());
+            });
+    }
+
+    #[test]
+    fn solver_block_jacobi_converges_faster_test() {
+        init();
+        let factor_graph = G2oParser::parse_file("data_files/optimizer_tests/full2d_0.g2o").unwrap();
+        let (H, b) = calculate_H_b(&factor_graph);
+        let iterations: Vec<usize> = [Preconditioner::Identity, Preconditioner::BlockJacobi]
+            .iter()
+            .map(|preconditioner| {
+                let mut solver = ConjugateGradientSolver::new(ConjugateGradientParams {
+                    tolerance: 1e-10,
+                    max_iterations: Some(10000),
+                    preconditioner: *preconditioner,
+                });
+                solver.solve(&H, &b).unwrap();
+                assert!(solver.statistics().unwrap().converged);
+                solver.statistics().unwrap().iterations
+            })
+            .collect();
+        assert!(
+            iterations[1] < iterations[0],
+            "{} iterations with block-Jacobi versus {} without",
+            iterations[1],
+            iterations[0]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Solver for linear systems using an LDLᵀ decomposition on a dense matrix.
+
+#![allow(non_snake_case)]
+
+use crate::error::Error;
+use crate::optimizer::solver::{check_dimensions, Solver};
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::{DMatrix, DVector};
+use std::cmp::Ordering;
+
+/// Implements the solver using the LDLᵀ decomposition on a dense matrix, avoiding square roots.
+///
+/// nalgebra does not provide an LDLᵀ decomposition, hence it is implemented here.
+/// Memory and time grow quadratically and cubically with the size of H, so it should only be used for small graphs.
+#[derive(Debug, Default)]
+pub struct DenseLdltSolver;
+
+impl Solver for DenseLdltSolver {
+    /// Assumes that H is symmetric. Only the lower triangle of H is read.
+    fn solve(&mut self, H: &SparseMatrix, b: &DVector<f64>) -> Result<Vec<f64>, Error> {
+        check_dimensions(H, b)?;
+        let (L, D) = decompose(&H.to_dense())?;
+        let n = b.len();
+        let mut x = b.clone();
+        for i in 0..n {
+            for k in 0..i {
+                x[i] -= L[(i, k)] * x[k];
+            }
+        }
+        for i in 0..n {
+            x[i] /= D[i];
+        }
+        for i in (0..n).rev() {
+            for k in i + 1..n {
+                x[i] -= L[(k, i)] * x[k];
+            }
+        }
+        Ok(x.data.into())
+    }
+}
+
+/// Returns the unit lower triangular matrix L and the diagonal D with H = L * D * Lᵀ.
+///
+/// Fails if a pivot is at most n * ε times its diagonal entry of H, i.e. not positive or only consisting of rounding
+/// errors as for singular H. The pivots are compared to their own diagonal entries, so that positive-definite
+/// matrices with widely spread scales are still decomposed.
+fn decompose(H: &DMatrix<f64>) -> Result<(DMatrix<f64>, DVector<f64>), Error> {
+    let n = H.nrows();
+    let mut L = DMatrix::identity(n, n);
+    let mut D = DVector::zeros(n);
+    for j in 0..n {
+        let LD_j: Vec<f64> = (0..j).map(|k| L[(j, k)] * D[k]).collect();
+        D[j] = H[(j, j)] - (0..j).map(|k| L[(j, k)] * LD_j[k]).sum::<f64>();
+        let tolerance = n as f64 * f64::EPSILON * H[(j, j)];
+        if D[j].partial_cmp(&tolerance) != Some(Ordering::Greater) {
+            return Err(Error::SolverFailure(String::from("H is not positive-definite")));
+        }
+        for i in j + 1..n {
+            L[(i, j)] = (H[(i, j)] - (0..j).map(|k| L[(i, k)] * LD_j[k]).sum::<f64>()) / D[j];
+        }
+    }
+    Ok((L, D))
+}
+
+#[cfg(test)]
+mod test {
+    use approx::relative_eq;
+    use nalgebra::{DMatrix, DVector};
+
+    use crate::optimizer::solver::dense_ldlt::DenseLdltSolver;
+    use crate::optimizer::solver::Solver;
+    use crate::optimizer::sparse_matrix::SparseMatrix;
+
+    #[test]
+    fn solver_positive_definite_test() {
+        #[allow(non_snake_case)]
+        #[rustfmt::skip]
+        let positive_definite_H = vec![
+            2.0, -1.0, 0.0, // transposed H is displayed
+            -1.0, 2.0, -1.0,
+            0.0, -1.0, 2.0,
+        ];
+        let x = DenseLdltSolver
+            .solve(
+                &SparseMatrix::from(DMatrix::from_vec(3, 3, positive_definite_H)),
+                &DVector::from_vec(vec![6.0, 6.0, 6.0]),
+            )
+            .unwrap();
+        assert!(relative_eq!(x[0], 9.0, epsilon = 1e-10));
+        assert!(relative_eq!(x[1], 12.0, epsilon = 1e-10));
+        assert!(relative_eq!(x[2], 9.0, epsilon = 1e-10));
+    }
+
+    #[test]
+    fn solver_not_positive_definite_test() {
+        #[allow(non_snake_case)]
+        #[rustfmt::skip]
+        let not_positive_definite_H = vec![
+            1.0, 2.0, 4.0, // transposed H is displayed
+            2.0, 3.0, 5.0,
+            4.0, 5.0, 6.0,
+        ];
+        let solve_output = DenseLdltSolver.solve(
+            &SparseMatrix::from(DMatrix::from_vec(3, 3, not_positive_definite_H)),
+            &DVector::from_vec(vec![6.0, 6.0, 6.0]),
+        );
+        assert!(solve_output.is_err());
+    }
+
+    #[test]
+    fn solver_equal_to_nalgebra_test() {
+        #[allow(non_snake_case)]
+        let A = DMatrix::from_fn(6, 6, |i, j| ((i * 7 + j * 3) % 5) as f64 - 2.0);
+        let scales = DVector::from_fn(6, |i, _| 10.0_f64.powi(3 * i as i32 - 8));
+        #[allow(non_snake_case)]
+        let H = DMatrix::from_diagonal(&scales)
+            * (&A * A.transpose() + DMatrix::identity(6, 6))
+            * DMatrix::from_diagonal(&scales);
+        let b = DVector::from_fn(6, |i, _| i as f64 + 1.0);
+        let x = DenseLdltSolver.solve(&SparseMatrix::from(H.clone()), &b).unwrap();
+        let expected = H.cholesky().unwrap().solve(&b);
+        (0..6).for_each(|i| assert!(relative_eq!(x[i], expected[i], max_relative = 1e-8)));
+    }
+
+    #[test]
+    fn solver_indefinite_test() {
+        #[allow(non_snake_case)]
+        #[rustfmt::skip]
+        let indefinite_H = DMatrix::from_vec(3, 3, vec![
+            4.0, 2.0, 0.0, // transposed H is displayed
+            2.0, 1.0, 3.0,
+            0.0, 3.0, 5.0,
+        ]);
+        assert!(indefinite_H.clone().cholesky().is_none());
+        let b = DVector::from_vec(vec![1.0, 1.0, 1.0]);
+        assert!(DenseLdltSolver.solve(&SparseMatrix::from(indefinite_H), &b).is_err());
+
+        #[allow(non_snake_case)]
+        let singular_H = DMatrix::from_vec(2, 2, vec![0.1, 0.3, 0.3, 0.9]);
+        let b = DVector::from_vec(vec![1.0, 1.0]);
+        assert!(DenseLdltSolver.solve(&SparseMatrix::from(singular_H), &b).is_err());
+    }
+}
@@ -14,13 +14,66 @@
 
 #![allow(non_snake_case)]
 
-use nalgebra::{DMatrix, DVector};
+use crate::error::Error;
+use crate::optimizer::solver::conjugate_gradient::{ConjugateGradientParams, ConjugateGradientSolver};
+use crate::optimizer::solver::dense_ldlt::DenseLdltSolver;
+use crate::optimizer::solver::sparse_cholesky::SparseCholeskySolver;
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::DVector;
 
+pub mod conjugate_gradient;
+pub mod dense_ldlt;
+pub mod schur_complement;
 pub mod sparse_cholesky;
 
-/// Trait which all solvers should implement.
+/// Trait which all solvers should implement. Solvers may keep state, e.g. caches, between calls.
 pub trait Solver {
     /// Solves the linear system defined by H*x = b.
-    /// H is expected column-by-column.
-    fn solve(H: DMatrix<f64>, b: &DVector<f64>) -> Result<Vec<f64>, String>;
+    fn solve(&mut self, H: &SparseMatrix, b: &DVector<f64>) -> Result<Vec<f64>, Error>;
+
+    /// Returns the norm of the residual H*x - b achieved by the last call of solve(),
+    /// if reported by the solver. Direct solvers do not report it.
+    fn residual_norm(&self) -> Option<f64> {
+        None
+    }
+}
+
+/// Returns an error if the dimensions of H and b differ.
+fn check_dimensions(H: &SparseMatrix, b: &DVector<f64>) -> Result<(), Error> {
+    if H.dim() != b.len() {
+        return Err(Error::DimensionMismatch {
+            context: String::from("Vector b of the linear system"),
+            expected: H.dim(),
+            actual: b.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Enum representing the solvers shipped with this crate, allowing to choose one at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SolverType {
+    /// LDLᵀ decomposition on a dense matrix. Fastest for small graphs.
+    DenseLdlt,
+    /// Cholesky decomposition on a sparse matrix, reusing its symbolic analysis across iterations.
+    SparseCholesky,
+    /// Iterative (preconditioned) conjugate gradient method, which never decomposes H. Suited for very large graphs.
+    ConjugateGradient(ConjugateGradientParams),
+}
+
+impl Default for SolverType {
+    fn default() -> Self {
+        SolverType::SparseCholesky
+    }
+}
+
+impl SolverType {
+    /// Returns a new instance of the corresponding solver.
+    pub fn create(&self) -> Box<dyn Solver> {
+        match self {
+            SolverType::DenseLdlt => Box::new(DenseLdltSolver),
+            SolverType::SparseCholesky => Box::new(SparseCholeskySolver::default()),
+            SolverType::ConjugateGradient(params) => Box::new(ConjugateGradientSolver::new(params.clone())),
+        }
+    }
 }
@@ -0,0 +1,206 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Elimination of landmark variables via the Schur complement before solving a linear system.
+
+#![allow(non_snake_case)]
+
+use crate::error::Error;
+use crate::optimizer::solver::{check_dimensions, Solver};
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::{DMatrix, DVector};
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+/// Solver eliminating the landmark variables via the Schur complement and solving the reduced system of all other
+/// variables with an inner solver. The landmarks are then recovered by back-substitution.
+///
+/// H = [A B; Bᵀ C] is expected to contain the rows of all landmarks behind those of all other variables,
+/// see Permutation::with_landmarks_last(). As no factor connects two landmarks, C is block-diagonal
+/// and can be inverted block by block, so the reduced system S = A - B * C⁻¹ * Bᵀ is cheap to calculate.
+pub struct SchurComplementSolver<'a> {
+    inner: &'a mut dyn Solver,
+    other_dim: usize,
+}
+
+/// A landmark's diagonal block of H and the off-diagonal entries connecting it to the other variables.
+struct LandmarkBlock {
+    range: Range<usize>,
+    C_inv: DMatrix<f64>,
+    other_rows: Vec<usize>,
+    B: DMatrix<f64>,
+}
+
+impl<'a> SchurComplementSolver<'a> {
+    /// Returns a solver eliminating all rows behind the first other_dim ones.
+    pub fn new(inner: &'a mut dyn Solver, other_dim: usize) -> Self {
+        SchurComplementSolver { inner, other_dim }
+    }
+
+    fn get_landmark_blocks(&self, H: &SparseMatrix) -> Result<Vec<LandmarkBlock>, Error> {
+        let ranges: Vec<Range<usize>> = if H.blocks().is_empty() {
+            (0..H.dim()).map(|i| i..i + 1).collect()
+        } else {
+            H.blocks().to_vec()
+        };
+        let mut block_indices = vec![None; H.dim()];
+        let mut landmark_ranges = vec![];
+        ranges
+            .iter()
+            .zip(H.diagonal_blocks())
+            .filter(|(range, _)| range.start >= self.other_dim)
+            .enumerate()
+            .for_each(|(block, (range, C))| {
+                range.clone().for_each(|i| block_indices[i] = Some(block));
+                landmark_ranges.push((range.clone(), C));
+            });
+        // a linearized prior may connect landmarks, in which case C is not block-diagonal
+        if H.entries().any(|(row, col, _)| {
+            row >= self.other_dim && col >= self.other_dim && block_indices[row] != block_indices[col]
+        }) {
+            return Err(Error::SolverFailure(String::from(
+                "Landmarks are connected with each other",
+            )));
+        }
+
+        let mut other_rows = vec![BTreeSet::new(); landmark_ranges.len()];
+        let B_entries: Vec<(usize, usize, f64)> = H
+            .entries()
+            .filter(|(row, col, _)| *row < self.other_dim && *col >= self.other_dim)
+            .collect();
+        B_entries.iter().for_each(|(row, col, _)| {
+            if let Some(block) = block_indices[*col] {
+                other_rows[block].insert(*row);
+            }
+        });
+
+        let mut landmark_blocks = landmark_ranges
+            .into_iter()
+            .zip(other_rows)
+            .map(|((range, C), other_rows)| {
+                let C_inv = C
+                    .cholesky()
+                    .ok_or_else(|| Error::SolverFailure(String::from("H is not positive-definite")))?
+                    .inverse();
+                let B = DMatrix::zeros(other_rows.len(), range.len());
+                Ok(LandmarkBlock {
+                    range,
+                    C_inv,
+                    other_rows: other_rows.into_iter().collect(),
+                    B,
+                })
+            })
+            .collect::<Result<Vec<LandmarkBlock>, Error>>()?;
+        B_entries.into_iter().for_each(|(row, col, value)| {
+            if let Some(block) = block_indices[col] {
+                let landmark_block = &mut landmark_blocks[block];
+                let local_row = landmark_block.other_rows.binary_search(&row).unwrap();
+                landmark_block.B[(local_row, col - landmark_block.range.start)] += value;
+            }
+        });
+        Ok(landmark_blocks)
+    }
+}
+
+impl<'a> Solver for SchurComplementSolver<'a> {
+    fn solve(&mut self, H: &SparseMatrix, b: &DVector<f64>) -> Result<Vec<f64>, Error> {
+        check_dimensions(H, b)?;
+        let other_dim = self.other_dim;
+        let landmark_blocks = self.get_landmark_blocks(H)?;
+
+        let mut S = SparseMatrix::new(other_dim);
+        H.entries()
+            .filter(|(row, col, _)| *row < other_dim && *col < other_dim)
+            .for_each(|(row, col, value)| S.add(row, col, value));
+        S.set_blocks(
+            H.blocks()
+                .iter()
+                .filter(|range| range.start < other_dim)
+                .cloned()
+                .collect(),
+        );
+        let mut reduced_b = b.rows(0, other_dim).into_owned();
+        landmark_blocks.iter().for_each(|landmark_block| {
+            let B_C_inv = &landmark_block.B * &landmark_block.C_inv;
+            let S_update = &B_C_inv * landmark_block.B.transpose();
+            let b_update = &B_C_inv * b.rows(landmark_block.range.start, landmark_block.range.len());
+            landmark_block.other_rows.iter().enumerate().for_each(|(i, row)| {
+                landmark_block
+                    .other_rows
+                    .iter()
+                    .enumerate()
+                    .for_each(|(j, col)| S.add(*row, *col, -S_update[(i, j)]));
+                reduced_b[*row] -= b_update[i];
+            });
+        });
+
+        let other_solution = self.inner.solve(&S, &reduced_b)?;
+        let mut solution = other_solution.clone();
+        solution.resize(H.dim(), 0.0);
+        landmark_blocks.iter().for_each(|landmark_block| {
+            let mut rhs = b
+                .rows(landmark_block.range.start, landmark_block.range.len())
+                .into_owned();
+            landmark_block.other_rows.iter().enumerate().for_each(|(i, row)| {
+                rhs -= landmark_block.B.row(i).transpose() * other_solution[*row];
+            });
+            let landmark_solution = &landmark_block.C_inv * rhs;
+            solution[landmark_block.range.clone()].copy_from_slice(landmark_solution.as_slice());
+        });
+        Ok(solution)
+    }
+
+    fn residual_norm(&self) -> Option<f64> {
+        self.inner.residual_norm()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use approx::relative_eq;
+    use nalgebra::{DMatrix, DVector};
+
+    use crate::optimizer::solver::dense_ldlt::DenseLdltSolver;
+    use crate::optimizer::solver::schur_complement::SchurComplementSolver;
+    use crate::optimizer::solver::Solver;
+    use crate::optimizer::sparse_matrix::SparseMatrix;
+
+    #[test]
+    fn solver_equal_to_direct_solution_test() {
+        #[allow(non_snake_case)]
+        #[rustfmt::skip]
+        let H = DMatrix::from_vec(5, 5, vec![
+            4.0, 1.0, 0.5, 0.0, 0.3,
+            1.0, 5.0, 0.0, 0.7, 0.2,
+            0.5, 0.0, 3.0, 0.5, 0.0,
+            0.0, 0.7, 0.5, 2.0, 0.0,
+            0.3, 0.2, 0.0, 0.0, 1.0,
+        ]);
+        let b = DVector::from_vec(vec![1.0, -2.0, 3.0, 0.5, -1.0]);
+        let expected = H.clone().cholesky().unwrap().solve(&b);
+        let mut sparse = SparseMatrix::from(H);
+        sparse.set_blocks(vec![0..1, 1..2, 2..4, 4..5]);
+
+        let mut inner = DenseLdltSolver;
+        let x = SchurComplementSolver::new(&mut inner, 2).solve(&sparse, &b).unwrap();
+        (0..5).for_each(|i| assert!(relative_eq!(x[i], expected[i], epsilon = 1e-10)));
+    }
+
+    #[test]
+    fn solver_not_positive_definite_test() {
+        let mut sparse = SparseMatrix::from(DMatrix::from_diagonal(&DVector::from_vec(vec![1.0, -1.0])));
+        sparse.set_blocks(vec![0..1, 1..2]);
+        let mut inner = DenseLdltSolver;
+        let solve_output = SchurComplementSolver::new(&mut inner, 1).solve(&sparse, &DVector::from_vec(vec![1.0, 1.0]));
+        assert!(solve_output.is_err());
+    }
+}
@@ -14,21 +14,61 @@
 
 #![allow(non_snake_case)]
 
-use crate::optimizer::solver::Solver;
-use nalgebra::{CsCholesky, CsMatrix, DMatrix, DVector};
+use crate::error::Error;
+use crate::optimizer::solver::{check_dimensions, Solver};
+use crate::optimizer::sparse_matrix::SparseMatrix;
+use nalgebra::{CsCholesky, DVector, Dynamic};
 
 /// Implements the solver using the Cholesky decomposition on a sparse matrix.
-pub struct SparseCholeskySolver;
+///
+/// The symbolic analysis of H is kept between calls. As long as the sparsity pattern of H does not change,
+/// which is the case for all iterations on the same factor graph, only the numeric factorization is repeated.
+#[derive(Default)]
+pub struct SparseCholeskySolver {
+    symbolic: Option<SymbolicCholesky>,
+}
+
+/// The symbolic analysis of H together with the information needed to refill its compressed values.
+struct SymbolicCholesky {
+    pattern: SparseMatrix,
+    slots: Vec<usize>,
+    value_count: usize,
+    cholesky: CsCholesky<f64, Dynamic>,
+}
+
+impl SparseCholeskySolver {
+    /// Returns whether the next call of solve() can reuse the symbolic analysis of the given H.
+    pub fn is_analyzed(&self, H: &SparseMatrix) -> bool {
+        self.symbolic
+            .as_ref()
+            .map_or(false, |symbolic| symbolic.pattern.has_same_pattern(H))
+    }
+
+    fn analyze(&mut self, H: &SparseMatrix) {
+        let (cs_matrix, slots) = H.compress();
+        self.symbolic = Some(SymbolicCholesky {
+            pattern: H.clone(),
+            slots,
+            value_count: cs_matrix.len(),
+            cholesky: CsCholesky::new_symbolic(&cs_matrix),
+        });
+    }
+}
 
 impl Solver for SparseCholeskySolver {
     /// Assumes that H is symmetric. Might return wrong result if this is not the case.
-    fn solve(H: DMatrix<f64>, b: &DVector<f64>) -> Result<Vec<f64>, String> {
-        // TODO @Daniel: pass matrix as sparse already
-        let sparse = CsCholesky::new(&CsMatrix::from(H));
-        match sparse.l() {
-            None => Err(String::from("H is not positive-definite")),
+    fn solve(&mut self, H: &SparseMatrix, b: &DVector<f64>) -> Result<Vec<f64>, Error> {
+        check_dimensions(H, b)?;
+        if !self.is_analyzed(H) {
+            self.analyze(H);
+        }
+        let symbolic = self.symbolic.as_mut().unwrap();
+        let values = H.compressed_values(&symbolic.slots, symbolic.value_count);
+        symbolic.cholesky.decompose_left_looking(&values);
+        match symbolic.cholesky.l() {
+            None => Err(Error::SolverFailure(String::from("H is not positive-definite"))),
             Some(l) => Ok(l
-                .tr_solve_lower_triangular(&l.solve_lower_triangular(&b).unwrap())
+                .tr_solve_lower_triangular(&l.solve_lower_triangular(b).unwrap())
                 .unwrap()
                 .data
                 .into()),
@@ -43,8 +83,10 @@ mod test {
     use log::LevelFilter;
     use nalgebra::{DMatrix, DVector};
 
+    use crate::error::Error;
     use crate::optimizer::solver::sparse_cholesky::SparseCholeskySolver;
     use crate::optimizer::solver::Solver;
+    use crate::optimizer::sparse_matrix::SparseMatrix;
 
     fn init() {
         let _ = env_logger::builder()
@@ -64,19 +106,51 @@ mod test {
             0.0, -1.0, 2.0,
         ];
         let b = vec![6.0, 6.0, 6.0];
-        let solve_output = SparseCholeskySolver::solve(
-            DMatrix::<f64>::from_vec(3, 3, positive_definite_H.clone()),
+        let solve_output = SparseCholeskySolver::default().solve(
+            &SparseMatrix::from(DMatrix::<f64>::from_vec(3, 3, positive_definite_H.clone())),
             &DVector::from_vec(b.clone()),
         );
         let x = match solve_output {
             Ok(sol) => sol,
-            Err(str) => panic!(str),
+            Err(error) => panic!("{}", error),
         };
         assert!(relative_eq!(x[0], 9.0, epsilon = 1e-10));
         assert!(relative_eq!(x[1], 12.0, epsilon = 1e-10));
         assert!(relative_eq!(x[2], 9.0, epsilon = 1e-10));
     }
 
+    #[test]
+    fn solver_reuses_symbolic_analysis_test() {
+        init();
+        #[allow(non_snake_case)]
+        #[rustfmt::skip]
+        let positive_definite_H = DMatrix::<f64>::from_vec(3, 3, vec![
+            2.0, -1.0, 0.0,
+            -1.0, 2.0, -1.0,
+            0.0, -1.0, 2.0,
+        ]);
+        let b = DVector::from_vec(vec![6.0, 6.0, 6.0]);
+        let mut solver = SparseCholeskySolver::default();
+        let first = SparseMatrix::from(positive_definite_H.clone());
+        assert!(!solver.is_analyzed(&first));
+        let x = solver.solve(&first, &b).unwrap();
+        assert!(relative_eq!(x[1], 12.0, epsilon = 1e-10));
+        assert!(solver.is_analyzed(&first));
+
+        let second = SparseMatrix::from(positive_definite_H * 2.0);
+        assert!(solver.is_analyzed(&second));
+        let x = solver.solve(&second, &b).unwrap();
+        assert!(relative_eq!(x[0], 4.5, epsilon = 1e-10));
+        assert!(relative_eq!(x[1], 6.0, epsilon = 1e-10));
+        assert!(relative_eq!(x[2], 4.5, epsilon = 1e-10));
+
+        let mut third = second.clone();
+        third.add_diagonal(1.0);
+        assert!(!solver.is_analyzed(&third));
+        solver.solve(&third, &b).unwrap();
+        assert!(solver.is_analyzed(&third));
+    }
+
     #[test]
     #[should_panic]
     fn solver_not_positive_definite_test() {
@@ -89,13 +163,13 @@ mod test {
             4.0, 5.0, 6.0,
         ];
         let b = vec![6.0, 6.0, 6.0];
-        let solve_output = SparseCholeskySolver::solve(
-            DMatrix::<f64>::from_vec(3, 3, not_positive_definite_H.clone()),
+        let solve_output = SparseCholeskySolver::default().solve(
+            &SparseMatrix::from(DMatrix::<f64>::from_vec(3, 3, not_positive_definite_H.clone())),
             &DVector::from_vec(b),
         );
         let x = match solve_output {
             Ok(sol) => sol,
-            Err(str) => panic!(str),
+            Err(error) => panic!("{}", error),
         };
         info!(
             "TEST FAILED! The solver returned {:?} for not positive-definite H = {:?}",
@@ -116,13 +190,13 @@ mod test {
             0.0, -1.0, 2.0,
         ];
         let b = vec![6.0, 6.0, 6.0];
-        let solve_output = SparseCholeskySolver::solve(
-            DMatrix::<f64>::from_vec(3, 3, not_symmetric_H.clone()),
+        let solve_output = SparseCholeskySolver::default().solve(
+            &SparseMatrix::from(DMatrix::<f64>::from_vec(3, 3, not_symmetric_H.clone())),
             &DVector::from_vec(b),
         );
         let x = match solve_output {
             Ok(sol) => sol,
-            Err(str) => panic!(str),
+            Err(error) => panic!("{}", error),
         };
         info!(
             "TEST FAILED! The solver returned {:?} for not symmetric H = {:?}",
@@ -131,7 +205,6 @@ mod test {
     }
 
     #[test]
-    #[should_panic]
     fn solver_incompatible_dimension_test() {
         init();
         #[allow(non_snake_case)]
@@ -142,17 +215,10 @@ mod test {
             0.0, -1.0, 2.0,
         ];
         let b = vec![6.0, 6.0, 6.0, 6.0];
-        let solve_output = SparseCholeskySolver::solve(
-            DMatrix::<f64>::from_vec(3, 3, positive_definite_H.clone()),
-            &DVector::from_vec(b.clone()),
-        );
-        let x = match solve_output {
-            Ok(sol) => sol,
-            Err(str) => panic!(str),
-        };
-        info!(
-            "TEST FAILED! The solver returned {:?} for incompatible dimensions: H = {:?}; b = {:?}",
-            x, positive_definite_H, b
+        let solve_output = SparseCholeskySolver::default().solve(
+            &SparseMatrix::from(DMatrix::<f64>::from_vec(3, 3, positive_definite_H)),
+            &DVector::from_vec(b),
         );
+        assert!(matches!(solve_output, Err(Error::DimensionMismatch { .. })));
     }
 }
@@ -0,0 +1,317 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Sparse representation of H, assembled block by block.
+
+use nalgebra::storage::Storage;
+use nalgebra::{CsMatrix, DMatrix, DVector, Dim, Matrix};
+use std::ops::{Mul, Range};
+
+/// Square sparse matrix in triplet (coordinate) format.
+///
+/// Entries added multiple times at the same position are summed up, so that factors can add their
+/// contributions independently. Memory is linear in the number of added entries.
+///
+/// Optionally, the matrix is partitioned into blocks along its diagonal, usually one block per variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseMatrix {
+    dim: usize,
+    rows: Vec<usize>,
+    cols: Vec<usize>,
+    values: Vec<f64>,
+    blocks: Vec<Range<usize>>,
+}
+
+impl SparseMatrix {
+    /// Returns an empty dim x dim matrix.
+    pub fn new(dim: usize) -> Self {
+        SparseMatrix {
+            dim,
+            rows: vec![],
+            cols: vec![],
+            values: vec![],
+            blocks: vec![],
+        }
+    }
+
+    /// Returns the number of rows, which equals the number of columns.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Returns the number of stored entries, including entries at identical positions.
+    pub fn entry_count(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns the diagonal blocks the matrix is partitioned into. Empty if no partition has been set.
+    pub fn blocks(&self) -> &[Range<usize>] {
+        &self.blocks
+    }
+
+    /// Partitions the matrix into the given diagonal blocks, which should cover every row exactly once.
+    pub fn set_blocks(&mut self, blocks: Vec<Range<usize>>) {
+        self.blocks = blocks;
+    }
+
+    /// Returns the dense diagonal blocks in the order of blocks(). If no partition has been set,
+    /// every diagonal entry is returned as a 1x1 block.
+    pub fn diagonal_blocks(&self) -> Vec<DMatrix<f64>> {
+        let blocks: Vec<Range<usize>> = if self.blocks.is_empty() {
+            (0..self.dim).map(|i| i..i + 1).collect()
+        } else {
+            self.blocks.clone()
+        };
+        let mut block_indices = vec![0; self.dim];
+        blocks
+            .iter()
+            .enumerate()
+            .for_each(|(block, range)| range.clone().for_each(|i| block_indices[i] = block));
+        let mut diagonal_blocks: Vec<DMatrix<f64>> = blocks
+            .iter()
+            .map(|range| DMatrix::zeros(range.len(), range.len()))
+            .collect();
+        self.entries()
+            .filter(|(row, col, _)| block_indices[*row] == block_indices[*col])
+            .for_each(|(row, col, value)| {
+                let block = block_indices[row];
+                let start = blocks[block].start;
+                diagonal_blocks[block][(row - start, col - start)] += value;
+            });
+        diagonal_blocks
+    }
+
+    /// Adds the given value to the entry at (row, col).
+    pub fn add(&mut self, row: usize, col: usize, value: f64) {
+        self.rows.push(row);
+        self.cols.push(col);
+        self.values.push(value);
+    }
+
+    /// Adds the given block to the entries starting at (row_start, col_start).
+    pub fn add_block<R: Dim, C: Dim, S: Storage<f64, R, C>>(
+        &mut self,
+        row_start: usize,
+        col_start: usize,
+        block: &Matrix<f64, R, C, S>,
+    ) {
+        for col in 0..block.ncols() {
+            for row in 0..block.nrows() {
+                self.add(row_start + row, col_start + col, block[(row, col)]);
+            }
+        }
+    }
+
+    /// Appends all entries of the given matrix of equal dimension, keeping their order.
+    pub fn append(&mut self, mut other: SparseMatrix) {
+        self.rows.append(&mut other.rows);
+        self.cols.append(&mut other.cols);
+        self.values.append(&mut other.values);
+    }
+
+    /// Adds the given value to all diagonal entries.
+    pub fn add_diagonal(&mut self, value: f64) {
+        (0..self.dim).for_each(|i| self.add(i, i, value));
+    }
+
+    /// Returns the diagonal entries.
+    pub fn diagonal(&self) -> DVector<f64> {
+        let mut diagonal = DVector::zeros(self.dim);
+        self.entries()
+            .filter(|(row, col, _)| row == col)
+            .for_each(|(row, _, value)| diagonal[row] += value);
+        diagonal
+    }
+
+    /// Returns the matrix with every entry (row, col) moved to (new_indices[row], new_indices[col]).
+    ///
+    /// The permutation is expected to keep the rows of every block together and in order.
+    pub fn permuted(&self, new_indices: &[usize]) -> SparseMatrix {
+        SparseMatrix {
+            dim: self.dim,
+            rows: self.rows.iter().map(|row| new_indices[*row]).collect(),
+            cols: self.cols.iter().map(|col| new_indices[*col]).collect(),
+            values: self.values.clone(),
+            blocks: self
+                .blocks
+                .iter()
+                .map(|range| new_indices[range.start]..new_indices[range.start] + range.len())
+                .collect(),
+        }
+    }
+
+    /// Returns the compressed sparse column representation, summing up entries at identical positions.
+    pub fn to_cs_matrix(&self) -> CsMatrix<f64> {
+        self.compress().0
+    }
+
+    /// Returns the compressed sparse column representation together with the index of every stored entry within
+    /// the compressed values. The indices allow refilling the compressed values via compressed_values().
+    pub fn compress(&self) -> (CsMatrix<f64>, Vec<usize>) {
+        // CsMatrix::from_triplet does not reliably sum up duplicates, so they are merged beforehand
+        let mut order: Vec<usize> = (0..self.values.len()).collect();
+        order.sort_by_key(|i| (self.cols[*i], self.rows[*i]));
+        let (mut rows, mut cols, mut values) = (vec![], vec![], vec![]);
+        let mut slots = vec![0; self.values.len()];
+        for i in order {
+            if rows.last() == Some(&self.rows[i]) && cols.last() == Some(&self.cols[i]) {
+                *values.last_mut().unwrap() += self.values[i];
+            } else {
+                rows.push(self.rows[i]);
+                cols.push(self.cols[i]);
+                values.push(self.values[i]);
+            }
+            slots[i] = values.len() - 1;
+        }
+        (CsMatrix::from_triplet(self.dim, self.dim, &rows, &cols, &values), slots)
+    }
+
+    /// Returns the values of the compressed sparse column representation,
+    /// using the indices returned by compress() for a matrix with the same pattern.
+    pub fn compressed_values(&self, slots: &[usize], value_count: usize) -> Vec<f64> {
+        let mut values = vec![0.0; value_count];
+        slots
+            .iter()
+            .zip(self.values.iter())
+            .for_each(|(slot, value)| values[*slot] += value);
+        values
+    }
+
+    /// Returns whether both matrices store entries at the same positions in the same order.
+    pub fn has_same_pattern(&self, other: &SparseMatrix) -> bool {
+        self.dim == other.dim && self.rows == other.rows && self.cols == other.cols
+    }
+
+    /// Returns the dense representation. Should only be used for small matrices.
+    pub fn to_dense(&self) -> DMatrix<f64> {
+        let mut dense = DMatrix::zeros(self.dim, self.dim);
+        self.entries().for_each(|(row, col, value)| dense[(row, col)] += value);
+        dense
+    }
+
+    /// Returns all stored entries as (row, col, value), including entries at identical positions.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        self.rows
+            .iter()
+            .zip(self.cols.iter())
+            .zip(self.values.iter())
+            .map(|((row, col), value)| (*row, *col, *value))
+    }
+}
+
+impl From<DMatrix<f64>> for SparseMatrix {
+    /// Expects a square matrix. Zero entries are not stored.
+    fn from(dense: DMatrix<f64>) -> Self {
+        let mut sparse = SparseMatrix::new(dense.nrows());
+        for col in 0..dense.ncols() {
+            for row in 0..dense.nrows() {
+                if dense[(row, col)] != 0.0 {
+                    sparse.add(row, col, dense[(row, col)]);
+                }
+            }
+        }
+        sparse
+    }
+}
+
+impl Mul<&DVector<f64>> for &SparseMatrix {
+    type Output = DVector<f64>;
+
+    fn mul(self, rhs: &DVector<f64>) -> DVector<f64> {
+        let mut product = DVector::zeros(self.dim);
+        self.entries()
+            .for_each(|(row, col, value)| product[row] += value * rhs[col]);
+        product
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_are_summed_up() {
+        let mut sparse = SparseMatrix::new(3);
+        sparse.add_block(0, 0, &DMatrix::from_vec(2, 2, vec![1.0, 2.0, 2.0, 3.0]));
+        sparse.add_block(1, 1, &DMatrix::from_vec(2, 2, vec![1.0, -1.0, -1.0, 1.0]));
+        sparse.add_diagonal(0.5);
+        #[rustfmt::skip]
+        let expected = DMatrix::from_vec(3, 3, vec![
+            1.5, 2.0, 0.0,
+            2.0, 4.5, -1.0,
+            0.0, -1.0, 1.5,
+        ]);
+        assert_eq!(sparse.to_dense(), expected);
+        assert_eq!(sparse.diagonal(), expected.diagonal());
+        assert_eq!(DMatrix::from(sparse.to_cs_matrix()), expected);
+
+        let x = DVector::from_vec(vec![1.0, 2.0, 3.0]);
+        assert_eq!(&sparse * &x, &expected * &x);
+
+        let mut appended = SparseMatrix::new(3);
+        appended.add_diagonal(1.0);
+        appended.append(sparse);
+        assert_eq!(appended.entry_count(), 14);
+        assert_eq!(appended.to_dense(), expected + DMatrix::identity(3, 3));
+    }
+
+    #[test]
+    fn test_diagonal_blocks() {
+        #[rustfmt::skip]
+        let dense = DMatrix::from_vec(3, 3, vec![
+            1.0, 2.0, 3.0,
+            2.0, 4.0, 5.0,
+            3.0, 5.0, 6.0,
+        ]);
+        let mut sparse = SparseMatrix::from(dense);
+        assert_eq!(
+            sparse.diagonal_blocks(),
+            vec![
+                DMatrix::from_element(1, 1, 1.0),
+                DMatrix::from_element(1, 1, 4.0),
+                DMatrix::from_element(1, 1, 6.0)
+            ]
+        );
+
+        sparse.set_blocks(vec![0..2, 2..3]);
+        assert_eq!(
+            sparse.diagonal_blocks(),
+            vec![
+                DMatrix::from_vec(2, 2, vec![1.0, 2.0, 2.0, 4.0]),
+                DMatrix::from_element(1, 1, 6.0)
+            ]
+        );
+
+        let permuted = sparse.permuted(&[1, 2, 0]);
+        assert_eq!(permuted.blocks(), &[1..3, 0..1]);
+        assert_eq!(permuted.diagonal_blocks(), sparse.diagonal_blocks());
+    }
+
+    #[test]
+    fn test_from_dense() {
+        let dense = DMatrix::from_vec(2, 2, vec![2.0, 0.0, 0.0, 3.0]);
+        let sparse = SparseMatrix::from(dense.clone());
+        assert_eq!(sparse.entry_count(), 2);
+        assert_eq!(sparse.to_dense(), dense);
+    }
+
+    #[test]
+    fn test_permuted() {
+        let dense = DMatrix::from_vec(3, 3, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        let permuted = SparseMatrix::from(dense.clone()).permuted(&[2, 0, 1]).to_dense();
+        for row in 0..3 {
+            for col in 0..3 {
+                assert_eq!(permuted[([2, 0, 1][row], [2, 0, 1][col])], dense[(row, col)]);
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Criteria for stopping an optimization before the maximum number of iterations is reached.
+
+use crate::optimizer::report::IterationStatistics;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Criteria for early termination of an optimization. Every criterion set to None is disabled.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TerminationCriteria {
+    /// Stops if an iteration decreases the total chi² by less than this value.
+    pub min_chi2_decrease: Option<f64>,
+    /// Stops if an iteration decreases the total chi² by less than this fraction of the total chi² before the iteration.
+    pub min_relative_chi2_decrease: Option<f64>,
+    /// Stops if the largest absolute entry of an iteration's update is smaller than this value.
+    pub min_update_norm: Option<f64>,
+    /// Stops if the norm of b, i.e. of the gradient, at the start of an iteration is smaller than this value.
+    pub min_gradient_norm: Option<f64>,
+    /// Stops as soon as an iteration finishes after the optimization has run for longer than this duration.
+    pub time_budget: Option<Duration>,
+}
+
+/// The reason why an optimization stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TerminationReason {
+    /// The maximum number of iterations has been performed.
+    MaxIterations,
+    /// The last iteration could not find an update decreasing the total chi².
+    NoImprovement,
+    /// The total chi² decreased by less than TerminationCriteria::min_chi2_decrease.
+    Chi2Decrease,
+    /// The total chi² decreased by less than TerminationCriteria::min_relative_chi2_decrease.
+    RelativeChi2Decrease,
+    /// The update was smaller than TerminationCriteria::min_update_norm.
+    UpdateNorm,
+    /// The gradient was smaller than TerminationCriteria::min_gradient_norm.
+    GradientNorm,
+    /// The optimization ran for longer than TerminationCriteria::time_budget.
+    TimeBudget,
+}
+
+impl TerminationCriteria {
+    /// Returns the reason to stop after the given iteration, or None if no criterion is met.
+    pub fn check(&self, iteration: &IterationStatistics, elapsed: Duration) -> Option<TerminationReason> {
+        let chi2_decrease = iteration.chi2_before - iteration.chi2_after;
+        if is_below(self.min_gradient_norm, iteration.gradient_norm) {
+            Some(TerminationReason::GradientNorm)
+        } else if is_below(self.min_update_norm, iteration.update_norm) {
+            Some(TerminationReason::UpdateNorm)
+        } else if is_below(self.min_chi2_decrease, chi2_decrease) {
+            Some(TerminationReason::Chi2Decrease)
+        } else if is_below(self.min_relative_chi2_decrease, chi2_decrease / iteration.chi2_before) {
+            Some(TerminationReason::RelativeChi2Decrease)
+        } else if self.time_budget.map_or(false, |budget| elapsed >= budget) {
+            Some(TerminationReason::TimeBudget)
+        } else {
+            None
+        }
+    }
+}
+
+fn is_below(threshold: Option<f64>, value: f64) -> bool {
+    threshold.map_or(false, |threshold| value < threshold)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_iteration() -> IterationStatistics {
+        let mut iteration = IterationStatistics::new(100.0, 5.0);
+        iteration.chi2_after = 90.0;
+        iteration.update_norm = 0.1;
+        iteration.accepted = true;
+        iteration
+    }
+
+    #[test]
+    fn test_no_criteria() {
+        let criteria = TerminationCriteria::default();
+        assert_eq!(criteria.check(&get_iteration(), Duration::from_secs(1000)), None);
+    }
+
+    #[test]
+    fn test_single_criteria() {
+        let iteration = get_iteration();
+        let elapsed = Duration::from_millis(10);
+        let check = |criteria: TerminationCriteria| criteria.check(&iteration, elapsed);
+
+        #[rustfmt::skip]
+        let cases = vec![
+            (TerminationCriteria { min_chi2_decrease: Some(10.5), ..Default::default() }, Some(TerminationReason::Chi2Decrease)),
+            (TerminationCriteria { min_chi2_decrease: Some(9.5), ..Default::default() }, None),
+            (TerminationCriteria { min_relative_chi2_decrease: Some(0.15), ..Default::default() }, Some(TerminationReason::RelativeChi2Decrease)),
+            (TerminationCriteria { min_relative_chi2_decrease: Some(0.05), ..Default::default() }, None),
+            (TerminationCriteria { min_update_norm: Some(0.2), ..Default::default() }, Some(TerminationReason::UpdateNorm)),
+            (TerminationCriteria { min_update_norm: Some(0.05), ..Default::default() }, None),
+            (TerminationCriteria { min_gradient_norm: Some(6.0), ..Default::default() }, Some(TerminationReason::GradientNorm)),
+            (TerminationCriteria { min_gradient_norm: Some(4.0), ..Default::default() }, None),
+            (TerminationCriteria { time_budget: Some(Duration::from_millis(5)), ..Default::default() }, Some(TerminationReason::TimeBudget)),
+            (TerminationCriteria { time_budget: Some(Duration::from_millis(50)), ..Default::default() }, None),
+        ];
+        cases
+            .into_iter()
+            .for_each(|(criteria, expected)| assert_eq!(check(criteria), expected));
+    }
+}
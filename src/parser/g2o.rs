@@ -12,7 +12,8 @@
 
 //! Conversion between factor graph structures and G2O files.
 
-use crate::parser::model::{Edge, FactorGraphModel, Vertex};
+use crate::error::Error;
+use crate::parser::model::{Edge, FactorGraphModel, RobustKernel, Vertex};
 use crate::parser::Parser;
 use std::collections::BTreeSet;
 
@@ -21,10 +22,14 @@ use std::collections::BTreeSet;
 /// More information on the G2O file format: https://github.com/RainerKuemmerle/g2o/wiki/File-Format
 ///
 /// Currently supported G2O vertices:
-/// VERTEX_SE2, VERTEX_XY, VERTEX_SE3:QUAT, VERTEX_TRACKXYZ
+/// VERTEX_SE2, VERTEX_XY, VERTEX_SE3:QUAT, VERTEX_TRACKXYZ, VERTEX_SWITCH
 ///
 /// Currently supported G2O edges:
-/// EDGE_PRIOR_SE2, EDGE_SE2, EDGE_SE2_XY, EDGE_SE3_PRIOR (*), EDGE_SE3:QUAT, EDGE_SE3_TRACKXYZ (*)
+/// EDGE_PRIOR_SE2, EDGE_SE2, EDGE_SE2_XY, EDGE_SE3_PRIOR (*), EDGE_SE3:QUAT, EDGE_SE3_TRACKXYZ (*),
+/// EDGE_SE2_SWITCHABLE, EDGE_SE3_SWITCHABLE, EDGE_SWITCH_PRIOR
+///
+/// The switchable edges and switch vertices are named as in vertigo, the switchable constraints extension of g2o.
+/// The switch vertex of a switchable edge is stated after the edge's two pose vertices.
 ///
 /// (*) When using one of these edges, the 2nd (EDGE_SE3_PRIOR) or 3rd (EDGE_SE3_TRACKXYZ)
 /// vertex/offset parameter is expected to be the offset with ID 0 as follows:
@@ -32,24 +37,29 @@ use std::collections::BTreeSet;
 /// Anything else will result in undefined and most likely undesired behavior.
 /// The offset "PARAMS_SE3OFFSET" is not supported in any other scenario.
 ///
-/// Note: Currently panics instead of returning an Err() when parsing an invalid file.
+/// As an extension to the G2O format, a line "ROBUST_KERNEL <type> <delta>" sets the robust kernel of the edge
+/// in the preceding line, using g2o's kernel names, e.g. "ROBUST_KERNEL Huber 1.0".
+///
+/// As another extension, linearized priors resulting from marginalization are stated as
+/// "EDGE_LINEARIZED_PRIOR <n> <vertex IDs> <m> <d> <restriction> <information matrix>" with n vertex IDs, m values
+/// of the restriction and the upper triangle of the d x d information matrix, e.g.
+/// "EDGE_LINEARIZED_PRIOR 1 0 3 3 1.0 0.0 1.57 1.0 0.0 0.0 1.0 0.0 1.0".
 pub struct G2oParser;
 
 impl Parser for G2oParser {
-    fn parse_string_to_model(s: &str) -> Result<FactorGraphModel, String> {
+    fn parse_string_to_model(s: &str) -> Result<FactorGraphModel, Error> {
         let mut model = FactorGraphModel {
             vertices: vec![],
             edges: vec![],
             fixed_vertices: BTreeSet::new(),
         };
-        let lines = s.split('\n');
-        lines
-            .enumerate()
-            .for_each(|(i, line)| Self::parse_line(&mut model, line, i + 1));
+        for (i, text) in s.split('\n').enumerate() {
+            Self::parse_line(&mut model, &Line::new(text, i + 1))?;
+        }
         Ok(model)
     }
 
-    fn compose_model_to_string(model: FactorGraphModel) -> Result<String, String> {
+    fn compose_model_to_string(model: FactorGraphModel) -> Result<String, Error> {
         let mut str_vec: Vec<String> = vec![];
         if model
             .edges
@@ -58,88 +68,219 @@ impl Parser for G2oParser {
         {
             str_vec.push(String::from("PARAMS_SE3OFFSET 0 0 0 0 0 0 0 1"));
         }
-        str_vec.extend::<Vec<String>>(
+        str_vec.extend(
             model
                 .vertices
                 .iter()
                 .map(|v| Self::vertex_to_string(v, &model.fixed_vertices))
-                .collect(),
+                .collect::<Result<Vec<String>, Error>>()?,
+        );
+        str_vec.extend(
+            model
+                .edges
+                .iter()
+                .map(Self::edge_to_string)
+                .collect::<Result<Vec<String>, Error>>()?,
         );
-        str_vec.extend::<Vec<String>>(model.edges.iter().map(Self::edge_to_string).collect());
         Ok(str_vec.join("\n"))
     }
 }
 
+/// A line of a G2O file split into its tokens, which keeps track of their positions for error reporting.
+struct Line<'a> {
+    text: &'a str,
+    number: usize,
+    tokens: Vec<&'a str>,
+}
+
+impl<'a> Line<'a> {
+    fn new(text: &'a str, number: usize) -> Self {
+        Line {
+            text,
+            number,
+            tokens: text.split_whitespace().collect(),
+        }
+    }
+
+    /// Returns a parse error at the token with the given index, or at the end of the line if it does not exist.
+    fn error_at(&self, token_index: usize, message: String) -> Error {
+        let offset = match self.tokens.get(token_index) {
+            Some(token) => token.as_ptr() as usize - self.text.as_ptr() as usize,
+            None => self.text.trim_end().len(),
+        };
+        Error::Parse {
+            line: self.number,
+            column: self.text[..offset].chars().count() + 1,
+            message,
+        }
+    }
+
+    fn unknown_keyword(&self) -> Error {
+        self.error_at(0, format!("Unknown keyword at beginning of line: {}", self.tokens[0]))
+    }
+
+    fn check_min_tokens(&self, expected: usize) -> Result<(), Error> {
+        if self.tokens.len() < expected {
+            return Err(self.error_at(
+                self.tokens.len(),
+                format!(
+                    "Wrong number of tokens: Expected at least: {}; Actual: {}",
+                    expected,
+                    self.tokens.len()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_tokens(&self, expected: usize) -> Result<(), Error> {
+        if self.tokens.len() != expected {
+            return Err(self.error_at(
+                expected.min(self.tokens.len()),
+                format!(
+                    "Wrong number of tokens: Expected: {}; Actual: {}",
+                    expected,
+                    self.tokens.len()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn parse_val<T: std::str::FromStr>(&self, token_index: usize) -> Result<T, Error> {
+        self.tokens[token_index].parse().map_err(|_| {
+            self.error_at(
+                token_index,
+                format!(
+                    "Could not parse the following value to the correct data type: {}",
+                    self.tokens[token_index]
+                ),
+            )
+        })
+    }
+
+    fn parse_vals<T: std::str::FromStr>(&self, token_indices: impl Iterator<Item = usize>) -> Result<Vec<T>, Error> {
+        token_indices.map(|i| self.parse_val(i)).collect()
+    }
+}
+
 impl G2oParser {
-    fn parse_line(model: &mut FactorGraphModel, line: &str, line_number: usize) {
-        let tokens: Vec<&str> = line.split_whitespace().collect();
-        if tokens.is_empty() || line.starts_with('#') {
-            return;
+    fn parse_line(model: &mut FactorGraphModel, line: &Line) -> Result<(), Error> {
+        if line.tokens.is_empty() || line.text.starts_with('#') {
+            return Ok(());
         }
-        match tokens[0] {
-            "VERTEX_SE2" | "VERTEX_XY" | "VERTEX_SE3:QUAT" | "VERTEX_TRACKXYZ" => {
-                model.vertices.push(Self::parse_vertex(&tokens, line_number))
+        match line.tokens[0] {
+            "VERTEX_SE2" | "VERTEX_XY" | "VERTEX_SE3:QUAT" | "VERTEX_TRACKXYZ" | "VERTEX_SWITCH" => {
+                model.vertices.push(Self::parse_vertex(line)?)
             }
-            "EDGE_PRIOR_SE2" | "EDGE_SE2" | "EDGE_SE2_XY" | "EDGE_SE3_PRIOR" | "EDGE_SE3:QUAT"
-            | "EDGE_SE3_TRACKXYZ" => model.edges.push(Self::parse_edge(&tokens, line_number)),
+            "EDGE_PRIOR_SE2"
+            | "EDGE_SE2"
+            | "EDGE_SE2_XY"
+            | "EDGE_SE3_PRIOR"
+            | "EDGE_SE3:QUAT"
+            | "EDGE_SE3_TRACKXYZ"
+            | "EDGE_SE2_SWITCHABLE"
+            | "EDGE_SE3_SWITCHABLE"
+            | "EDGE_SWITCH_PRIOR" => model.edges.push(Self::parse_edge(line)?),
+            "EDGE_LINEARIZED_PRIOR" => model.edges.push(Self::parse_linearized_prior(line)?),
+            "ROBUST_KERNEL" => Self::parse_robust_kernel(model, line)?,
             "FIX" => {
-                model.fixed_vertices.extend(Self::parse_fix(&tokens, line_number));
+                model.fixed_vertices.extend(Self::parse_fix(line)?);
             }
             "PARAMS_SE3OFFSET" => (), // line expected to equal "PARAMS_SE3OFFSET 0 0 0 0 0 0 0 1"
-            _ => panic!("Unknown keyword at beginning of line {}: {}", line_number, tokens[0]),
+            _ => return Err(line.unknown_keyword()),
         };
+        Ok(())
     }
 
-    fn parse_vertex(tokens: &[&str], line_number: usize) -> Vertex {
-        let (type_str, c_len) = match tokens[0] {
+    fn parse_vertex(line: &Line) -> Result<Vertex, Error> {
+        let (type_str, c_len) = match line.tokens[0] {
             "VERTEX_SE2" => ("Vehicle2D", 3),
             "VERTEX_XY" => ("Landmark2D", 2),
             "VERTEX_SE3:QUAT" => ("Vehicle3D", 7),
             "VERTEX_TRACKXYZ" => ("Landmark3D", 3),
-            _ => panic!("Unknown keyword at beginning of line {}: {}", line_number, tokens[0]),
+            "VERTEX_SWITCH" => ("Switch", 1),
+            _ => return Err(line.unknown_keyword()),
         };
         let expected_length = 2 + c_len;
-        Self::assert_tokens(expected_length, tokens.len(), line_number);
-        Vertex {
-            id: Self::parse_val(tokens[1], line_number),
+        line.check_tokens(expected_length)?;
+        Ok(Vertex {
+            id: line.parse_val(1)?,
             vertex_type: String::from(type_str),
-            content: tokens[2..].iter().map(|s| Self::parse_val(s, line_number)).collect(),
-        }
+            content: line.parse_vals(2..expected_length)?,
+        })
     }
 
-    fn parse_edge(tokens: &[&str], line_number: usize) -> Edge {
-        let (type_str, v_num, c_len, (index_mapping, upper_t_len)) = match tokens[0] {
+    fn parse_edge(line: &Line) -> Result<Edge, Error> {
+        let (type_str, v_num, c_len, (index_mapping, upper_t_len)) = match line.tokens[0] {
             "EDGE_PRIOR_SE2" => ("Position2D", 1, 3, Self::get_index_mapping_vec_and_upper_t_len(3)),
             "EDGE_SE2" => ("Odometry2D", 2, 3, Self::get_index_mapping_vec_and_upper_t_len(3)),
             "EDGE_SE2_XY" => ("Observation2D", 2, 2, Self::get_index_mapping_vec_and_upper_t_len(2)),
             "EDGE_SE3_PRIOR" => ("Position3D", 2, 7, Self::get_index_mapping_vec_and_upper_t_len(6)),
             "EDGE_SE3:QUAT" => ("Odometry3D", 2, 7, Self::get_index_mapping_vec_and_upper_t_len(6)),
             "EDGE_SE3_TRACKXYZ" => ("Observation3D", 3, 3, Self::get_index_mapping_vec_and_upper_t_len(3)),
-            _ => panic!("Unknown keyword at beginning of line {}: {}", line_number, tokens[0]),
+            "EDGE_SE2_SWITCHABLE" => (
+                "SwitchableOdometry2D",
+                3,
+                3,
+                Self::get_index_mapping_vec_and_upper_t_len(3),
+            ),
+            "EDGE_SE3_SWITCHABLE" => (
+                "SwitchableOdometry3D",
+                3,
+                7,
+                Self::get_index_mapping_vec_and_upper_t_len(6),
+            ),
+            "EDGE_SWITCH_PRIOR" => ("SwitchPrior", 1, 1, Self::get_index_mapping_vec_and_upper_t_len(1)),
+            _ => return Err(line.unknown_keyword()),
         };
         let expected_length = 1 + v_num + c_len + upper_t_len;
-        Self::assert_tokens(expected_length, tokens.len(), line_number);
-        Edge {
+        line.check_tokens(expected_length)?;
+        Ok(Edge {
             edge_type: String::from(type_str),
-            vertices: match tokens[0] {
-                "EDGE_SE3_PRIOR" | "EDGE_SE3_TRACKXYZ" => tokens[1..v_num]
-                    .iter()
-                    .map(|s| Self::parse_val(s, line_number))
-                    .collect(),
-                _ => tokens[1..1 + v_num]
-                    .iter()
-                    .map(|s| Self::parse_val(s, line_number))
-                    .collect(),
+            vertices: match line.tokens[0] {
+                "EDGE_SE3_PRIOR" | "EDGE_SE3_TRACKXYZ" => line.parse_vals(1..v_num)?,
+                _ => line.parse_vals(1..1 + v_num)?,
             },
-            restriction: tokens[1 + v_num..1 + v_num + c_len]
-                .iter()
-                .map(|s| Self::parse_val(s, line_number))
-                .collect(),
-            information_matrix: index_mapping
-                .iter()
-                .map(|i| Self::parse_val(tokens[1 + v_num + c_len + *i], line_number))
-                .collect(),
-        }
+            restriction: line.parse_vals(1 + v_num..1 + v_num + c_len)?,
+            information_matrix: line.parse_vals(index_mapping.iter().map(|i| 1 + v_num + c_len + *i))?,
+            robust_kernel: None,
+        })
+    }
+
+    /// Parses a linearized prior, whose numbers of vertices and values are stated in the line itself.
+    fn parse_linearized_prior(line: &Line) -> Result<Edge, Error> {
+        line.check_min_tokens(2)?;
+        let v_num: usize = line.parse_val(1)?;
+        line.check_min_tokens(v_num.saturating_add(4))?;
+        let c_len: usize = line.parse_val(2 + v_num)?;
+        let dim: usize = line.parse_val(3 + v_num)?;
+        // checked before allocating the index mapping, so that the stated numbers are bounded by the line's length
+        let values_start = 4 + v_num;
+        let upper_t_len = dim.saturating_mul(dim.saturating_add(1)) / 2;
+        line.check_tokens(values_start.saturating_add(c_len).saturating_add(upper_t_len))?;
+        let (index_mapping, _) = Self::get_index_mapping_vec_and_upper_t_len(dim);
+        Ok(Edge {
+            edge_type: String::from("LinearizedPrior"),
+            vertices: line.parse_vals(2..2 + v_num)?,
+            restriction: line.parse_vals(values_start..values_start + c_len)?,
+            information_matrix: line.parse_vals(index_mapping.iter().map(|i| values_start + c_len + *i))?,
+            robust_kernel: None,
+        })
+    }
+
+    fn parse_robust_kernel(model: &mut FactorGraphModel, line: &Line) -> Result<(), Error> {
+        line.check_tokens(3)?;
+        let delta = line.parse_val(2)?;
+        let edge = match model.edges.last_mut() {
+            Some(edge) => edge,
+            None => return Err(line.error_at(0, String::from("Robust kernel without preceding edge"))),
+        };
+        edge.robust_kernel = Some(RobustKernel {
+            kernel_type: String::from(line.tokens[1]),
+            delta,
+        });
+        Ok(())
     }
 
     fn get_index_mapping_vec_and_upper_t_len(dim: usize) -> (Vec<usize>, usize) {
@@ -156,46 +297,25 @@ impl G2oParser {
         (full_matrix_vec, upper_t_len)
     }
 
-    fn parse_fix(tokens: &[&str], line_number: usize) -> BTreeSet<usize> {
-        if tokens.len() == 1 {
-            panic!(
-                "Empty set of fixed vertices in line {}: Expected at least one vertex ID.",
-                line_number
-            );
-        }
-        tokens[1..].iter().map(|s| Self::parse_val(s, line_number)).collect()
-    }
-
-    fn assert_tokens(expected: usize, actual: usize, line_number: usize) {
-        if actual != expected {
-            panic!(
-                "Wrong number of tokens in line {}: Expected: {}; Actual: {}",
-                line_number, expected, actual
-            );
+    fn parse_fix(line: &Line) -> Result<BTreeSet<usize>, Error> {
+        if line.tokens.len() == 1 {
+            return Err(line.error_at(
+                1,
+                String::from("Empty set of fixed vertices: Expected at least one vertex ID."),
+            ));
         }
+        Ok(line.parse_vals(1..line.tokens.len())?.into_iter().collect())
     }
 
-    fn parse_val<T: std::str::FromStr>(s: &str, line_number: usize) -> T {
-        match s.parse() {
-            Ok(val) => val,
-            Err(_str) => panic!(
-                "Could not parse the following value to the correct data type in line {}: {}",
-                line_number, s
-            ),
-        }
-    }
-
-    fn vertex_to_string(v: &Vertex, fixed_vertices: &BTreeSet<usize>) -> String {
+    fn vertex_to_string(v: &Vertex, fixed_vertices: &BTreeSet<usize>) -> Result<String, Error> {
         let mut tokens: Vec<String> = vec![];
         match v.vertex_type.as_str() {
             "Vehicle2D" => tokens.push(String::from("VERTEX_SE2")),
             "Landmark2D" => tokens.push(String::from("VERTEX_XY")),
             "Vehicle3D" => tokens.push(String::from("VERTEX_SE3:QUAT")),
             "Landmark3D" => tokens.push(String::from("VERTEX_TRACKXYZ")),
-            other_type => panic!(format!(
-                "Vertex type unsupported to be composed to G2O format: {}",
-                other_type
-            )),
+            "Switch" => tokens.push(String::from("VERTEX_SWITCH")),
+            other_type => return Err(Error::UnknownVertexType(String::from(other_type))),
         }
         tokens.push(v.id.to_string());
         Self::append_f64_slice_to_string_vec(&mut tokens, &v.content);
@@ -203,39 +323,53 @@ impl G2oParser {
         if fixed_vertices.contains(&v.id) {
             vertex_string.push_str(&format!("\nFIX {}", v.id));
         }
-        vertex_string
+        Ok(vertex_string)
     }
 
-    fn edge_to_string(e: &Edge) -> String {
+    fn edge_to_string(e: &Edge) -> Result<String, Error> {
         let mut tokens: Vec<String> = vec![];
-        match e.edge_type.as_str() {
-            "Position2D" => tokens.push(String::from("EDGE_PRIOR_SE2")),
-            "Odometry2D" => tokens.push(String::from("EDGE_SE2")),
-            "Observation2D" => tokens.push(String::from("EDGE_SE2_XY")),
-            "Position3D" => tokens.push(String::from("EDGE_SE3_PRIOR")),
-            "Odometry3D" => tokens.push(String::from("EDGE_SE3:QUAT")),
-            "Observation3D" => tokens.push(String::from("EDGE_SE3_TRACKXYZ")),
-            other_type => panic!(format!(
-                "Edge type unsupported to be composed to G2O format: {}",
-                other_type
-            )),
+        let (keyword, dim) = match e.edge_type.as_str() {
+            "Position2D" => ("EDGE_PRIOR_SE2", 3),
+            "Odometry2D" => ("EDGE_SE2", 3),
+            "Observation2D" => ("EDGE_SE2_XY", 2),
+            "Position3D" => ("EDGE_SE3_PRIOR", 6),
+            "Odometry3D" => ("EDGE_SE3:QUAT", 6),
+            "Observation3D" => ("EDGE_SE3_TRACKXYZ", 3),
+            "SwitchableOdometry2D" => ("EDGE_SE2_SWITCHABLE", 3),
+            "SwitchableOdometry3D" => ("EDGE_SE3_SWITCHABLE", 6),
+            "SwitchPrior" => ("EDGE_SWITCH_PRIOR", 1),
+            "LinearizedPrior" => (
+                "EDGE_LINEARIZED_PRIOR",
+                (e.information_matrix.len() as f64).sqrt() as usize,
+            ),
+            other_type => return Err(Error::UnknownEdgeType(String::from(other_type))),
+        };
+        if e.information_matrix.len() != dim * dim {
+            return Err(Error::DimensionMismatch {
+                context: format!("Information matrix of {} edge", e.edge_type),
+                expected: dim * dim,
+                actual: e.information_matrix.len(),
+            });
+        }
+        tokens.push(String::from(keyword));
+        if e.edge_type == "LinearizedPrior" {
+            Self::append_usize_slice_to_string_vec(&mut tokens, &[e.vertices.len()]);
         }
         Self::append_usize_slice_to_string_vec(&mut tokens, e.vertices.as_slice());
+        if e.edge_type == "LinearizedPrior" {
+            Self::append_usize_slice_to_string_vec(&mut tokens, &[e.restriction.len(), dim]);
+        }
         if e.edge_type == "Position3D" || e.edge_type == "Observation3D" {
             Self::append_usize_slice_to_string_vec(&mut tokens, &[0]); // the last vertex/offset index should be 0 for these edges
         }
         Self::append_f64_slice_to_string_vec(&mut tokens, &e.restriction);
-        let upper_triangle = match e.edge_type.as_str() {
-            "Position2D" | "Odometry2D" | "Observation3D" => Self::get_upper_triangle_indices(3),
-            "Observation2D" => Self::get_upper_triangle_indices(2),
-            "Position3D" | "Odometry3D" => Self::get_upper_triangle_indices(6),
-            other_type => panic!(format!(
-                "Edge type unsupported to be composed to G2O format: {}",
-                other_type
-            )),
-        };
+        let upper_triangle = Self::get_upper_triangle_indices(dim);
         Self::append_f64_slice_elements_to_string_vec(&mut tokens, &e.information_matrix, &upper_triangle);
-        tokens.join(" ")
+        let mut edge_string = tokens.join(" ");
+        if let Some(kernel) = &e.robust_kernel {
+            edge_string.push_str(&format!("\nROBUST_KERNEL {} {:?}", kernel.kernel_type, kernel.delta));
+        }
+        Ok(edge_string)
     }
 
     fn get_upper_triangle_indices(dim: usize) -> Vec<usize> {
@@ -264,9 +398,11 @@ impl G2oParser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::factor_graph::FactorGraph;
     use crate::parser::model::{Edge, Vertex};
     use log::LevelFilter;
     use std::collections::BTreeSet;
+    use std::convert::TryFrom;
     use std::fs;
 
     fn init() {
@@ -300,18 +436,21 @@ mod tests {
                 vertices: vec![0, 1],
                 restriction: vec![1.0, 1.5, 1.57],
                 information_matrix: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+                robust_kernel: None,
             },
             Edge {
                 edge_type: String::from("Observation2D"),
                 vertices: vec![0, 2],
                 restriction: vec![0.0, -1.0],
                 information_matrix: vec![1.0, 0.0, 0.0, 1.0],
+                robust_kernel: None,
             },
             Edge {
                 edge_type: String::from("Position2D"),
                 vertices: vec![1],
                 restriction: vec![0.0, 1.0, 3.13],
                 information_matrix: vec![10.0, 0.0, 0.0, 0.0, 10.0, 0.0, 0.0, 0.0, 1.0],
+                robust_kernel: None,
             },
         ];
         let mut fixed_vertices = BTreeSet::new();
@@ -401,6 +540,7 @@ mod tests {
                     40.2084,
                     4100.08,
                 ],
+                robust_kernel: None,
             },
             Edge {
                 edge_type: String::from("Position3D"),
@@ -444,6 +584,7 @@ mod tests {
                     40.2084,
                     4100.08,
                 ],
+                robust_kernel: None,
             },
             Edge {
                 edge_type: String::from("Observation3D"),
@@ -452,6 +593,7 @@ mod tests {
                 information_matrix: vec![
                     3934.45, -9.14727, 63.005, -9.14727, 3998.72, 10.7561, 63.005, 10.7561, 3909.38,
                 ],
+                robust_kernel: None,
             },
         ];
         let mut fixed_vertices = BTreeSet::new();
@@ -479,4 +621,147 @@ mod tests {
         let expected_string = fs::read_to_string("data_files/full_demos/all_3d_types.g2o").unwrap();
         assert_eq!(&composed_string, &expected_string);
     }
+
+    #[test]
+    fn test_robust_kernel_round_trip() {
+        init();
+        let g2o_string = "VERTEX_SE2 0 1.0 0.0 1.57\n\
+                          VERTEX_SE2 1 0.0 1.0 3.14\n\
+                          EDGE_SE2 0 1 1.0 1.5 1.57 1.0 0.0 0.0 1.0 0.0 1.0\n\
+                          ROBUST_KERNEL Cauchy 2.5\n\
+                          EDGE_PRIOR_SE2 1 0.0 1.0 3.13 10.0 0.0 0.0 10.0 0.0 1.0";
+        let model = G2oParser::parse_string_to_model(g2o_string).unwrap();
+        assert_eq!(
+            model.edges[0].robust_kernel,
+            Some(RobustKernel {
+                kernel_type: String::from("Cauchy"),
+                delta: 2.5,
+            })
+        );
+        assert_eq!(model.edges[1].robust_kernel, None);
+        assert_eq!(G2oParser::compose_model_to_string(model).unwrap(), g2o_string);
+    }
+
+    #[test]
+    fn test_switchable_round_trip() {
+        init();
+        let g2o_string = "VERTEX_SE2 0 1.0 0.0 1.57\n\
+                          VERTEX_SE2 1 0.0 1.0 3.14\n\
+                          VERTEX_SWITCH 2 1.0\n\
+                          EDGE_SE2_SWITCHABLE 0 1 2 1.0 1.5 1.57 1.0 0.0 0.0 1.0 0.0 1.0\n\
+                          EDGE_SWITCH_PRIOR 2 1.0 1.0";
+        let model = G2oParser::parse_string_to_model(g2o_string).unwrap();
+        assert_eq!(model.vertices[2].vertex_type, "Switch");
+        assert_eq!(model.edges[0].edge_type, "SwitchableOdometry2D");
+        assert_eq!(model.edges[0].vertices, vec![0, 1, 2]);
+        assert_eq!(model.edges[1].edge_type, "SwitchPrior");
+        assert_eq!(model.edges[1].information_matrix, vec![1.0]);
+        let factor_graph = FactorGraph::try_from(model).unwrap();
+        let composed_string = G2oParser::compose_model_to_string((&factor_graph).into()).unwrap();
+        assert_eq!(composed_string, g2o_string);
+    }
+
+    #[test]
+    fn test_linearized_prior_round_trip() {
+        init();
+        let g2o_string = "VERTEX_SE2 0 1.0 0.0 1.57\n\
+                          VERTEX_XY 1 1.5 2.0\n\
+                          EDGE_SE2_XY 0 1 0.0 -1.0 1.0 0.0 1.0\n\
+                          EDGE_LINEARIZED_PRIOR 2 0 1 5 5 1.0 0.0 1.57 1.5 2.0 \
+                          2.0 0.5 0.0 0.0 0.0 2.0 0.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0";
+        let model = G2oParser::parse_string_to_model(g2o_string).unwrap();
+        assert_eq!(model.edges[1].edge_type, "LinearizedPrior");
+        assert_eq!(model.edges[1].vertices, vec![0, 1]);
+        assert_eq!(model.edges[1].restriction, vec![1.0, 0.0, 1.57, 1.5, 2.0]);
+        assert_eq!(model.edges[1].information_matrix[..6], [2.0, 0.5, 0.0, 0.0, 0.0, 0.5]);
+        let factor_graph = FactorGraph::try_from(model).unwrap();
+        assert_eq!(factor_graph.linearized_priors.len(), 1);
+        let composed_string = G2oParser::compose_model_to_string((&factor_graph).into()).unwrap();
+        assert_eq!(composed_string, g2o_string);
+
+        let get_position = |s: &str| match G2oParser::parse_string_to_model(s) {
+            Err(Error::Parse { line, column, .. }) => (line, column),
+            other => panic!("Expected a parse error: {:?}", other),
+        };
+        assert_eq!(get_position("EDGE_LINEARIZED_PRIOR"), (1, 22));
+        assert_eq!(get_position("EDGE_LINEARIZED_PRIOR 2 0 1 5"), (1, 30));
+        assert_eq!(get_position("EDGE_LINEARIZED_PRIOR 1 0 1 1 1.0"), (1, 34));
+        assert_eq!(
+            get_position(&format!("EDGE_LINEARIZED_PRIOR {} 0 1 1 1.0", usize::MAX)),
+            (1, 53)
+        );
+        assert_eq!(
+            get_position(&format!("EDGE_LINEARIZED_PRIOR 1 0 1 {} 1.0", usize::MAX)),
+            (1, 53)
+        );
+    }
+
+    #[test]
+    fn test_robust_kernel_without_edge() {
+        init();
+        assert_eq!(
+            G2oParser::parse_string_to_model("VERTEX_SE2 0 1.0 0.0 1.57\nROBUST_KERNEL Huber 1.0").unwrap_err(),
+            Error::Parse {
+                line: 2,
+                column: 1,
+                message: String::from("Robust kernel without preceding edge"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        init();
+        let get_position = |s: &str| match G2oParser::parse_string_to_model(s) {
+            Err(Error::Parse { line, column, .. }) => (line, column),
+            other => panic!("Expected a parse error: {:?}", other),
+        };
+        assert_eq!(get_position("VERTEX_SE2 0 1.0 0.0 1.57\nVERTEX_FOO 1 1.0"), (2, 1));
+        assert_eq!(
+            get_position("VERTEX_SE2 0 1.0 0.0 1.57\n  VERTEX_SE2 1 1.0 x 1.57"),
+            (2, 20)
+        );
+        assert_eq!(get_position("VERTEX_SE2 0 1.0 0.0"), (1, 21));
+        assert_eq!(get_position("VERTEX_XY 0 1.0 0.0 1.57"), (1, 21));
+        assert_eq!(get_position("VERTEX_XY -1 1.0 0.0"), (1, 11));
+        assert_eq!(get_position("FIX"), (1, 4));
+    }
+
+    #[test]
+    fn test_invalid_model() {
+        init();
+        let to_factor_graph = |s: &str| FactorGraph::try_from(G2oParser::parse_string_to_model(s).unwrap());
+        assert_eq!(
+            to_factor_graph("VERTEX_SE2 0 1.0 0.0 1.57\nEDGE_SE2 0 1 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0").unwrap_err(),
+            Error::UnknownVertexId(1)
+        );
+        assert_eq!(
+            to_factor_graph("VERTEX_SE2 0 1.0 0.0 1.57\nVERTEX_XY 0 1.0 1.0").unwrap_err(),
+            Error::DuplicateVertexId(0)
+        );
+        assert_eq!(
+            to_factor_graph("VERTEX_SE2 0 1.0 0.0 1.57\nEDGE_PRIOR_SE2 0 1.0 0.0 0.0 1.0 2.0 0.0 1.0 0.0 1.0")
+                .unwrap_err(),
+            Error::NonPsdInformationMatrix(String::from("Position2D factor"))
+        );
+        assert_eq!(
+            to_factor_graph("VERTEX_SE2 0 1.0 0.0 1.57\nEDGE_PRIOR_SE2 0 1.0 0.0 0.0 1.0 0.0 0.0 1.0 0.0 1.0\nROBUST_KERNEL Foo 1.0")
+                .unwrap_err(),
+            Error::UnknownRobustKernelType(String::from("Foo"))
+        );
+
+        let get_model = || {
+            let mut model = G2oParser::parse_string_to_model("VERTEX_SE2 0 1.0 0.0 1.57").unwrap();
+            model.vertices[0].vertex_type = String::from("Vehicle4D");
+            model
+        };
+        assert_eq!(
+            FactorGraph::try_from(get_model()).unwrap_err(),
+            Error::UnknownVertexType(String::from("Vehicle4D"))
+        );
+        assert_eq!(
+            G2oParser::compose_model_to_string(get_model()).unwrap_err(),
+            Error::UnknownVertexType(String::from("Vehicle4D"))
+        );
+    }
 }
@@ -12,6 +12,7 @@
 
 //! Conversion between factor graph structures and JSON files.
 
+use crate::error::Error;
 use crate::parser::model::FactorGraphModel;
 use crate::parser::Parser;
 
@@ -21,28 +22,29 @@ use crate::parser::Parser;
 pub struct JsonParser;
 
 impl Parser for JsonParser {
-    fn parse_string_to_model(s: &str) -> Result<FactorGraphModel, String> {
-        match serde_json::from_str::<FactorGraphModel>(s) {
-            Ok(model) => Ok(model),
-            Err(e) => Err(format!("Parsing to FactorGraphModel unsuccessful: {}", e)),
-        }
+    fn parse_string_to_model(s: &str) -> Result<FactorGraphModel, Error> {
+        serde_json::from_str::<FactorGraphModel>(s).map_err(|e| Error::Parse {
+            line: e.line(),
+            column: e.column(),
+            message: format!("Parsing to FactorGraphModel unsuccessful: {}", e),
+        })
     }
 
-    fn compose_model_to_string(model: FactorGraphModel) -> Result<String, String> {
-        match serde_json::to_string_pretty(&model) {
-            Ok(s) => Ok(s),
-            Err(e) => Err(format!("Composing FactorGraphModel as JSON string unsuccessful: {}", e)),
-        }
+    fn compose_model_to_string(model: FactorGraphModel) -> Result<String, Error> {
+        serde_json::to_string_pretty(&model)
+            .map_err(|e| Error::Serialization(format!("Composing FactorGraphModel as JSON string unsuccessful: {}", e)))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parser::model::{Edge, Vertex};
+    use crate::factor_graph::FactorGraph;
+    use crate::parser::model::{Edge, RobustKernel, Vertex};
     use log::info;
     use log::LevelFilter;
     use std::collections::BTreeSet;
+    use std::convert::TryFrom;
     use std::fs;
 
     fn init() {
@@ -63,6 +65,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_error_position() {
+        init();
+        match JsonParser::parse_string_to_model("{\n  \"vertices\": [],\n  \"edges\": 1\n}") {
+            Err(Error::Parse { line, column, .. }) => assert_eq!((line, column), (3, 12)),
+            other => panic!("Expected a parse error: {:?}", other),
+        }
+    }
+
     fn get_2d_model() -> FactorGraphModel {
         let vertices = vec![
             Vertex {
@@ -87,18 +98,21 @@ mod tests {
                 vertices: vec![0, 1],
                 restriction: vec![1.0, 1.5, 1.57],
                 information_matrix: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+                robust_kernel: None,
             },
             Edge {
                 edge_type: String::from("Observation2D"),
                 vertices: vec![0, 2],
                 restriction: vec![0.0, -1.0],
                 information_matrix: vec![1.0, 0.0, 0.0, 1.0],
+                robust_kernel: None,
             },
             Edge {
                 edge_type: String::from("Position2D"),
                 vertices: vec![1],
                 restriction: vec![0.0, 1.0, 3.13],
                 information_matrix: vec![10.0, 0.0, 0.0, 0.0, 10.0, 0.0, 0.0, 0.0, 1.0],
+                robust_kernel: None,
             },
         ];
         let mut fixed_vertices = BTreeSet::new();
@@ -119,6 +133,17 @@ mod tests {
         assert_eq!(parsed_model, expected_model);
     }
 
+    #[test]
+    fn test_non_square_information_matrix() {
+        init();
+        let mut model = get_2d_model();
+        model.edges[0].information_matrix.pop();
+        match FactorGraph::try_from(model) {
+            Err(Error::DimensionMismatch { expected, actual, .. }) => assert_eq!((expected, actual), (9, 8)),
+            other => panic!("Expected a dimension mismatch: {:?}", other),
+        }
+    }
+
     #[test]
     fn test_2d_type_composition() {
         let model = get_2d_model();
@@ -152,18 +177,21 @@ mod tests {
                 vertices: vec![0, 1],
                 restriction: vec![0.309576, 2.34636, 0.00315914, -0.139007, 0.0806488, 0.14657, 0.976059],
                 information_matrix: vec![1.0, 0.000000000000000000962965, 0.000000000000000000962965, 0.0000000588441, -0.0000000203096, 0.00000000340337, 0.000000000000000000962965, 1.0, 0.000000000000000000962965, 0.0000000588441, -0.0000000203096, 0.00000000340337, 0.000000000000000000962965, 0.000000000000000000962965, 1.0, 0.0000000588441, -0.0000000203096, 0.00000000340337, 0.0000000588441, 0.0000000588441, 0.0000000588441, 4108.72, -34.2982, 884.091, -0.0000000203096, -0.0000000203096, -0.0000000203096, -34.2982, 3951.5, 40.2084, 0.00000000340337, 0.00000000340337, 0.00000000340337, 884.091, 40.2084, 4100.08],
+                robust_kernel: None,
             },
             Edge {
                 edge_type: String::from("Position3D"),
                 vertices: vec![1],
                 restriction: vec![0.309576, 2.34636, 0.00315914, -0.139007, 0.0806488, 0.14657, 0.976059],
                 information_matrix: vec![1.0, 0.000000000000000000962965, 0.000000000000000000962965, 0.0000000588441, -0.0000000203096, 0.00000000340337, 0.000000000000000000962965, 1.0, 0.000000000000000000962965, 0.0000000588441, -0.0000000203096, 0.00000000340337, 0.000000000000000000962965, 0.000000000000000000962965, 1.0, 0.0000000588441, -0.0000000203096, 0.00000000340337, 0.0000000588441, 0.0000000588441, 0.0000000588441, 4108.72, -34.2982, 884.091, -0.0000000203096, -0.0000000203096, -0.0000000203096, -34.2982, 3951.5, 40.2084, 0.00000000340337, 0.00000000340337, 0.00000000340337, 884.091, 40.2084, 4100.08],
+                robust_kernel: None,
             },
             Edge {
                 edge_type: String::from("Observation3D"),
                 vertices: vec![1, 2],
                 restriction: vec![-0.034127, 2.24359, -0.503123],
                 information_matrix: vec![3934.45, -9.14727, 63.005, -9.14727, 3998.72, 10.7561, 63.005, 10.7561, 3909.38],
+                robust_kernel: None,
             }
         ];
         let mut fixed_vertices = BTreeSet::new();
@@ -191,4 +219,25 @@ mod tests {
         let expected_string = fs::read_to_string("data_files/full_demos/all_3d_types.json").unwrap();
         assert_eq!(&composed_string, &expected_string);
     }
+
+    #[test]
+    fn test_robust_kernel_round_trip() {
+        init();
+        let mut model = get_2d_model();
+        model.edges[0].robust_kernel = Some(RobustKernel {
+            kernel_type: String::from("Huber"),
+            delta: 1.5,
+        });
+        let composed_string = JsonParser::compose_model_to_string(model).unwrap();
+        assert_eq!(composed_string.matches("robustKernel").count(), 1);
+        let parsed_model = JsonParser::parse_string_to_model(&composed_string).unwrap();
+        assert_eq!(
+            parsed_model.edges[0].robust_kernel,
+            Some(RobustKernel {
+                kernel_type: String::from("Huber"),
+                delta: 1.5,
+            })
+        );
+        assert_eq!(parsed_model.edges[1].robust_kernel, None);
+    }
 }
@@ -12,47 +12,94 @@
 
 //! Conversion between factor graph structures and files.
 
+use crate::error::Error;
 use crate::factor_graph::FactorGraph;
+use crate::parser::model::validation::Severity;
 use crate::parser::model::FactorGraphModel;
+use std::convert::TryFrom;
 use std::fs;
 
 pub mod g2o;
 pub mod json;
 pub mod model;
 
+/// Options for parsing files to factor graphs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOptions {
+    /// Handling of the issues found by validating the parsed model before converting it.
+    pub validation: ValidationMode,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            validation: ValidationMode::Skip,
+        }
+    }
+}
+
+/// Handling of the issues found by validating a parsed model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationMode {
+    /// The model is not validated. Models which cannot be converted still result in an error.
+    Skip,
+    /// Parsing fails with all found issues if any of them is an error.
+    RejectErrors,
+    /// Parsing fails with all found issues if there is any issue, including warnings.
+    RejectWarnings,
+}
+
 /// Trait to be used by all parsers with the basic file parsing and composition functionality.
 pub trait Parser {
     /// Tries to parse a file at the given path to the internal factor graph representation.
-    fn parse_file(file_path: &str) -> Result<FactorGraph, String> {
-        match Self::parse_file_to_model(file_path) {
-            Ok(model) => Ok(model.into()),
-            Err(s) => Err(s),
+    fn parse_file(file_path: &str) -> Result<FactorGraph, Error> {
+        Self::parse_file_with_options(file_path, &ParseOptions::default())
+    }
+
+    /// Tries to parse a file at the given path to the internal factor graph representation,
+    /// validating the parsed model as specified by the options.
+    fn parse_file_with_options(file_path: &str, options: &ParseOptions) -> Result<FactorGraph, Error> {
+        let model = Self::parse_file_to_model(file_path)?;
+        let rejected_severity = match options.validation {
+            ValidationMode::Skip => None,
+            ValidationMode::RejectErrors => Some(Severity::Error),
+            ValidationMode::RejectWarnings => Some(Severity::Warning),
+        };
+        if let Some(rejected_severity) = rejected_severity {
+            let issues = model.validate();
+            if issues.iter().any(|issue| issue.severity >= rejected_severity) {
+                return Err(Error::Validation(issues));
+            }
         }
+        FactorGraph::try_from(model)
     }
 
     /// Tries to parse a file at the given path to the factor graph model used in the context with files.
-    fn parse_file_to_model(file_path: &str) -> Result<FactorGraphModel, String> {
-        let file_string = match fs::read_to_string(file_path) {
-            Ok(s) => s,
-            Err(_e) => return Err(format!("File could not be parsed: {}", file_path)),
-        };
+    fn parse_file_to_model(file_path: &str) -> Result<FactorGraphModel, Error> {
+        let file_string = fs::read_to_string(file_path).map_err(|e| Error::Io {
+            path: String::from(file_path),
+            message: e.to_string(),
+        })?;
         Self::parse_string_to_model(&file_string)
     }
 
     /// Tries to parse a string to the factor graph model used in the context with files.
-    fn parse_string_to_model(s: &str) -> Result<FactorGraphModel, String>;
+    fn parse_string_to_model(s: &str) -> Result<FactorGraphModel, Error>;
 
     /// Tries to compose a file at the given path containing the serialized factor graph.
-    fn compose_file(factor_graph: &FactorGraph, file_path: &str) -> Result<(), String> {
+    fn compose_file(factor_graph: &FactorGraph, file_path: &str) -> Result<(), Error> {
         Self::compose_model_to_file(factor_graph.into(), file_path)
     }
 
     /// Tries to compose a file at the given path containing the factor graph model's serialization.
-    fn compose_model_to_file(model: FactorGraphModel, file_path: &str) -> Result<(), String> {
+    fn compose_model_to_file(model: FactorGraphModel, file_path: &str) -> Result<(), Error> {
         let s = Self::compose_model_to_string(model)?;
-        fs::write(file_path, s).map_err(|_| format!("File could not be written to: {}", file_path))
+        fs::write(file_path, s).map_err(|e| Error::Io {
+            path: String::from(file_path),
+            message: e.to_string(),
+        })
     }
 
     /// Tries to compose a string containing the factor graph model's serialization.
-    fn compose_model_to_string(model: FactorGraphModel) -> Result<String, String>;
+    fn compose_model_to_string(model: FactorGraphModel) -> Result<String, Error>;
 }
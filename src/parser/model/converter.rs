@@ -10,36 +10,35 @@
 // This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
 //
 
-use petgraph::csr::Csr;
-
+use crate::error::Error;
+use crate::factor_graph::factor;
 use crate::factor_graph::factor::{Factor, FactorType::*};
 use crate::factor_graph::variable::{
-    FixedType, LandmarkVariable2D, LandmarkVariable3D, Variable, VehicleVariable2D, VehicleVariable3D,
+    FixedType, LandmarkVariable2D, LandmarkVariable3D, SwitchVariable, Variable, VehicleVariable2D, VehicleVariable3D,
 };
 use crate::factor_graph::FactorGraph;
-use crate::parser::model::{Edge, FactorGraphModel, Vertex};
+use crate::parser::model::{Edge, FactorGraphModel, RobustKernel, Vertex};
 
 use petgraph::visit::EdgeRef;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::BTreeSet;
+use std::convert::{TryFrom, TryInto};
 use std::ops::Index;
 
-impl From<FactorGraphModel> for FactorGraph {
-    fn from(model: FactorGraphModel) -> Self {
-        let mut factor_graph = FactorGraph {
-            csr: Csr::new(),
-            node_indices: vec![],
-            matrix_dim: 0,
-            custom_to_csr_id_map: HashMap::new(),
-        };
+impl TryFrom<FactorGraphModel> for FactorGraph {
+    type Error = Error;
 
-        model
-            .vertices
-            .iter()
-            .for_each(|v| add_vertex(&mut factor_graph, v, model.fixed_vertices.contains(&v.id)));
+    fn try_from(model: FactorGraphModel) -> Result<Self, Error> {
+        let mut factor_graph = FactorGraph::default();
 
-        model.edges.iter().for_each(|e| add_edge(&mut factor_graph, e));
+        for v in &model.vertices {
+            add_vertex(&mut factor_graph, v, model.fixed_vertices.contains(&v.id))?;
+        }
+
+        for e in &model.edges {
+            add_edge(&mut factor_graph, e)?;
+        }
 
-        factor_graph
+        Ok(factor_graph)
     }
 }
 
@@ -59,111 +58,165 @@ impl From<&FactorGraph> for FactorGraphModel {
                     Variable::Landmark2D(_) => String::from("Landmark2D"),
                     Variable::Vehicle3D(_) => String::from("Vehicle3D"),
                     Variable::Landmark3D(_) => String::from("Landmark3D"),
+                    Variable::Switch(_) => String::from("Switch"),
                 },
                 content: node.get_content(),
             });
             for edge in factor_graph.csr.edges(*node_index) {
-                let factor: &Factor = edge.weight();
-                let mut edge_vertices = vec![node.get_id()];
+                let mut indices = vec![*node_index];
                 if edge.target() != *node_index {
-                    edge_vertices.push(factor_graph.csr.index(edge.target()).get_id());
+                    indices.push(edge.target());
                 }
-                model.edges.push(Edge {
-                    edge_type: match factor.factor_type {
-                        Position2D => String::from("Position2D"),
-                        Odometry2D => String::from("Odometry2D"),
-                        Observation2D => String::from("Observation2D"),
-                        Position3D => String::from("Position3D"),
-                        Odometry3D => String::from("Odometry3D"),
-                        Observation3D => String::from("Observation3D"),
-                    },
-                    vertices: edge_vertices,
-                    restriction: factor.constraint.clone(),
-                    information_matrix: factor.information_matrix.content.as_slice().to_owned(),
-                });
+                indices.extend(edge.weight().switch_index);
+                model.edges.push(get_model_edge(factor_graph, edge.weight(), &indices));
             }
             if node.get_fixed_type() == &FixedType::Fixed {
                 model.fixed_vertices.insert(node.get_id());
             }
         }
+        for prior in &factor_graph.linearized_priors {
+            model
+                .edges
+                .push(get_model_edge(factor_graph, prior, &prior.prior_indices));
+        }
         model
     }
 }
 
-fn add_edge(factor_graph: &mut FactorGraph, edge: &Edge) {
-    let (target_index, factor_type) = match edge.edge_type.as_str() {
-        "Position2D" => (0, Position2D),
-        "Odometry2D" => (1, Odometry2D),
-        "Observation2D" => (1, Observation2D),
-        "Position3D" => (0, Position3D),
-        "Odometry3D" => (1, Odometry3D),
-        "Observation3D" => (1, Observation3D),
-        other_type => panic!("Unsupported edge type in the model: {}", other_type),
-    };
-    factor_graph.csr.add_edge(
-        factor_graph.custom_to_csr_id_map[&edge.vertices[0]],
-        factor_graph.custom_to_csr_id_map[&edge.vertices[target_index]],
-        Factor {
-            factor_type,
-            constraint: edge.restriction.to_vec(),
-            information_matrix: edge.information_matrix.to_vec().into(),
+/// Returns the edge of the model representing the given factor on the variables with the given internal CSR indices.
+fn get_model_edge(factor_graph: &FactorGraph, factor: &Factor, indices: &[usize]) -> Edge {
+    Edge {
+        edge_type: match factor.factor_type {
+            Position2D => String::from("Position2D"),
+            Odometry2D => String::from("Odometry2D"),
+            Observation2D => String::from("Observation2D"),
+            Position3D => String::from("Position3D"),
+            Odometry3D => String::from("Odometry3D"),
+            Observation3D => String::from("Observation3D"),
+            SwitchableOdometry2D => String::from("SwitchableOdometry2D"),
+            SwitchableOdometry3D => String::from("SwitchableOdometry3D"),
+            SwitchPrior => String::from("SwitchPrior"),
+            LinearizedPrior => String::from("LinearizedPrior"),
         },
-    );
+        vertices: indices
+            .iter()
+            .map(|index| factor_graph.csr.index(*index).get_id())
+            .collect(),
+        restriction: factor.constraint.clone(),
+        information_matrix: factor.information_matrix.content.as_slice().to_owned(),
+        robust_kernel: factor_graph.get_robust_kernel(factor).map(|kernel| kernel.into()),
+    }
 }
 
-fn add_vertex(factor_graph: &mut FactorGraph, vertex: &Vertex, fixed: bool) {
-    match vertex.vertex_type.as_str() {
-        "Vehicle2D" => factor_graph
-            .node_indices
-            .push(factor_graph.csr.add_node(Variable::Vehicle2D(VehicleVariable2D::new(
-                vertex.id,
-                vertex.content[0],
-                vertex.content[1],
-                vertex.content[2],
-                add_var_to_matrix(&mut factor_graph.matrix_dim, 3, fixed),
-            )))),
-        "Landmark2D" => factor_graph
-            .node_indices
-            .push(factor_graph.csr.add_node(Variable::Landmark2D(LandmarkVariable2D::new(
-                vertex.id,
-                vertex.content[0],
-                vertex.content[1],
-                add_var_to_matrix(&mut factor_graph.matrix_dim, 2, fixed),
-            )))),
-        "Vehicle3D" => factor_graph
-            .node_indices
-            .push(factor_graph.csr.add_node(Variable::Vehicle3D(VehicleVariable3D::new(
-                vertex.id,
-                vertex.content[0],
-                vertex.content[1],
-                vertex.content[2],
-                vertex.content[3],
-                vertex.content[4],
-                vertex.content[5],
-                vertex.content[6],
-                add_var_to_matrix(&mut factor_graph.matrix_dim, 6, fixed),
-            )))),
-        "Landmark3D" => factor_graph
-            .node_indices
-            .push(factor_graph.csr.add_node(Variable::Landmark3D(LandmarkVariable3D::new(
-                vertex.id,
-                vertex.content[0],
-                vertex.content[1],
-                vertex.content[2],
-                add_var_to_matrix(&mut factor_graph.matrix_dim, 3, fixed),
-            )))),
-        other_type => panic!("Unsupported vertex type in the model: {}", other_type),
+fn add_edge(factor_graph: &mut FactorGraph, edge: &Edge) -> Result<(), Error> {
+    let (vertex_count, factor_type) = match edge.edge_type.as_str() {
+        "Position2D" => (1, Position2D),
+        "Odometry2D" => (2, Odometry2D),
+        "Observation2D" => (2, Observation2D),
+        "Position3D" => (1, Position3D),
+        "Odometry3D" => (2, Odometry3D),
+        "Observation3D" => (2, Observation3D),
+        "SwitchableOdometry2D" => (3, SwitchableOdometry2D),
+        "SwitchableOdometry3D" => (3, SwitchableOdometry3D),
+        "SwitchPrior" => (1, SwitchPrior),
+        "LinearizedPrior" => (edge.vertices.len().max(1), LinearizedPrior),
+        other_type => return Err(Error::UnknownEdgeType(String::from(other_type))),
+    };
+    if edge.vertices.len() != vertex_count {
+        return Err(Error::DimensionMismatch {
+            context: format!("Vertices of {} edge", edge.edge_type),
+            expected: vertex_count,
+            actual: edge.vertices.len(),
+        });
+    }
+    let dim = (edge.information_matrix.len() as f64).sqrt() as usize;
+    if dim * dim != edge.information_matrix.len() {
+        let expected_dim = factor_graph.get_information_dim(&factor_type, &edge.vertices)?;
+        return Err(Error::DimensionMismatch {
+            context: format!("Information matrix of {} edge", edge.edge_type),
+            expected: expected_dim * expected_dim,
+            actual: edge.information_matrix.len(),
+        });
+    }
+    let factor = Factor {
+        factor_type,
+        constraint: edge.restriction.to_vec(),
+        information_matrix: edge.information_matrix.to_vec().into(),
+        robust_kernel: edge
+            .robust_kernel
+            .as_ref()
+            .map(|kernel| kernel.try_into())
+            .transpose()?,
+        switch_index: None,
+        prior_indices: vec![],
     };
-    factor_graph
-        .custom_to_csr_id_map
-        .insert(vertex.id, *factor_graph.node_indices.last().unwrap());
+    factor_graph.add_factor(factor, &edge.vertices)
 }
 
-fn add_var_to_matrix(dim: &mut usize, added_dim: usize, fixed: bool) -> FixedType {
-    if fixed {
+impl TryFrom<&RobustKernel> for factor::robust_kernel::RobustKernel {
+    type Error = Error;
+
+    fn try_from(kernel: &RobustKernel) -> Result<Self, Error> {
+        use factor::robust_kernel::RobustKernel::*;
+        match kernel.kernel_type.as_str() {
+            "Huber" => Ok(Huber(kernel.delta)),
+            "Cauchy" => Ok(Cauchy(kernel.delta)),
+            "Tukey" => Ok(Tukey(kernel.delta)),
+            "GemanMcClure" => Ok(GemanMcClure(kernel.delta)),
+            "DCS" => Ok(Dcs(kernel.delta)),
+            other_type => Err(Error::UnknownRobustKernelType(String::from(other_type))),
+        }
+    }
+}
+
+impl From<factor::robust_kernel::RobustKernel> for RobustKernel {
+    fn from(kernel: factor::robust_kernel::RobustKernel) -> Self {
+        use factor::robust_kernel::RobustKernel::*;
+        RobustKernel {
+            kernel_type: String::from(match kernel {
+                Huber(_) => "Huber",
+                Cauchy(_) => "Cauchy",
+                Tukey(_) => "Tukey",
+                GemanMcClure(_) => "GemanMcClure",
+                Dcs(_) => "DCS",
+            }),
+            delta: kernel.delta(),
+        }
+    }
+}
+
+fn add_vertex(factor_graph: &mut FactorGraph, vertex: &Vertex, fixed: bool) -> Result<(), Error> {
+    let content_len = match vertex.vertex_type.as_str() {
+        "Vehicle2D" => 3,
+        "Landmark2D" => 2,
+        "Vehicle3D" => 7,
+        "Landmark3D" => 3,
+        "Switch" => 1,
+        other_type => return Err(Error::UnknownVertexType(String::from(other_type))),
+    };
+    if vertex.content.len() != content_len {
+        return Err(Error::DimensionMismatch {
+            context: format!("Content of {} vertex {}", vertex.vertex_type, vertex.id),
+            expected: content_len,
+            actual: vertex.content.len(),
+        });
+    }
+    // the rows of non-fixed variables are assigned when adding them
+    let fixed_type = if fixed {
         FixedType::Fixed
     } else {
-        *dim += added_dim;
-        FixedType::NonFixed(*dim - added_dim..*dim)
-    }
+        FixedType::NonFixed(0..0)
+    };
+    let c = &vertex.content;
+    let variable = match vertex.vertex_type.as_str() {
+        "Vehicle2D" => Variable::Vehicle2D(VehicleVariable2D::new(vertex.id, c[0], c[1], c[2], fixed_type)),
+        "Landmark2D" => Variable::Landmark2D(LandmarkVariable2D::new(vertex.id, c[0], c[1], fixed_type)),
+        "Vehicle3D" => Variable::Vehicle3D(VehicleVariable3D::new(
+            vertex.id, c[0], c[1], c[2], c[3], c[4], c[5], c[6], fixed_type,
+        )),
+        "Landmark3D" => Variable::Landmark3D(LandmarkVariable3D::new(vertex.id, c[0], c[1], c[2], fixed_type)),
+        _ => Variable::Switch(SwitchVariable::new(vertex.id, c[0], fixed_type)),
+    };
+    factor_graph.add_variable(variable)?;
+    Ok(())
 }
@@ -17,6 +17,7 @@ use std::collections::BTreeSet;
 use std::fmt::Debug;
 
 mod converter;
+pub mod validation;
 
 /// Structure containing the serializable model of a factor graph.
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -47,6 +48,8 @@ pub struct Vertex {
     /// Content for "Vehicle3D": vec![position_x, position_y, position_z, quaternion_x, quaternion_y, quaternion_z, quaternion_w]
     ///
     /// Content for "Landmark3D": vec![position_x, position_y, position_z]
+    ///
+    /// Content for "Switch": vec![switch_value]
     pub content: Vec<f64>,
 }
 
@@ -69,6 +72,14 @@ pub struct Edge {
     /// Content for "Odometry3D": vec![Vehicle3D_vertex, Vehicle3D_vertex]
     ///
     /// Content for "Observation3D": vec![Vehicle3D_vertex, Landmark3D_vertex]
+    ///
+    /// Content for "SwitchableOdometry2D": vec![Vehicle2D_vertex, Vehicle2D_vertex, Switch_vertex]
+    ///
+    /// Content for "SwitchableOdometry3D": vec![Vehicle3D_vertex, Vehicle3D_vertex, Switch_vertex]
+    ///
+    /// Content for "SwitchPrior": vec![Switch_vertex]
+    ///
+    /// Content for "LinearizedPrior": the IDs of all of the prior's vertices, in the order of its rows
     pub vertices: Vec<usize>,
     /// The edge's restriction, representing a measurement. The structure depends on the edge's type:
     ///
@@ -83,8 +94,27 @@ pub struct Edge {
     /// Content for "Odometry3D": vec![delta_position_x, delta_position_y, delta_position_z, quaternion_x, quaternion_y, quaternion_z, quaternion_w]
     ///
     /// Content for "Observation3D": vec![delta_position_x, delta_position_y, delta_position_z]
+    ///
+    /// Content for "SwitchableOdometry2D" and "SwitchableOdometry3D": see "Odometry2D" and "Odometry3D"
+    ///
+    /// Content for "SwitchPrior": vec![switch_value]
+    ///
+    /// Content for "LinearizedPrior": the concatenated contents of the prior's mean for all of its vertices
     pub restriction: Vec<f64>,
     /// The edge's entire information matrix. It is expected to be symmetric, hence having identical row- and column-major representations.
     #[serde(rename = "informationMatrix")]
     pub information_matrix: Vec<f64>,
+    /// The edge's robust kernel. If None, the edge's residual is weighted purely by its information matrix.
+    #[serde(rename = "robustKernel", default, skip_serializing_if = "Option::is_none")]
+    pub robust_kernel: Option<RobustKernel>,
+}
+
+/// Structure containing an edge's robust kernel.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct RobustKernel {
+    /// The kernel's type, named as in g2o. Supported types: "Huber", "Cauchy", "Tukey", "GemanMcClure", "DCS"
+    #[serde(rename = "type")]
+    pub kernel_type: String,
+    /// The kernel width.
+    pub delta: f64,
 }
@@ -0,0 +1,586 @@
+// -----------------------------------------------------------------------------------------------------
+//                                      gs-rs - Graph SLAM in Rust
+// -----------------------------------------------------------------------------------------------------
+//
+// SPDX-FileCopyrightText:      © 2020 Samuel Valenzuela (samuel.valenzuela@tngtech.com)
+//                              © 2020 Florian Rohm (florian.rohm@tngtech.com)
+//                              © 2020 Daniel Pape (daniel.pape@tngtech.com)
+// SPDX-License-Identifier:     MIT OR Apache-2.0
+//
+// This product includes software developed at TNG Technology Consulting GmbH (https://www.tngtech.com/).
+//
+
+//! Validation of factor graph models, reporting all problems at once instead of failing at the first one.
+
+use crate::factor_graph::factor::{robust_kernel, InformationMatrix};
+use crate::factor_graph::FactorGraph;
+use crate::parser::model::{Edge, FactorGraphModel, Vertex};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Maximum deviation of a rotation quaternion's norm from 1 which is not reported.
+const QUATERNION_NORM_TOLERANCE: f64 = 1e-5;
+
+/// Severity of a validation issue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The model can be converted and optimized, but the result may not be as expected.
+    Warning,
+    /// The model cannot be converted to a factor graph or not be optimized.
+    Error,
+}
+
+/// Kind of problem found during validation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// A vertex type which is not supported.
+    UnknownVertexType,
+    /// An edge type which is not supported.
+    UnknownEdgeType,
+    /// A robust kernel type which is not supported.
+    UnknownRobustKernelType,
+    /// A vertex or edge with a wrong number of values or vertices.
+    DimensionMismatch,
+    /// A vertex or edge containing values which are not finite.
+    NonFiniteValue,
+    /// A vertex ID which is used by more than one vertex.
+    DuplicateVertexId,
+    /// An edge or fixed vertex referencing a vertex ID which does not exist.
+    UnknownVertexId,
+    /// An edge whose vertices do not fit its type or which contains a vertex more than once.
+    InvalidEdgeVertices,
+    /// An edge from and to the same vertices as a preceding edge.
+    DuplicateEdge,
+    /// An information matrix which is not symmetric.
+    AsymmetricInformationMatrix,
+    /// A symmetric information matrix with negative eigenvalues.
+    IndefiniteInformationMatrix,
+    /// A rotation quaternion whose norm is not 1. Quaternions with norm 0 are errors, all others warnings.
+    NonUnitQuaternion,
+    /// Parts of the factor graph which are not connected with each other by any edge.
+    DisconnectedComponents,
+}
+
+/// A problem found during validation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    /// Whether the problem prevents the conversion or optimization of the model.
+    pub severity: Severity,
+    /// The kind of problem.
+    pub kind: IssueKind,
+    /// Description of the problem, stating the affected vertex IDs or edge index.
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.severity, self.message)
+    }
+}
+
+impl FactorGraphModel {
+    /// Returns all problems of the model in the order in which they were found.
+    ///
+    /// A model without errors can be converted to a factor graph.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = vec![];
+        let vertex_types = validate_vertices(&self.vertices, &mut issues);
+        for id in self.fixed_vertices.iter().filter(|id| !vertex_types.contains_key(id)) {
+            issues.push(issue(
+                Severity::Warning,
+                IssueKind::UnknownVertexId,
+                format!("Fixed vertex {} does not exist", id),
+            ));
+        }
+        let mut connections = BTreeSet::new();
+        for (index, edge) in self.edges.iter().enumerate() {
+            validate_edge(index, edge, &vertex_types, &mut connections, &mut issues);
+        }
+        validate_connectivity(&self.edges, &vertex_types, &mut issues);
+        issues
+    }
+}
+
+impl FactorGraph {
+    /// Returns all problems of the factor graph as stated by FactorGraphModel::validate().
+    ///
+    /// Only warnings are possible, e.g. for non-unit quaternions or disconnected components, as the construction of
+    /// a factor graph already fails for errors.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        FactorGraphModel::from(self).validate()
+    }
+}
+
+fn issue(severity: Severity, kind: IssueKind, message: String) -> ValidationIssue {
+    ValidationIssue {
+        severity,
+        kind,
+        message,
+    }
+}
+
+/// Returns the length of the content and the dimension within H of the given vertex type.
+fn get_vertex_layout(vertex_type: &str) -> Option<(usize, usize)> {
+    match vertex_type {
+        "Vehicle2D" => Some((3, 3)),
+        "Landmark2D" => Some((2, 2)),
+        "Vehicle3D" => Some((7, 6)),
+        "Landmark3D" => Some((3, 3)),
+        "Switch" => Some((1, 1)),
+        _ => None,
+    }
+}
+
+/// Returns the vertex types, the length of the restriction and the dimension of the information matrix of the given
+/// edge type. Linearized priors are not included, as their layout depends on their vertices.
+fn get_edge_layout(edge_type: &str) -> Option<(&'static [&'static str], usize, usize)> {
+    match edge_type {
+        "Position2D" => Some((&["Vehicle2D"], 3, 3)),
+        "Odometry2D" => Some((&["Vehicle2D", "Vehicle2D"], 3, 3)),
+        "Observation2D" => Some((&["Vehicle2D", "Landmark2D"], 2, 2)),
+        "Position3D" => Some((&["Vehicle3D"], 7, 6)),
+        "Odometry3D" => Some((&["Vehicle3D", "Vehicle3D"], 7, 6)),
+        "Observation3D" => Some((&["Vehicle3D", "Landmark3D"], 3, 3)),
+        "SwitchableOdometry2D" => Some((&["Vehicle2D", "Vehicle2D", "Switch"], 3, 3)),
+        "SwitchableOdometry3D" => Some((&["Vehicle3D", "Vehicle3D", "Switch"], 7, 6)),
+        "SwitchPrior" => Some((&["Switch"], 1, 1)),
+        _ => None,
+    }
+}
+
+/// Validates all vertices and returns the types of all vertex IDs, taking the first vertex of duplicate IDs.
+fn validate_vertices<'a>(vertices: &'a [Vertex], issues: &mut Vec<ValidationIssue>) -> BTreeMap<usize, &'a str> {
+    let mut vertex_types = BTreeMap::new();
+    for vertex in vertices {
+        let context = format!("{} vertex {}", vertex.vertex_type, vertex.id);
+        if vertex_types.contains_key(&vertex.id) {
+            issues.push(issue(
+                Severity::Error,
+                IssueKind::DuplicateVertexId,
+                format!("Vertex ID {} is used more than once", vertex.id),
+            ));
+            continue;
+        }
+        vertex_types.insert(vertex.id, vertex.vertex_type.as_str());
+        let content_len = match get_vertex_layout(&vertex.vertex_type) {
+            Some((content_len, _)) => content_len,
+            None => {
+                issues.push(issue(
+                    Severity::Error,
+                    IssueKind::UnknownVertexType,
+                    format!("Type of {} is not supported", context),
+                ));
+                continue;
+            }
+        };
+        if vertex.content.len() != content_len {
+            issues.push(issue(
+                Severity::Error,
+                IssueKind::DimensionMismatch,
+                format!(
+                    "Content of {} has {} instead of {} values",
+                    context,
+                    vertex.content.len(),
+                    content_len
+                ),
+            ));
+        } else if vertex.content.iter().any(|value| !value.is_finite()) {
+            issues.push(issue(
+                Severity::Error,
+                IssueKind::NonFiniteValue,
+                format!("Content of {} contains values which are not finite", context),
+            ));
+        } else if vertex.vertex_type == "Vehicle3D" {
+            validate_quaternion(&vertex.content[3..], &context, issues);
+        }
+    }
+    vertex_types
+}
+
+/// Validates the edge with the given index, registering its source and target among the given connections
+/// in order to find duplicate edges. Linearized priors are stored apart from all other edges and never duplicate them.
+fn validate_edge(
+    index: usize,
+    edge: &Edge,
+    vertex_types: &BTreeMap<usize, &str>,
+    connections: &mut BTreeSet<(usize, usize)>,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    let context = format!("{} edge {}", edge.edge_type, index);
+    let types: Option<Vec<&str>> = edge.vertices.iter().map(|id| vertex_types.get(id).copied()).collect();
+    for id in edge.vertices.iter().filter(|id| !vertex_types.contains_key(id)) {
+        issues.push(issue(
+            Severity::Error,
+            IssueKind::UnknownVertexId,
+            format!("{} references the unknown vertex {}", context, id),
+        ));
+    }
+    let vertex_set: BTreeSet<&usize> = edge.vertices.iter().collect();
+    if vertex_set.len() != edge.vertices.len() {
+        issues.push(issue(
+            Severity::Error,
+            IssueKind::InvalidEdgeVertices,
+            format!("{} contains a vertex more than once", context),
+        ));
+    }
+    let layout = if edge.edge_type == "LinearizedPrior" {
+        types
+            .and_then(|types| {
+                types
+                    .iter()
+                    .map(|vertex_type| get_vertex_layout(vertex_type))
+                    .collect::<Option<Vec<(usize, usize)>>>()
+            })
+            .filter(|layouts| !layouts.is_empty())
+            .map(|layouts| {
+                layouts
+                    .iter()
+                    .fold((0, 0), |(restriction_len, dim), (content_len, var_dim)| {
+                        (restriction_len + content_len, dim + var_dim)
+                    })
+            })
+    } else {
+        let (expected_types, restriction_len, dim) = match get_edge_layout(&edge.edge_type) {
+            Some(layout) => layout,
+            None => {
+                issues.push(issue(
+                    Severity::Error,
+                    IssueKind::UnknownEdgeType,
+                    format!("Type of {} is not supported", context),
+                ));
+                return;
+            }
+        };
+        if edge.vertices.len() != expected_types.len() {
+            issues.push(issue(
+                Severity::Error,
+                IssueKind::DimensionMismatch,
+                format!(
+                    "{} has {} instead of {} vertices",
+                    context,
+                    edge.vertices.len(),
+                    expected_types.len()
+                ),
+            ));
+        } else if let Some(types) = types {
+            if types != expected_types {
+                issues.push(issue(
+                    Severity::Error,
+                    IssueKind::InvalidEdgeVertices,
+                    format!(
+                        "{} expects vertices of the types {:?} instead of {:?}",
+                        context, expected_types, types
+                    ),
+                ));
+            }
+            let source = edge.vertices[0];
+            let target = *edge.vertices.get(1).unwrap_or(&source);
+            if !connections.insert((source, target)) {
+                issues.push(issue(
+                    Severity::Error,
+                    IssueKind::DuplicateEdge,
+                    format!(
+                        "{} connects the vertices {} and {} like a preceding edge",
+                        context, source, target
+                    ),
+                ));
+            }
+        }
+        Some((restriction_len, dim))
+    };
+    if let Some(kernel) = &edge.robust_kernel {
+        if robust_kernel::RobustKernel::try_from(kernel).is_err() {
+            issues.push(issue(
+                Severity::Error,
+                IssueKind::UnknownRobustKernelType,
+                format!(
+                    "Robust kernel type {} of {} is not supported",
+                    kernel.kernel_type, context
+                ),
+            ));
+        }
+    }
+    if let Some((restriction_len, dim)) = layout {
+        validate_edge_values(edge, &context, restriction_len, dim, issues);
+    }
+}
+
+/// Validates the restriction and information matrix of an edge against the given expected dimensions.
+fn validate_edge_values(
+    edge: &Edge,
+    context: &str,
+    restriction_len: usize,
+    dim: usize,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    let mut mismatch = |name: &str, expected: usize, actual: usize| {
+        issues.push(issue(
+            Severity::Error,
+            IssueKind::DimensionMismatch,
+            format!("{} of {} has {} instead of {} values", name, context, actual, expected),
+        ))
+    };
+    let restriction_fits = edge.restriction.len() == restriction_len;
+    if !restriction_fits {
+        mismatch("Restriction", restriction_len, edge.restriction.len());
+    }
+    let information_fits = edge.information_matrix.len() == dim * dim;
+    if !information_fits {
+        mismatch("Information matrix", dim * dim, edge.information_matrix.len());
+    }
+    if edge
+        .restriction
+        .iter()
+        .chain(edge.information_matrix.iter())
+        .any(|value| !value.is_finite())
+    {
+        issues.push(issue(
+            Severity::Error,
+            IssueKind::NonFiniteValue,
+            format!("{} contains values which are not finite", context),
+        ));
+        return;
+    }
+    if information_fits {
+        let information = InformationMatrix::from(edge.information_matrix.clone());
+        if !information.is_symmetric() {
+            issues.push(issue(
+                Severity::Error,
+                IssueKind::AsymmetricInformationMatrix,
+                format!("Information matrix of {} is not symmetric", context),
+            ));
+        } else if !information.is_positive_semi_definite() {
+            issues.push(issue(
+                Severity::Error,
+                IssueKind::IndefiniteInformationMatrix,
+                format!("Information matrix of {} has negative eigenvalues", context),
+            ));
+        }
+    }
+    let has_quaternion = matches!(
+        edge.edge_type.as_str(),
+        "Position3D" | "Odometry3D" | "SwitchableOdometry3D"
+    );
+    if has_quaternion && restriction_fits {
+        validate_quaternion(&edge.restriction[3..], context, issues);
+    }
+}
+
+/// Validates the rotation quaternion [x, y, z, w] of the vertex or edge described by the given context.
+fn validate_quaternion(quaternion: &[f64], context: &str, issues: &mut Vec<ValidationIssue>) {
+    let norm = quaternion.iter().map(|value| value * value).sum::<f64>().sqrt();
+    if norm == 0.0 {
+        issues.push(issue(
+            Severity::Error,
+            IssueKind::NonUnitQuaternion,
+            format!("Rotation quaternion of {} has norm 0", context),
+        ));
+    } else if (norm - 1.0).abs() > QUATERNION_NORM_TOLERANCE {
+        issues.push(issue(
+            Severity::Warning,
+            IssueKind::NonUnitQuaternion,
+            format!("Rotation quaternion of {} has norm {} instead of 1", context, norm),
+        ));
+    }
+}
+
+/// Reports all vertices which are not connected with the first vertex by any path of edges.
+fn validate_connectivity(edges: &[Edge], vertex_types: &BTreeMap<usize, &str>, issues: &mut Vec<ValidationIssue>) {
+    let mut neighbors: BTreeMap<usize, Vec<usize>> = vertex_types.keys().map(|id| (*id, vec![])).collect();
+    for edge in edges {
+        let known: Vec<usize> = edge
+            .vertices
+            .iter()
+            .copied()
+            .filter(|id| vertex_types.contains_key(id))
+            .collect();
+        for pair in known.windows(2) {
+            neighbors.get_mut(&pair[0]).unwrap().push(pair[1]);
+            neighbors.get_mut(&pair[1]).unwrap().push(pair[0]);
+        }
+    }
+    let mut visited = BTreeSet::new();
+    let mut component_ids = vec![];
+    for id in vertex_types.keys() {
+        if !visited.insert(*id) {
+            continue;
+        }
+        component_ids.push(*id);
+        let mut stack = vec![*id];
+        while let Some(current) = stack.pop() {
+            for neighbor in &neighbors[&current] {
+                if visited.insert(*neighbor) {
+                    stack.push(*neighbor);
+                }
+            }
+        }
+    }
+    if component_ids.len() > 1 {
+        issues.push(issue(
+            Severity::Warning,
+            IssueKind::DisconnectedComponents,
+            format!(
+                "Factor graph consists of {} disconnected components with the smallest vertex IDs {:?}",
+                component_ids.len(),
+                component_ids
+            ),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::parser::g2o::G2oParser;
+    use crate::parser::json::JsonParser;
+    use crate::parser::{ParseOptions, Parser, ValidationMode};
+    use log::LevelFilter;
+    use std::fs;
+
+    fn init() {
+        let _ = env_logger::builder()
+            .is_test(true)
+            .filter_level(LevelFilter::Debug)
+            .try_init();
+    }
+
+    fn get_kinds(issues: &[ValidationIssue]) -> Vec<(Severity, IssueKind)> {
+        issues.iter().map(|issue| (issue.severity, issue.kind)).collect()
+    }
+
+    #[test]
+    fn test_valid_files() {
+        init();
+        let model = G2oParser::parse_file_to_model("data_files/full_demos/all_2d_types.g2o").unwrap();
+        assert_eq!(model.validate(), vec![]);
+        let factor_graph = JsonParser::parse_file("data_files/full_demos/all_3d_types.json").unwrap();
+        assert_eq!(factor_graph.validate(), vec![]);
+
+        let issues = G2oParser::parse_file("data_files/optimizer_tests/pos3d_only_0.g2o")
+            .unwrap()
+            .validate();
+        assert_eq!(
+            get_kinds(&issues),
+            vec![(Severity::Warning, IssueKind::DisconnectedComponents)]
+        );
+        assert_eq!(
+            issues[0].message,
+            "Factor graph consists of 5 disconnected components with the smallest vertex IDs [1, 2, 3, 4, 5]"
+        );
+    }
+
+    #[test]
+    fn test_invalid_model() {
+        init();
+        let model = G2oParser::parse_string_to_model(
+            "VERTEX_SE2 0 1.0 0.0 1.57\n\
+             VERTEX_SE2 1 0.0 1.0 3.14\n\
+             VERTEX_XY 1 1.5 2.0\n\
+             VERTEX_SE3:QUAT 2 0.0 0.0 0.0 0.0 0.0 0.0 2.0\n\
+             FIX 3\n\
+             EDGE_SE2 0 1 1.0 1.5 1.57 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2 0 1 1.0 1.5 1.57 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             EDGE_SE2_XY 0 4 0.0 -1.0 1.0 0.0 1.0\n\
+             EDGE_PRIOR_SE2 1 0.0 1.0 3.13 1.0 2.0 0.0 1.0 0.0 1.0",
+        )
+        .unwrap();
+        let issues = model.validate();
+        assert_eq!(
+            get_kinds(&issues),
+            vec![
+                (Severity::Error, IssueKind::DuplicateVertexId),
+                (Severity::Warning, IssueKind::NonUnitQuaternion),
+                (Severity::Warning, IssueKind::UnknownVertexId),
+                (Severity::Error, IssueKind::DuplicateEdge),
+                (Severity::Error, IssueKind::UnknownVertexId),
+                (Severity::Error, IssueKind::IndefiniteInformationMatrix),
+                (Severity::Warning, IssueKind::DisconnectedComponents),
+            ]
+        );
+        assert_eq!(
+            issues[3].message,
+            "Odometry2D edge 1 connects the vertices 0 and 1 like a preceding edge"
+        );
+        assert_eq!(
+            issues[4].message,
+            "Observation2D edge 2 references the unknown vertex 4"
+        );
+
+        let mut model = G2oParser::parse_string_to_model(
+            "VERTEX_SE2 0 1.0 0.0 1.57\n\
+             VERTEX_XY 1 1.5 2.0\n\
+             EDGE_SE2 0 1 1.0 1.5 1.57 1.0 0.0 0.0 1.0 0.0 1.0\n\
+             ROBUST_KERNEL Foo 1.0",
+        )
+        .unwrap();
+        model.vertices[1].content.push(f64::NAN);
+        model.edges[0].information_matrix[1] = 0.5;
+        assert_eq!(
+            get_kinds(&model.validate()),
+            vec![
+                (Severity::Error, IssueKind::DimensionMismatch),
+                (Severity::Error, IssueKind::InvalidEdgeVertices),
+                (Severity::Error, IssueKind::UnknownRobustKernelType),
+                (Severity::Error, IssueKind::AsymmetricInformationMatrix),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_linearized_prior_with_position() {
+        init();
+        let model = JsonParser::parse_string_to_model(
+            r#"{
+                "vertices": [{ "id": 0, "type": "Vehicle2D", "content": [1.0, 0.0, 1.57] }],
+                "edges": [
+                    {
+                        "type": "LinearizedPrior",
+                        "vertices": [0],
+                        "restriction": [1.0, 0.0, 1.57],
+                        "informationMatrix": [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]
+                    },
+                    {
+                        "type": "Position2D",
+                        "vertices": [0],
+                        "restriction": [1.0, 0.0, 1.57],
+                        "informationMatrix": [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]
+                    }
+                ],
+                "fixedVertices": []
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(model.validate(), vec![]);
+        assert!(FactorGraph::try_from(model).is_ok());
+    }
+
+    #[test]
+    fn test_parse_with_validation() {
+        init();
+        // unique per process, so that concurrent runs of the test suite do not share the file
+        let file_path = std::env::temp_dir().join(format!("gs_rs_validation_test_{}.g2o", std::process::id()));
+        let file_path = file_path.to_str().unwrap();
+        fs::write(file_path, "VERTEX_SE2 0 1.0 0.0 1.57\nVERTEX_XY 1 1.5 2.0").unwrap();
+        let parse = |validation| G2oParser::parse_file_with_options(file_path, &ParseOptions { validation });
+        assert!(parse(ValidationMode::Skip).is_ok());
+        assert!(parse(ValidationMode::RejectErrors).is_ok());
+        match parse(ValidationMode::RejectWarnings) {
+            Err(Error::Validation(issues)) => assert_eq!(
+                get_kinds(&issues),
+                vec![(Severity::Warning, IssueKind::DisconnectedComponents)]
+            ),
+            result => panic!("Unexpected result: {:?}", result.map(|_| ())),
+        }
+
+        fs::write(file_path, "VERTEX_SE2 0 1.0 0.0 1.57\nVERTEX_SE2 0 1.5 2.0 0.0").unwrap();
+        assert_eq!(parse(ValidationMode::Skip).unwrap_err(), Error::DuplicateVertexId(0));
+        let error = parse(ValidationMode::RejectErrors).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Validation failed: Error: Vertex ID 0 is used more than once"
+        );
+        fs::remove_file(file_path).unwrap();
+    }
+}
@@ -15,7 +15,7 @@
 use crate::factor_graph::FactorGraph;
 use crate::factor_graph::{
     factor::{Factor, FactorType::*},
-    variable::{LandmarkVariable2D, LandmarkVariable3D, Variable, VehicleVariable2D, VehicleVariable3D},
+    variable::Variable,
 };
 use kiss3d::camera::ArcBall;
 use kiss3d::scene::SceneNode;
@@ -33,10 +33,11 @@ struct VisualFactorGraph {
 pub fn visualize(factor_graph: &FactorGraph) {
     let mut window = Window::new("gs-rs");
     let visual_factor_graph = add_factor_graph_to_window(&mut window, &factor_graph);
-    let init_point = match factor_graph.node_indices.len() {
-        0 => Point3::new(0.0, 0.0, 0.0),
-        _ => get_var_point(factor_graph.get_var(factor_graph.node_indices[0])),
-    };
+    let init_point = factor_graph
+        .node_indices
+        .iter()
+        .find_map(|i| get_var_point(factor_graph.get_var(*i)))
+        .unwrap_or_else(|| Point3::new(0.0, 0.0, 0.0));
     let mut cam = ArcBall::new(Point3::new(0.0, 0.0, 50.0), init_point);
     while window.render_with_camera(&mut cam) {
         visual_factor_graph
@@ -52,6 +53,7 @@ fn add_factor_graph_to_window(window: &mut Window, factor_graph: &FactorGraph) -
         lines: vec![],
     };
 
+    // switch variables, their priors and linearized priors have no spatial representation and are skipped
     factor_graph
         .node_indices
         .iter()
@@ -72,23 +74,34 @@ fn add_factor_graph_to_window(window: &mut Window, factor_graph: &FactorGraph) -
 }
 
 fn add_var(visual_factor_graph: &mut VisualFactorGraph, var: &Variable) {
-    let var_point = get_var_point(var);
+    let var_point = match get_var_point(var) {
+        Some(var_point) => var_point,
+        None => return,
+    };
     let mut var_object = add_var_core(visual_factor_graph, &var_point);
     handle_var_rotation(var, &mut var_object);
     color_var_object(var, &mut var_object);
 }
 
 fn add_factor(visual_factor_graph: &mut VisualFactorGraph, factor: &Factor, source: &Variable, target: &Variable) {
-    let meas_point = calc_meas_point(factor, source);
+    let (source_point, target_point) = match (get_var_point(source), get_var_point(target)) {
+        (Some(source_point), Some(target_point)) => (source_point, target_point),
+        _ => return,
+    };
+    let (meas_point, color) = match (calc_meas_point(factor, source, source_point), get_factor_color(factor)) {
+        (Some(meas_point), Some(color)) => (meas_point, color),
+        _ => return,
+    };
     let mut meas_object = add_factor_core(visual_factor_graph, &meas_point);
     handle_factor_rotation(factor, &mut meas_object, source);
-    color_meas_object(factor, &mut meas_object);
+    color_meas_object(color, &mut meas_object);
     add_factor_lines(
         visual_factor_graph,
         factor,
+        color,
         meas_point,
-        get_var_point(source),
-        get_var_point(target),
+        source_point,
+        target_point,
     );
 }
 
@@ -104,10 +117,10 @@ fn handle_var_rotation(var: &Variable, var_object: &mut SceneNode) {
     match var {
         Variable::Vehicle2D(v) => rot_object.set_local_rotation(UnitQuaternion::from_axis_angle(
             &Vector3::z_axis(),
-            get_rot_from_2d(&*v.pose.borrow()),
+            get_rot_from_2d(&v.pose()),
         )),
         Variable::Vehicle3D(v) => {
-            rot_object.set_local_rotation(get_rot_from_3d(&*v.pose.borrow()));
+            rot_object.set_local_rotation(get_rot_from_3d(&v.pose()));
         }
         _ => (),
     }
@@ -119,24 +132,27 @@ fn color_var_object(var: &Variable, var_object: &mut SceneNode) {
     match var {
         Variable::Vehicle2D(_) | Variable::Vehicle3D(_) => var_object.set_color(1.0, 0.0, 0.0),
         Variable::Landmark2D(_) | Variable::Landmark3D(_) => var_object.set_color(0.0, 1.0, 0.0),
+        Variable::Switch(_) => (),
     };
 }
 
-fn calc_meas_point(factor: &Factor, source: &Variable) -> Point3<f32> {
-    let factor_point = get_factor_point(factor);
-    match factor.factor_type {
+fn calc_meas_point(factor: &Factor, source: &Variable, source_point: Point3<f32>) -> Option<Point3<f32>> {
+    let factor_point = get_factor_point(factor)?;
+    let meas_point = match factor.factor_type {
         Position2D | Position3D => factor_point,
-        Odometry2D | Observation2D => {
+        Odometry2D | Observation2D | SwitchableOdometry2D => {
             let source_rot = get_rot_from_2d(&source.get_content());
             let local_point = Rotation3::new(Vector3::z() * source_rot) * factor_point;
-            (get_var_point(source).coords + local_point.coords).into()
+            (source_point.coords + local_point.coords).into()
         }
-        Odometry3D | Observation3D => {
+        Odometry3D | Observation3D | SwitchableOdometry3D => {
             let source_rot = get_rot_from_3d(&source.get_content());
             let local_point = source_rot.to_rotation_matrix() * factor_point;
-            (get_var_point(source).coords + local_point.coords).into()
+            (source_point.coords + local_point.coords).into()
         }
-    }
+        SwitchPrior | LinearizedPrior => return None,
+    };
+    Some(meas_point)
 }
 
 fn add_factor_core(visual_factor_graph: &mut VisualFactorGraph, meas_point: &Point3<f32>) -> SceneNode {
@@ -146,38 +162,34 @@ fn add_factor_core(visual_factor_graph: &mut VisualFactorGraph, meas_point: &Poi
 }
 
 fn handle_factor_rotation(factor: &Factor, meas_object: &mut SceneNode, source: &Variable) {
-    if factor.factor_type == Position2D || factor.factor_type == Odometry2D {
-        let factor_rot = get_rot_from_2d(&factor.constraint);
-        let meas_rot = match factor.factor_type {
-            Position2D => factor_rot,
-            Odometry2D => factor_rot + get_rot_from_2d(&source.get_content()),
-            _ => panic!("Internal Error at visualization of unsupported rotation."),
-        };
-        let mut meas_rot_object = meas_object.add_capsule(0.04, 1.5);
-        meas_rot_object.set_local_rotation(UnitQuaternion::from_axis_angle(&Vector3::z_axis(), meas_rot));
-        meas_rot_object.prepend_to_local_translation(&Translation3::new(0.0, 0.15, 0.0));
-    } else if factor.factor_type == Position3D || factor.factor_type == Odometry3D {
-        let factor_rot = get_rot_from_3d(&factor.constraint);
-        let meas_rot = factor_rot * get_rot_from_3d(&source.get_content());
-        let mut meas_rot_object = meas_object.add_capsule(0.04, 1.5);
-        meas_rot_object.set_local_rotation(meas_rot);
-        meas_rot_object.prepend_to_local_translation(&Translation3::new(0.0, 0.15, 0.0));
-    }
+    let meas_rot = match factor.factor_type {
+        Position2D => UnitQuaternion::from_axis_angle(&Vector3::z_axis(), get_rot_from_2d(&factor.constraint)),
+        Odometry2D | SwitchableOdometry2D => UnitQuaternion::from_axis_angle(
+            &Vector3::z_axis(),
+            get_rot_from_2d(&factor.constraint) + get_rot_from_2d(&source.get_content()),
+        ),
+        Position3D | Odometry3D | SwitchableOdometry3D => {
+            get_rot_from_3d(&factor.constraint) * get_rot_from_3d(&source.get_content())
+        }
+        Observation2D | Observation3D | SwitchPrior | LinearizedPrior => return,
+    };
+    let mut meas_rot_object = meas_object.add_capsule(0.04, 1.5);
+    meas_rot_object.set_local_rotation(meas_rot);
+    meas_rot_object.prepend_to_local_translation(&Translation3::new(0.0, 0.15, 0.0));
 }
 
-fn color_meas_object(factor: &Factor, meas_object: &mut SceneNode) {
-    let (r, g, b) = get_factor_color(factor);
+fn color_meas_object((r, g, b): (f32, f32, f32), meas_object: &mut SceneNode) {
     meas_object.set_color(r, g, b);
 }
 
 fn add_factor_lines(
     visual_factor_graph: &mut VisualFactorGraph,
     factor: &Factor,
+    (r, g, b): (f32, f32, f32),
     meas_point: Point3<f32>,
     source_point: Point3<f32>,
     target_point: Point3<f32>,
 ) {
-    let (r, g, b) = get_factor_color(factor);
     visual_factor_graph
         .lines
         .push([meas_point, source_point, Point3::new(r, g, b)]);
@@ -185,43 +197,42 @@ fn add_factor_lines(
         visual_factor_graph
             .lines
             .push([meas_point, target_point, Point3::new(r, g, b)]);
-    } else if factor.factor_type == Odometry2D || factor.factor_type == Odometry3D {
+    } else if [Odometry2D, Odometry3D, SwitchableOdometry2D, SwitchableOdometry3D].contains(&factor.factor_type) {
         visual_factor_graph
             .lines
             .push([source_point, target_point, Point3::new(1.0, 1.0, 1.0)]);
     }
 }
 
-fn get_factor_color(factor: &Factor) -> (f32, f32, f32) {
+fn get_factor_color(factor: &Factor) -> Option<(f32, f32, f32)> {
     match factor.factor_type {
-        Position2D | Position3D => (1.0, 0.5, 0.5),
-        Odometry2D | Odometry3D => (0.5, 0.5, 1.0),
-        Observation2D | Observation3D => (0.5, 1.0, 0.5),
+        Position2D | Position3D => Some((1.0, 0.5, 0.5)),
+        Odometry2D | Odometry3D => Some((0.5, 0.5, 1.0)),
+        Observation2D | Observation3D => Some((0.5, 1.0, 0.5)),
+        SwitchableOdometry2D | SwitchableOdometry3D => Some((1.0, 0.5, 1.0)),
+        SwitchPrior | LinearizedPrior => None,
     }
 }
 
-fn get_var_point(var: &Variable) -> Point3<f32> {
+fn get_var_point(var: &Variable) -> Option<Point3<f32>> {
     let (x, y, z) = match var {
-        Variable::Vehicle2D(VehicleVariable2D { pose, .. }) => (pose.borrow()[0], pose.borrow()[1], 0.),
-        Variable::Landmark2D(LandmarkVariable2D { position, .. }) => (position.borrow()[0], position.borrow()[1], 0.),
-        Variable::Vehicle3D(VehicleVariable3D { pose, .. }) => (pose.borrow()[0], pose.borrow()[1], pose.borrow()[2]),
-        Variable::Landmark3D(LandmarkVariable3D { position, .. }) => {
-            (position.borrow()[0], position.borrow()[1], position.borrow()[2])
-        }
+        Variable::Vehicle2D(v) => (v.pose()[0], v.pose()[1], 0.),
+        Variable::Landmark2D(v) => (v.position()[0], v.position()[1], 0.),
+        Variable::Vehicle3D(v) => (v.pose()[0], v.pose()[1], v.pose()[2]),
+        Variable::Landmark3D(v) => (v.position()[0], v.position()[1], v.position()[2]),
+        Variable::Switch(_) => return None,
     };
 
-    Point3::new(x as f32, y as f32, z as f32)
+    Some(Point3::new(x as f32, y as f32, z as f32))
 }
 
-fn get_factor_point(factor: &Factor) -> Point3<f32> {
-    Point3::new(
-        factor.constraint[0] as f32,
-        factor.constraint[1] as f32,
-        match factor.factor_type {
-            Position2D | Odometry2D | Observation2D => 0.0 as f32,
-            Position3D | Odometry3D | Observation3D => factor.constraint[2] as f32,
-        },
-    )
+fn get_factor_point(factor: &Factor) -> Option<Point3<f32>> {
+    let z = match factor.factor_type {
+        Position2D | Odometry2D | Observation2D | SwitchableOdometry2D => 0.0 as f32,
+        Position3D | Odometry3D | Observation3D | SwitchableOdometry3D => factor.constraint[2] as f32,
+        SwitchPrior | LinearizedPrior => return None,
+    };
+    Some(Point3::new(factor.constraint[0] as f32, factor.constraint[1] as f32, z))
 }
 
 fn get_rot_from_2d(content: &[f64]) -> f32 {
@@ -237,17 +248,6 @@ fn get_rot_from_3d(content: &[f64]) -> UnitQuaternion<f32> {
     ))
 }
 
-impl Variable {
-    pub fn get_content(&self) -> Vec<f64> {
-        match self {
-            Variable::Vehicle2D(v) => v.pose.borrow().to_vec(),
-            Variable::Landmark2D(v) => v.position.borrow().to_vec(),
-            Variable::Vehicle3D(v) => v.pose.borrow().to_vec(),
-            Variable::Landmark3D(v) => v.position.borrow().to_vec(),
-        }
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::*;